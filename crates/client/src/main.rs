@@ -5,10 +5,12 @@ mod secure_channel;
 
 use anyhow::{Context, Result};
 use futures_util::{SinkExt, StreamExt};
-use rga::RemoteOp;
+use rga::{ApplyError, Change, Expand, MarkOp, Marks, RemoteOp, Rga, S4Vector};
 use secure_channel::client_handshake;
 use serde::{Deserialize, Serialize};
 use std::io::{self, Write};
+use std::iter;
+use std::ops::Range;
 use std::sync::Arc;
 use tokio::sync::{mpsc, Mutex};
 use tokio_tungstenite::{connect_async, tungstenite::Message};
@@ -38,6 +40,11 @@ pub enum ClientMessage {
     // Leave the current room
     LeaveRoom,
 
+    // Declare the unit of positions and lengths in our messages
+    SetPositionEncoding {
+        encoding: PositionEncoding,
+    },
+
     // Send a CRDT operation (legacy)
     Operation {
         op: RemoteOp<char>,
@@ -55,9 +62,40 @@ pub enum ClientMessage {
         length: usize,
     },
 
+    // Move text to another position (client-friendly)
+    Move {
+        position: usize,
+        length: usize,
+        destination: usize,
+    },
+
+    // Replace text at a position in one step (client-friendly)
+    Replace {
+        position: usize,
+        length: usize,
+        text: String,
+    },
+
+    // Send a formatting mark
+    Mark {
+        op: MarkOp,
+    },
+
     // Request current document state
     RequestSync,
 
+    // Request the operations not covered by our vector clock
+    SyncSince {
+        vector_clock: Vec<u32>,
+        session: Option<u32>,
+    },
+
+    // Report the operations our replica has integrated
+    Acknowledge {
+        vector_clock: Vec<u32>,
+        session: u32,
+    },
+
     // Save a version snapshot
     SaveVersion {
         author: Option<String>,
@@ -82,10 +120,26 @@ pub enum ClientMessage {
         limit: Option<usize>,
     },
 
+    // Request who wrote each part of the document
+    RequestBlame,
+
+    // Request the document as it was at a past vector clock
+    RequestContentAt {
+        session: u32,
+        vector_clock: Vec<u32>,
+    },
+
     // Heartbeat/ping
     Ping,
 }
 
+// Unit in which the server counts our positions and lengths
+// The CLI only uses code points, the unit of `Rga<char>`
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum PositionEncoding {
+    CodePoint,
+}
+
 // Messages sent from server to client
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
@@ -94,7 +148,6 @@ pub enum ServerMessage {
     RoomCreated {
         room_id: String,
         site_id: u32,
-        num_sites: usize,
         filename: String,
         document_content: String,
     },
@@ -103,10 +156,11 @@ pub enum ServerMessage {
     JoinedRoom {
         room_id: String,
         site_id: u32,
-        num_sites: usize,
         filename: String,
         document_content: String,
         buffered_ops: Vec<RemoteOp<char>>,
+        #[serde(default)]
+        marks: Vec<MarkOp>,
     },
 
     // Another user joined the room
@@ -127,6 +181,12 @@ pub enum ServerMessage {
         op: RemoteOp<char>,
     },
 
+    // Incoming formatting mark from another client
+    Mark {
+        from_site: u32,
+        op: MarkOp,
+    },
+
     // Document checkpoint reached
     Checkpoint {
         document_content: String,
@@ -137,6 +197,21 @@ pub enum ServerMessage {
     SyncResponse {
         document_content: String,
         buffered_ops: Vec<RemoteOp<char>>,
+        #[serde(default)]
+        marks: Vec<MarkOp>,
+    },
+
+    // Operations we have not seen yet (reply to SyncSince)
+    MissingOps {
+        operations: Vec<RemoteOp<char>>,
+        #[serde(default)]
+        session: Option<u32>,
+    },
+
+    // Every client reached the checkpoint: start a new CRDT session
+    NewSession {
+        session: u32,
+        vector_clock: Vec<u32>,
     },
 
     // Error message
@@ -176,6 +251,18 @@ pub enum ServerMessage {
     ActivityEvent {
         event: ActivityEvent,
     },
+
+    // Authorship of the document (reply to RequestBlame)
+    Blame {
+        spans: Vec<BlameSpan>,
+    },
+
+    // The document at a past causal point (reply to RequestContentAt)
+    ContentAt {
+        session: u32,
+        vector_clock: Vec<u32>,
+        document_content: String,
+    },
 }
 
 // A saved version entry for a document
@@ -200,68 +287,199 @@ pub struct ActivityEvent {
     pub details: Option<String>,
 }
 
+// Authorship of a contiguous range of the document
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BlameSpan {
+    pub position: usize,
+    pub length: usize,
+    pub site_id: u32,
+    pub user_id: Option<String>,
+    pub inserted: S4Vector,
+    pub last_update: Option<S4Vector>,
+    pub updated_by: Option<String>,
+}
+
 // Client State
 // Client state for collaborative editing
 #[derive(Debug, Clone)]
 struct ClientState {
     // Our site ID (assigned by server)
     site_id: Option<u32>,
-    // Number of sites in the room
-    num_sites: usize,
     // Current room ID
     room_id: Option<String>,
     // Document filename
     filename: Option<String>,
     // Current document content (synced from server)
     content: String,
+    // CRDT replica built from the server's operation log, used to locate
+    // remote operations in the document
+    replica: Option<Rga<char>>,
+    // Formatting marks of the document, anchored to characters of `replica`
+    marks: Option<Marks>,
 }
 
 impl ClientState {
     fn new() -> Self {
         ClientState {
             site_id: None,
-            num_sites: 10,
             room_id: None,
             filename: None,
             content: String::new(),
+            replica: None,
+            marks: None,
         }
     }
 
     // Apply a local insert operation
+    // Positions and lengths of local operations count characters, as the
+    // server does for us
     fn local_insert(&mut self, pos: usize, text: &str) -> bool {
-        if pos > self.content.len() {
+        let Some(range) = byte_range(&self.content, pos, 0) else {
             return false;
-        }
-        self.content.insert_str(pos, text);
+        };
+        self.content.insert_str(range.start, text);
         true
     }
 
     // Apply a local delete operation
     fn local_delete(&mut self, pos: usize, len: usize) -> bool {
-        if pos + len > self.content.len() {
+        let Some(range) = byte_range(&self.content, pos, len) else {
+            return false;
+        };
+        self.content.replace_range(range, "");
+        true
+    }
+
+    // Apply a local replace operation
+    fn local_replace(&mut self, pos: usize, len: usize, text: &str) -> bool {
+        let Some(range) = byte_range(&self.content, pos, len) else {
+            return false;
+        };
+        self.content.replace_range(range, text);
+        true
+    }
+
+    // Apply a local move operation
+    // `dest` is counted before the text is taken out
+    fn local_move(&mut self, pos: usize, len: usize, dest: usize) -> bool {
+        let end = pos.saturating_add(len);
+        if len == 0 || (pos..=end).contains(&dest) || byte_range(&self.content, dest, 0).is_none() {
             return false;
         }
-        self.content.replace_range(pos..pos + len, "");
+        let Some(range) = byte_range(&self.content, pos, len) else {
+            return false;
+        };
+
+        let text: String = self.content.drain(range).collect();
+        let dest = if dest > end { dest - len } else { dest };
+        let at = byte_offset(&self.content, dest);
+        self.content.insert_str(at, &text);
         true
     }
 
     // Apply a remote operation to update local view
-    fn apply_remote_op(&mut self, op: &RemoteOp<char>) {
-        match op {
-            RemoteOp::Insert { value, .. } => {
-                // We can't know the exact position without the full CRDT state
-                println!("[remote] Insert: '{value}'");
-            }
-            RemoteOp::Delete { .. } => {
-                println!("[remote] Delete operation");
+    // Returns the visible changes, or None without a replica to locate them
+    // Ops we already have (e.g. resent after a reconnect) change nothing
+    // Content is taken from the replica afterwards: our own edits reach the
+    // replica only with the next sync, so the changes' indices may not match
+    // the text we edited locally
+    fn apply_remote_op(
+        &mut self,
+        op: &RemoteOp<char>,
+    ) -> Option<Result<Vec<Change<char>>, ApplyError>> {
+        let replica = self.replica.as_mut()?;
+        let changes = replica.apply_remote(op.clone());
+        if changes.as_ref().is_ok_and(|changes| !changes.is_empty()) {
+            self.content = replica.read().into_iter().collect();
+        }
+        Some(changes)
+    }
+
+    // Bring the replica up to date with operations from the server's log
+    // Ops of another session are its whole log, so the replica starts over
+    // (marks come back re-anchored with the next sync)
+    // Content is taken from the replica afterwards
+    fn apply_missing_ops(&mut self, operations: Vec<RemoteOp<char>>, session: Option<u32>) {
+        let site_id = self.site_id.unwrap_or(0);
+        let session = session.unwrap_or_else(|| self.replica.as_ref().map_or(1, Rga::session));
+        if self
+            .replica
+            .as_ref()
+            .is_some_and(|replica| replica.session() != session)
+        {
+            self.replica = None;
+            self.marks = None;
+        }
+        let replica = self
+            .replica
+            .get_or_insert_with(|| Rga::with_session(site_id, 1, session));
+
+        for op in operations {
+            if let Err(e) = replica.apply_remote(op) {
+                println!("[warn] Skipping invalid operation: {e}");
             }
-            RemoteOp::Update { value, .. } => {
-                println!("[remote] Update: '{value}'");
+        }
+        self.content = replica.read().into_iter().collect();
+    }
+
+    // Follow the server into a new session from the checkpoint `vector_clock`
+    // Returns None without a replica
+    fn start_session(&mut self, vector_clock: &[u32]) -> Option<Result<u32, ApplyError>> {
+        let replica = self.replica.as_mut()?;
+        Some(match self.marks.as_mut() {
+            Some(marks) => marks.start_session(replica, vector_clock),
+            None => replica.start_session(vector_clock),
+        })
+    }
+
+    // Apply formatting marks from the server; ones we already have are skipped
+    fn apply_marks(&mut self, ops: Vec<MarkOp>) {
+        let site_id = self.site_id.unwrap_or(0);
+        let marks = self.marks.get_or_insert_with(|| Marks::new(site_id, 1));
+
+        for op in ops {
+            if let Err(e) = marks.apply_remote(op) {
+                println!("[warn] Skipping invalid mark: {e}");
             }
         }
     }
 }
 
+// Byte offset of the character at `index` (or the end of `content`)
+fn byte_offset(content: &str, index: usize) -> usize {
+    content
+        .char_indices()
+        .nth(index)
+        .map_or(content.len(), |(offset, _)| offset)
+}
+
+// Byte range of the `len` characters from character `index`
+// None if it extends past the end of `content`
+fn byte_range(content: &str, index: usize, len: usize) -> Option<Range<usize>> {
+    let mut offsets = content
+        .char_indices()
+        .map(|(offset, _)| offset)
+        .chain(iter::once(content.len()));
+    let start = offsets.nth(index)?;
+    let end = match len.checked_sub(1) {
+        Some(last) => offsets.nth(last)?,
+        None => start,
+    };
+    Some(start..end)
+}
+
+// Describe a remote change for the terminal
+fn describe_change(change: &Change<char>) -> String {
+    match change {
+        Change::Insert { index, values } => {
+            let text: String = values.iter().collect();
+            format!("inserted '{text}' at position {index}")
+        }
+        Change::Delete { index, len } => format!("deleted {len} chars at position {index}"),
+        Change::Update { index, value } => format!("replaced position {index} with '{value}'"),
+    }
+}
+
 // Main Application
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -302,6 +520,11 @@ async fn main() -> Result<()> {
     // Channel for sending messages to WebSocket
     let (msg_tx, mut msg_rx) = mpsc::unbounded_channel::<ClientMessage>();
 
+    // Our positions count characters
+    msg_tx.send(ClientMessage::SetPositionEncoding {
+        encoding: PositionEncoding::CodePoint,
+    })?;
+
     // Spawn task to send encrypted messages to server
     let secure_write_clone = secure_write.clone();
     let send_task = tokio::spawn(async move {
@@ -324,6 +547,7 @@ async fn main() -> Result<()> {
 
     // Spawn task to receive and decrypt messages from server
     let state_for_recv = state.clone();
+    let sync_tx = msg_tx.clone();
     let secure_read_clone = secure_read.clone();
     let recv_task = tokio::spawn(async move {
         while let Some(Ok(msg)) = ws_rx.next().await {
@@ -333,7 +557,7 @@ async fn main() -> Result<()> {
                     Ok(plaintext) => match String::from_utf8(plaintext) {
                         Ok(text) => match serde_json::from_str::<ServerMessage>(&text) {
                             Ok(server_msg) => {
-                                handle_server_message(&state_for_recv, server_msg).await;
+                                handle_server_message(&state_for_recv, server_msg, &sync_tx).await;
                             }
                             Err(e) => {
                                 println!("[error] Failed to parse server message: {e}");
@@ -414,6 +638,24 @@ async fn main() -> Result<()> {
                 }
             }
 
+            "move" | "m" => {
+                if let Err(e) = handle_move_command(args, &state, &msg_tx).await {
+                    println!("[error] {e}");
+                }
+            }
+
+            "replace" | "r" => {
+                if let Err(e) = handle_replace_command(args, &state, &msg_tx).await {
+                    println!("[error] {e}");
+                }
+            }
+
+            "format" | "f" => {
+                if let Err(e) = handle_format_command(args, &state, &msg_tx).await {
+                    println!("[error] {e}");
+                }
+            }
+
             "show" | "s" => {
                 let state_guard = state.lock().await;
                 if state_guard.room_id.is_some() {
@@ -484,6 +726,30 @@ async fn main() -> Result<()> {
                 println!("[info] Fetching activity log...");
             }
 
+            "blame" | "b" => {
+                msg_tx.send(ClientMessage::RequestBlame).ok();
+                println!("[info] Fetching authorship...");
+            }
+
+            "at" => {
+                let parts: Vec<&str> = args.split_whitespace().collect();
+                let clock: Option<Vec<u32>> = parts
+                    .get(1)
+                    .and_then(|clock| clock.split(',').map(|c| c.parse().ok()).collect());
+                match (parts.first().and_then(|s| s.parse::<u32>().ok()), clock) {
+                    (Some(session), Some(vector_clock)) if parts.len() == 2 => {
+                        msg_tx
+                            .send(ClientMessage::RequestContentAt {
+                                session,
+                                vector_clock,
+                            })
+                            .ok();
+                        println!("[info] Fetching past content...");
+                    }
+                    _ => println!("[error] Usage: at <session> <clock,clock,...>"),
+                }
+            }
+
             "ping" => {
                 msg_tx.send(ClientMessage::Ping).ok();
                 println!("[info] Ping sent");
@@ -495,7 +761,15 @@ async fn main() -> Result<()> {
                 println!("Room ID:  {:?}", state_guard.room_id);
                 println!("Site ID:  {:?}", state_guard.site_id);
                 println!("Filename: {:?}", state_guard.filename);
-                println!("Content length: {} chars", state_guard.content.len());
+                println!(
+                    "Content length: {} chars",
+                    state_guard.content.chars().count()
+                );
+                if let Some(replica) = &state_guard.replica {
+                    let clock: Vec<String> =
+                        replica.vector_clock().iter().map(u32::to_string).collect();
+                    println!("Clock:    {} {}", replica.session(), clock.join(","));
+                }
                 println!("─────────────────────────────────────────");
             }
 
@@ -518,6 +792,56 @@ async fn main() -> Result<()> {
 }
 
 // Command Handlers
+async fn handle_format_command(
+    args: &str,
+    state: &Arc<Mutex<ClientState>>,
+    msg_tx: &mpsc::UnboundedSender<ClientMessage>,
+) -> Result<()> {
+    let mut state_guard = state.lock().await;
+    let state_ref = &mut *state_guard;
+    let (Some(replica), Some(marks)) = (&state_ref.replica, &mut state_ref.marks) else {
+        anyhow::bail!("Not synced with a room yet. Use 'create' or 'join' first.");
+    };
+
+    // Without arguments, list the formatted ranges
+    if args.is_empty() {
+        let chars = replica.read();
+        for span in marks.format_spans(replica) {
+            if span.attributes.is_empty() {
+                continue;
+            }
+            let text: String = chars[span.index..span.index + span.len].iter().collect();
+            let attributes = serde_json::to_string(&span.attributes)?;
+            println!("[format] {text:?} {attributes}");
+        }
+        return Ok(());
+    }
+
+    let parts: Vec<&str> = args.splitn(4, ' ').collect();
+    if parts.len() < 3 {
+        anyhow::bail!("Usage: format <position> <length> <key> [value]");
+    }
+
+    let pos: usize = parts[0].parse().context("Position must be a number")?;
+    let len: usize = parts[1].parse().context("Length must be a number")?;
+    let key = parts[2];
+    let end = pos.checked_add(len).context("Format range out of bounds")?;
+
+    // Values are JSON ("null" clears the attribute); bare words are strings
+    let value = match parts.get(3) {
+        Some(raw) => serde_json::from_str(raw).unwrap_or_else(|_| serde_json::json!(raw)),
+        None => serde_json::Value::Bool(true),
+    };
+
+    let op = marks
+        .mark_local(replica, pos..end, key, value, Expand::default_for(key))
+        .context("Format range out of bounds")?;
+    msg_tx.send(ClientMessage::Mark { op })?;
+
+    println!("[local] Set '{key}' on {len} chars at position {pos}");
+    Ok(())
+}
+
 fn print_help() {
     println!("┌─────────────────────────────────────────────────────────────┐");
     println!("│                      Available Commands                     │");
@@ -527,6 +851,10 @@ fn print_help() {
     println!("│  leave                               - Leave current room   │");
     println!("│  insert <pos> <text>                 - Insert text at pos   │");
     println!("│  delete <pos> <len>                  - Delete len chars     │");
+    println!("│  move <pos> <len> <dest>             - Move len chars       │");
+    println!("│  replace <pos> <len> <text>          - Replace len chars    │");
+    println!("│  format <pos> <len> <key> [value]    - Format chars         │");
+    println!("│  format                              - Show formatting      │");
     println!("│  show                                - Show document        │");
     println!("│  sync                                - Request full sync    │");
     println!("├─────────────────────────────────────────────────────────────┤");
@@ -535,6 +863,8 @@ fn print_help() {
     println!("│  restore <seq>                       - Restore a version    │");
     println!("│  diff <seq1> <seq2>                  - Compare versions     │");
     println!("│  activity [limit]                    - View activity log    │");
+    println!("│  blame                               - Show who wrote what  │");
+    println!("│  at <session> <clock>                - Show past content    │");
     println!("├─────────────────────────────────────────────────────────────┤");
     println!("│  status                              - Show connection info │");
     println!("│  ping                                - Ping server          │");
@@ -542,7 +872,7 @@ fn print_help() {
     println!("│  quit                                - Exit client          │");
     println!("└─────────────────────────────────────────────────────────────┘");
     println!();
-    println!("Shortcuts: c=create, j=join, l=leave, i=insert, d=delete, s=show, q=quit");
+    println!("Shortcuts: c=create, j=join, l=leave, i=insert, d=delete, m=move, r=replace, s=show, q=quit");
     println!();
 }
 
@@ -652,7 +982,7 @@ async fn handle_delete_command(
     if !state_guard.local_delete(pos, len) {
         anyhow::bail!(
             "Delete range out of bounds (document has {} chars)",
-            state_guard.content.len() + len
+            state_guard.content.chars().count()
         );
     }
 
@@ -667,22 +997,108 @@ async fn handle_delete_command(
     Ok(())
 }
 
+async fn handle_move_command(
+    args: &str,
+    state: &Arc<Mutex<ClientState>>,
+    msg_tx: &mpsc::UnboundedSender<ClientMessage>,
+) -> Result<()> {
+    let parts: Vec<&str> = args.split_whitespace().collect();
+
+    if parts.len() < 3 {
+        anyhow::bail!("Usage: move <position> <length> <destination>");
+    }
+
+    let pos: usize = parts[0].parse().context("Position must be a number")?;
+    let len: usize = parts[1].parse().context("Length must be a number")?;
+    let dest: usize = parts[2].parse().context("Destination must be a number")?;
+
+    let mut state_guard = state.lock().await;
+
+    if state_guard.room_id.is_none() {
+        anyhow::bail!("Not in a room. Use 'create' or 'join' first.");
+    }
+
+    if !state_guard.local_move(pos, len, dest) {
+        anyhow::bail!("Move range out of bounds, or destination inside it");
+    }
+
+    // Send position-based move - server handles CRDT conversion and auto-syncs
+    msg_tx.send(ClientMessage::Move {
+        position: pos,
+        length: len,
+        destination: dest,
+    })?;
+
+    println!("[local] Moved {len} chars from position {pos} to {dest}");
+
+    Ok(())
+}
+
+async fn handle_replace_command(
+    args: &str,
+    state: &Arc<Mutex<ClientState>>,
+    msg_tx: &mpsc::UnboundedSender<ClientMessage>,
+) -> Result<()> {
+    let parts: Vec<&str> = args.splitn(3, ' ').collect();
+
+    if parts.len() < 3 {
+        anyhow::bail!("Usage: replace <position> <length> <text>");
+    }
+
+    let pos: usize = parts[0].parse().context("Position must be a number")?;
+    let len: usize = parts[1].parse().context("Length must be a number")?;
+    let text = parts[2].to_string();
+
+    let mut state_guard = state.lock().await;
+
+    if state_guard.room_id.is_none() {
+        anyhow::bail!("Not in a room. Use 'create' or 'join' first.");
+    }
+
+    if !state_guard.local_replace(pos, len, &text) {
+        anyhow::bail!("Replace range out of bounds");
+    }
+
+    // Send position-based replace - the server applies it as one transaction
+    msg_tx.send(ClientMessage::Replace {
+        position: pos,
+        length: len,
+        text: text.clone(),
+    })?;
+
+    println!("[local] Replaced {len} chars at position {pos} with '{text}'");
+
+    Ok(())
+}
+
 // Server Message Handler
-async fn handle_server_message(state: &Arc<Mutex<ClientState>>, msg: ServerMessage) {
+async fn handle_server_message(
+    state: &Arc<Mutex<ClientState>>,
+    msg: ServerMessage,
+    msg_tx: &mpsc::UnboundedSender<ClientMessage>,
+) {
     match msg {
         ServerMessage::RoomCreated {
             room_id,
             site_id,
-            num_sites,
             filename,
             document_content,
         } => {
             let mut state_guard = state.lock().await;
             state_guard.room_id = Some(room_id.clone());
             state_guard.site_id = Some(site_id);
-            state_guard.num_sites = num_sites;
             state_guard.filename = Some(filename.clone());
             state_guard.content = document_content.clone();
+            state_guard.replica = None;
+            state_guard.marks = Some(Marks::new(site_id, 1));
+
+            // Fetch the full operation log to build our replica
+            msg_tx
+                .send(ClientMessage::SyncSince {
+                    vector_clock: Vec::new(),
+                    session: None,
+                })
+                .ok();
 
             println!();
             println!("╔══════════════════════════════════════════════════════════════╗");
@@ -710,17 +1126,27 @@ async fn handle_server_message(state: &Arc<Mutex<ClientState>>, msg: ServerMessa
         ServerMessage::JoinedRoom {
             room_id,
             site_id,
-            num_sites,
             filename,
             document_content,
             buffered_ops: _,
+            marks,
         } => {
             let mut state_guard = state.lock().await;
             state_guard.room_id = Some(room_id.clone());
             state_guard.site_id = Some(site_id);
-            state_guard.num_sites = num_sites;
             state_guard.filename = Some(filename.clone());
             state_guard.content = document_content.clone();
+            state_guard.replica = None;
+            state_guard.marks = None;
+            state_guard.apply_marks(marks);
+
+            // Fetch the full operation log to build our replica
+            msg_tx
+                .send(ClientMessage::SyncSince {
+                    vector_clock: Vec::new(),
+                    session: None,
+                })
+                .ok();
 
             println!();
             println!("╔══════════════════════════════════════════════════════════════╗");
@@ -771,10 +1197,33 @@ async fn handle_server_message(state: &Arc<Mutex<ClientState>>, msg: ServerMessa
 
         ServerMessage::Operation { from_site, op } => {
             let mut state_guard = state.lock().await;
-            state_guard.apply_remote_op(&op);
             println!();
-            println!("[remote] Operation from site {from_site}");
-            println!("[info] Use 'sync' to update document view");
+            match state_guard.apply_remote_op(&op) {
+                Some(Ok(changes)) if changes.is_empty() => {
+                    println!("[remote] Operation from site {from_site} had no visible effect");
+                }
+                Some(Ok(changes)) => {
+                    for change in &changes {
+                        println!("[remote] Site {from_site} {}", describe_change(change));
+                    }
+                }
+                Some(Err(e)) => {
+                    println!("[warn] Rejected operation from site {from_site}: {e}");
+                }
+                None => {
+                    println!("[remote] Operation from site {from_site}");
+                    println!("[info] Use 'sync' to update document view");
+                }
+            }
+            print!("> ");
+            io::stdout().flush().ok();
+        }
+
+        ServerMessage::Mark { from_site, op } => {
+            let key = op.key.clone();
+            state.lock().await.apply_marks(vec![op]);
+            println!();
+            println!("[remote] Site {from_site} set '{key}' formatting");
             print!("> ");
             io::stdout().flush().ok();
         }
@@ -795,9 +1244,22 @@ async fn handle_server_message(state: &Arc<Mutex<ClientState>>, msg: ServerMessa
         ServerMessage::SyncResponse {
             document_content,
             buffered_ops: _,
+            marks,
         } => {
             let mut state_guard = state.lock().await;
             state_guard.content = document_content.clone();
+            state_guard.apply_marks(marks);
+
+            // Pull our own edits (never echoed back as Operations) into the replica
+            if let Some(replica) = &state_guard.replica {
+                msg_tx
+                    .send(ClientMessage::SyncSince {
+                        vector_clock: replica.vector_clock().to_vec(),
+                        session: Some(replica.session()),
+                    })
+                    .ok();
+            }
+
             println!();
             println!("[sync] Document updated from server");
             println!("[sync] Content: {document_content}");
@@ -805,6 +1267,49 @@ async fn handle_server_message(state: &Arc<Mutex<ClientState>>, msg: ServerMessa
             io::stdout().flush().ok();
         }
 
+        ServerMessage::MissingOps {
+            operations,
+            session,
+        } => {
+            let mut state_guard = state.lock().await;
+            state_guard.apply_missing_ops(operations, session);
+
+            // Lets the server purge tombstones and start new sessions
+            if let Some(replica) = &state_guard.replica {
+                msg_tx
+                    .send(ClientMessage::Acknowledge {
+                        vector_clock: replica.vector_clock().to_vec(),
+                        session: replica.session(),
+                    })
+                    .ok();
+            }
+        }
+
+        ServerMessage::NewSession {
+            session,
+            vector_clock,
+        } => {
+            let mut state_guard = state.lock().await;
+            println!();
+            match state_guard.start_session(&vector_clock) {
+                Some(Ok(_)) => println!("[info] Started session {session}"),
+                // Not at the checkpoint: fetch the new session's log instead
+                Some(Err(_)) | None => {
+                    msg_tx
+                        .send(ClientMessage::SyncSince {
+                            vector_clock: Vec::new(),
+                            session: None,
+                        })
+                        .ok();
+                    state_guard.replica = None;
+                    state_guard.marks = None;
+                    println!("[info] Session {session} started; resyncing");
+                }
+            }
+            print!("> ");
+            io::stdout().flush().ok();
+        }
+
         ServerMessage::Error { message } => {
             println!();
             println!("[error] Server error: {message}");
@@ -923,6 +1428,42 @@ async fn handle_server_message(state: &Arc<Mutex<ClientState>>, msg: ServerMessa
             io::stdout().flush().ok();
         }
 
+        ServerMessage::Blame { spans } => {
+            let chars: Vec<char> = state.lock().await.content.chars().collect();
+            println!();
+            for span in &spans {
+                let end = (span.position + span.length).min(chars.len());
+                let text: String = chars
+                    .get(span.position..end)
+                    .unwrap_or(&[])
+                    .iter()
+                    .collect();
+                let author = span.user_id.as_deref().unwrap_or("server");
+                match span.last_update {
+                    Some(update) => println!(
+                        "[blame] site {} ({author}), updated by site {} ({}): {text:?}",
+                        span.site_id,
+                        update.sid,
+                        span.updated_by.as_deref().unwrap_or("server")
+                    ),
+                    None => println!("[blame] site {} ({author}): {text:?}", span.site_id),
+                }
+            }
+            print!("> ");
+            io::stdout().flush().ok();
+        }
+
+        ServerMessage::ContentAt {
+            session,
+            vector_clock,
+            document_content,
+        } => {
+            println!();
+            println!("[history] Session {session} at {vector_clock:?}: {document_content}");
+            print!("> ");
+            io::stdout().flush().ok();
+        }
+
         ServerMessage::ActivityEvent { event } => {
             println!();
             println!(
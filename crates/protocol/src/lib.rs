@@ -1,3 +1,5 @@
 pub mod messages;
 
-pub use messages::{ActivityEvent, ClientMessage, ServerMessage, Version};
+pub use messages::{
+    ActivityEvent, BlameSpan, ClientMessage, PositionEncoding, ServerMessage, Version,
+};
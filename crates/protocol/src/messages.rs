@@ -1,7 +1,7 @@
 // WebSocket message types for client-server communication
 
 use chrono::{DateTime, Utc};
-use rga::RemoteOp;
+use rga::{MarkOp, RemoteOp, S4Vector};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
@@ -27,6 +27,36 @@ pub struct ActivityEvent {
     pub details: Option<String>,
 }
 
+// Authorship of a contiguous range of the document (one entry of a blame view)
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BlameSpan {
+    // In the requesting connection's PositionEncoding
+    pub position: usize,
+    pub length: usize,
+    // Site that inserted the range, and the client that joined as that site
+    // (None for the server's initial content or sites from before a restart)
+    pub site_id: u32,
+    pub user_id: Option<String>,
+    // S4Vector of the insert that created the first character
+    pub inserted: S4Vector,
+    // Update that last changed the range and the client that made it
+    pub last_update: Option<S4Vector>,
+    pub updated_by: Option<String>,
+}
+
+// Unit in which a connection counts document positions and lengths
+// The document itself is a sequence of code points
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PositionEncoding {
+    // Unicode scalar values (Rust `char`s)
+    #[default]
+    CodePoint,
+    // UTF-16 code units (JavaScript string offsets)
+    Utf16,
+    // Extended grapheme clusters (user-perceived characters)
+    Grapheme,
+}
+
 // Messages sent from client to server
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
@@ -45,18 +75,47 @@ pub enum ClientMessage {
     // Leave the current room
     LeaveRoom,
 
+    // Count the positions and lengths of this connection's messages in
+    // `encoding` from now on (code points until declared otherwise)
+    SetPositionEncoding { encoding: PositionEncoding },
+
     // Send a CRDT operation (legacy, for inter-server sync)
     Operation { op: RemoteOp<char> },
 
     // Insert text at a position (client-friendly)
+    // Positions and lengths of this and the other client-friendly edits
+    // count in the connection's PositionEncoding
     Insert { position: usize, text: String },
 
     // Delete text at a position (client-friendly)
     Delete { position: usize, length: usize },
 
+    // Move text to another position (client-friendly)
+    // `destination` is counted before the text is taken out
+    Move { position: usize, length: usize, destination: usize },
+
+    // Replace text at a position in one step (client-friendly)
+    // Other clients see the delete and the insert together, never one alone
+    Replace { position: usize, length: usize, text: String },
+
+    // Send a formatting mark over part of the document
+    Mark { op: MarkOp },
+
     // Request current document state
     RequestSync,
 
+    // Request only the operations not covered by the client's vector clock
+    // A replica of an older session (or none) gets the whole current log
+    SyncSince {
+        vector_clock: Vec<u32>,
+        #[serde(default)]
+        session: Option<u32>,
+    },
+
+    // Report that the client's replica has integrated every operation
+    // covered by `vector_clock` in `session`
+    Acknowledge { vector_clock: Vec<u32>, session: u32 },
+
     // Save a version snapshot
     SaveVersion { author: Option<String> },
 
@@ -72,6 +131,12 @@ pub enum ClientMessage {
     // Get recent activity/audit log
     GetActivityLog { limit: Option<usize> },
 
+    // Request who wrote each part of the current document
+    RequestBlame,
+
+    // Request the document as it was at `vector_clock` of `session`
+    RequestContentAt { session: u32, vector_clock: Vec<u32> },
+
     // Heartbeat/ping
     Ping,
 }
@@ -84,7 +149,6 @@ pub enum ServerMessage {
     RoomCreated {
         room_id: String,
         site_id: u32,
-        num_sites: usize,
         filename: String,
         // Initial document content
         document_content: String,
@@ -94,12 +158,14 @@ pub enum ServerMessage {
     JoinedRoom {
         room_id: String,
         site_id: u32,
-        num_sites: usize,
         filename: String,
         // Master copy of the document (base state)
         document_content: String,
         // Buffered operations since last checkpoint
         buffered_ops: Vec<RemoteOp<char>>,
+        // Formatting marks of the document
+        #[serde(default)]
+        marks: Vec<MarkOp>,
     },
 
     // Another user joined the room
@@ -111,6 +177,9 @@ pub enum ServerMessage {
     // Incoming CRDT operation from another client
     Operation { from_site: u32, op: RemoteOp<char> },
 
+    // Incoming formatting mark from another client
+    Mark { from_site: u32, op: MarkOp },
+
     // Document checkpoint reached (server applied buffered ops)
     Checkpoint {
         // New base document content after applying buffered ops
@@ -123,8 +192,23 @@ pub enum ServerMessage {
     SyncResponse {
         document_content: String,
         buffered_ops: Vec<RemoteOp<char>>,
+        #[serde(default)]
+        marks: Vec<MarkOp>,
+    },
+
+    // Operations the client has not seen yet (reply to SyncSince)
+    // Sent in causal order; apply them in sequence. When `session` is not
+    // the client's, they are the whole log of that session instead
+    MissingOps {
+        operations: Vec<RemoteOp<char>>,
+        #[serde(default)]
+        session: Option<u32>,
     },
 
+    // Every client acknowledged the operations up to `vector_clock` after a
+    // checkpoint: replicas at it start session `session` from there
+    NewSession { session: u32, vector_clock: Vec<u32> },
+
     // Error message
     Error { message: String },
 
@@ -148,6 +232,12 @@ pub enum ServerMessage {
 
     // New activity event (broadcast)
     ActivityEvent { event: ActivityEvent },
+
+    // Authorship of the current document, in document order (reply to RequestBlame)
+    Blame { spans: Vec<BlameSpan> },
+
+    // The document at a past causal point (reply to RequestContentAt)
+    ContentAt { session: u32, vector_clock: Vec<u32>, document_content: String },
 }
 
 // Internal message for server-side communication between tasks
@@ -172,7 +262,6 @@ pub enum InternalMessage {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use rga::S4Vector;
 
     #[test]
     fn test_message_serialization() {
@@ -194,6 +283,18 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_position_encoding_message() {
+        let json = r#"{"type":"SetPositionEncoding","encoding":"Utf16"}"#;
+        match serde_json::from_str::<ClientMessage>(json).unwrap() {
+            ClientMessage::SetPositionEncoding { encoding } => {
+                assert_eq!(encoding, PositionEncoding::Utf16);
+            }
+            _ => panic!("Wrong message type"),
+        }
+        assert_eq!(PositionEncoding::default(), PositionEncoding::CodePoint);
+    }
+
     #[test]
     fn test_operation_message() {
         let op = RemoteOp::Insert {
@@ -218,4 +319,59 @@ mod tests {
             _ => panic!("Wrong message type"),
         }
     }
+
+    #[test]
+    fn test_move_operation_message() {
+        let op = RemoteOp::Move {
+            spans: vec![(S4Vector::new(1, 0, 1, 1), 3)],
+            left_id: None,
+            s4v: S4Vector::new(1, 0, 6, 6),
+            vector_clock: vec![8, 0],
+        };
+
+        let msg = ServerMessage::Operation { from_site: 0, op };
+        let json = serde_json::to_string(&msg).unwrap();
+        let deserialized: ServerMessage = serde_json::from_str(&json).unwrap();
+
+        match deserialized {
+            ServerMessage::Operation { op, .. } => {
+                assert_eq!(op.last_s4v(), S4Vector::new(1, 0, 8, 8));
+                assert_eq!(op.dependencies().len(), 1);
+            }
+            _ => panic!("Wrong message type"),
+        }
+    }
+
+    #[test]
+    fn test_batch_operation_message() {
+        let op = RemoteOp::Batch {
+            ops: vec![
+                RemoteOp::Delete {
+                    target_id: S4Vector::new(1, 1, 1, 1),
+                    s4v: S4Vector::new(1, 0, 3, 2),
+                    vector_clock: vec![2, 1],
+                },
+                RemoteOp::Insert {
+                    left_id: None,
+                    value: 'x',
+                    s4v: S4Vector::new(1, 0, 4, 3),
+                    vector_clock: vec![3, 1],
+                },
+            ],
+            s4v: S4Vector::new(1, 0, 3, 2),
+            vector_clock: vec![3, 1],
+        };
+
+        let msg = ServerMessage::Operation { from_site: 0, op };
+        let json = serde_json::to_string(&msg).unwrap();
+        let deserialized: ServerMessage = serde_json::from_str(&json).unwrap();
+
+        match deserialized {
+            ServerMessage::Operation { op, .. } => {
+                assert_eq!(op.last_s4v(), S4Vector::new(1, 0, 4, 3));
+                assert_eq!(op.dependencies(), vec![(S4Vector::new(1, 1, 1, 1), 1)]);
+            }
+            _ => panic!("Wrong message type"),
+        }
+    }
 }
@@ -64,8 +64,8 @@ fn example_two_sites() {
     let op2 = alice.insert_local(1, "World".to_string()).unwrap();
 
     // Bob receives Alice's operations
-    bob.apply_remote(op1);
-    bob.apply_remote(op2);
+    bob.apply_remote(op1).unwrap();
+    bob.apply_remote(op2).unwrap();
 
     println!("Initial state:");
     println!("  Alice: {:?}", alice.read());
@@ -79,8 +79,8 @@ fn example_two_sites() {
     let bob_op = bob.update_local(1, "Universe".to_string()).unwrap();
 
     // Exchange operations
-    alice.apply_remote(bob_op);
-    bob.apply_remote(alice_op);
+    alice.apply_remote(bob_op).unwrap();
+    bob.apply_remote(alice_op).unwrap();
 
     println!("\nAfter concurrent operations:");
     println!("  Alice: {:?}", alice.read());
@@ -106,10 +106,10 @@ fn example_dopt_puzzle() {
     let op_b = site0.insert_local(1, 'b').unwrap();
 
     // Replicate to all sites
-    site1.apply_remote(op_a.clone());
-    site1.apply_remote(op_b.clone());
-    site2.apply_remote(op_a.clone());
-    site2.apply_remote(op_b.clone());
+    site1.apply_remote(op_a.clone()).unwrap();
+    site1.apply_remote(op_b.clone()).unwrap();
+    site2.apply_remote(op_a.clone()).unwrap();
+    site2.apply_remote(op_b.clone()).unwrap();
 
     println!("Initial state at all sites: {:?}\n", site0.read());
 
@@ -128,18 +128,18 @@ fn example_dopt_puzzle() {
     println!("Applying operations in different orders:");
 
     // Site 0: O1 (local), O3, O2
-    site0.apply_remote(op3.clone());
-    site0.apply_remote(op2.clone());
+    site0.apply_remote(op3.clone()).unwrap();
+    site0.apply_remote(op2.clone()).unwrap();
     println!("  Site 0 execution: O1 → O3 → O2 = {:?}", site0.read());
 
     // Site 1: O2 (local), O3, O1
-    site1.apply_remote(op3.clone());
-    site1.apply_remote(op1.clone());
+    site1.apply_remote(op3.clone()).unwrap();
+    site1.apply_remote(op1.clone()).unwrap();
     println!("  Site 1 execution: O2 → O3 → O1 = {:?}", site1.read());
 
     // Site 2: O3 (local), O2, O1
-    site2.apply_remote(op2.clone());
-    site2.apply_remote(op1.clone());
+    site2.apply_remote(op2.clone()).unwrap();
+    site2.apply_remote(op1.clone()).unwrap();
     println!("  Site 2 execution: O3 → O2 → O1 = {:?}", site2.read());
 
     println!(
@@ -162,7 +162,7 @@ fn example_delete_update_precedence() {
 
     // Create initial document
     let op_init = alice.insert_local(0, "original".to_string()).unwrap();
-    bob.apply_remote(op_init);
+    bob.apply_remote(op_init).unwrap();
 
     println!("Initial state: {:?}\n", alice.read());
 
@@ -175,8 +175,8 @@ fn example_delete_update_precedence() {
     println!("  Bob updates index 0 to 'modified'\n");
 
     // Apply in different orders
-    alice.apply_remote(update_op.clone());
-    bob.apply_remote(delete_op.clone());
+    alice.apply_remote(update_op.clone()).unwrap();
+    bob.apply_remote(delete_op.clone()).unwrap();
 
     println!("Results:");
     println!("  Alice: {:?}", alice.read());
@@ -211,10 +211,10 @@ fn example_vector_clock_propagation() {
 
     // Sites 1 and 2 receive operations
     println!("\nSites 1 and 2 receive operations from Site 0:");
-    site1.apply_remote(op_a.clone());
-    site1.apply_remote(op_b.clone());
-    site2.apply_remote(op_a.clone());
-    site2.apply_remote(op_b.clone());
+    site1.apply_remote(op_a.clone()).unwrap();
+    site1.apply_remote(op_b.clone()).unwrap();
+    site2.apply_remote(op_a.clone()).unwrap();
+    site2.apply_remote(op_b.clone()).unwrap();
     println!("  Vector clocks updated: Site 1 and 2 now know about Site 0's operations");
 
     // Now Site 1 and Site 2 perform concurrent inserts
@@ -234,10 +234,10 @@ fn example_vector_clock_propagation() {
 
     // Exchange operations
     println!("\nExchanging operations between all sites:");
-    site0.apply_remote(op_1.clone());
-    site0.apply_remote(op_2.clone());
-    site1.apply_remote(op_2.clone());
-    site2.apply_remote(op_1.clone());
+    site0.apply_remote(op_1.clone()).unwrap();
+    site0.apply_remote(op_2.clone()).unwrap();
+    site1.apply_remote(op_2.clone()).unwrap();
+    site2.apply_remote(op_1.clone()).unwrap();
 
     println!("  Site 0: {:?}", site0.read());
     println!("  Site 1: {:?}", site1.read());
@@ -0,0 +1,156 @@
+// Stable positions for cursors, selections, comments and bookmarks
+//
+// A visible index shifts whenever text is inserted or deleted before it. An
+// anchor instead names the node next to the position by its s_k, which never
+// changes, so it can be resolved back to a current index after any sequence
+// of local and remote operations.
+
+use crate::rga::Rga;
+use crate::s4vector::S4Vector;
+use serde::{Deserialize, Serialize};
+
+/// Which neighbour of a position an anchor sticks to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Gravity {
+    /// Stick to the character before the position; text inserted exactly at
+    /// the anchor ends up after it (e.g. the end of a selection)
+    Left,
+
+    /// Stick to the character after the position; text inserted exactly at
+    /// the anchor ends up before it (e.g. a caret, the start of a selection)
+    Right,
+}
+
+/// A position between characters bound to a node identifier
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Anchor {
+    /// s_k of the node the anchor sticks to; None means the start of the
+    /// document for left gravity and the end for right gravity
+    pub id: Option<S4Vector>,
+    pub gravity: Gravity,
+}
+
+impl<T: Clone> Rga<T> {
+    /// Anchor the position before visible index `index` (0..=len)
+    ///
+    /// Returns None if `index` is past the end of the document.
+    pub fn anchor_at(&self, index: usize, gravity: Gravity) -> Option<Anchor> {
+        if index > self.len() {
+            return None;
+        }
+
+        let id = match gravity {
+            Gravity::Left => match index.checked_sub(1) {
+                Some(before) => Some(self.id_at(before)?),
+                None => None,
+            },
+            Gravity::Right => self.id_at(index),
+        };
+
+        Some(Anchor { id, gravity })
+    }
+
+    /// Current visible index of an anchor
+    ///
+    /// A deleted anchor node still resolves to where it would be, i.e. the
+    /// position between its surviving neighbours, and a moved one to where
+    /// it was moved. Returns None only if the
+    /// node is unknown here, either because its insert has not arrived yet or
+    /// because its tombstone was purged.
+    pub fn resolve_anchor(&self, anchor: &Anchor) -> Option<usize> {
+        let Some(id) = anchor.id else {
+            return Some(match anchor.gravity {
+                Gravity::Left => 0,
+                Gravity::Right => self.len(),
+            });
+        };
+
+        // Moved text takes its anchors along
+        let id = self.moves.current_location(&id);
+        match (self.element_position(&id)?, anchor.gravity) {
+            ((before, true), Gravity::Left) => Some(before + 1),
+            ((before, _), _) => Some(before),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Gravity, Rga};
+
+    fn replica(site_id: u32, text: &str) -> Rga<char> {
+        let mut rga = Rga::new(site_id, 2);
+        for (i, ch) in text.chars().enumerate() {
+            rga.insert_local(i, ch);
+        }
+        rga
+    }
+
+    #[test]
+    fn test_gravity_at_insertion_point() {
+        let mut rga = replica(0, "abcd");
+        let left = rga.anchor_at(2, Gravity::Left).unwrap();
+        let right = rga.anchor_at(2, Gravity::Right).unwrap();
+
+        rga.insert_local(2, 'x');
+        assert_eq!(rga.resolve_anchor(&left), Some(2));
+        assert_eq!(rga.resolve_anchor(&right), Some(3));
+
+        rga.insert_local(0, 'y');
+        assert_eq!(rga.resolve_anchor(&left), Some(3));
+        assert_eq!(rga.resolve_anchor(&right), Some(4));
+    }
+
+    #[test]
+    fn test_document_boundaries() {
+        let mut rga = replica(0, "ab");
+        let start = rga.anchor_at(0, Gravity::Left).unwrap();
+        let end = rga.anchor_at(2, Gravity::Right).unwrap();
+        assert!(rga.anchor_at(3, Gravity::Right).is_none());
+
+        rga.insert_local(0, 'x');
+        rga.insert_local(3, 'y');
+        assert_eq!(rga.resolve_anchor(&start), Some(0));
+        assert_eq!(rga.resolve_anchor(&end), Some(4));
+    }
+
+    #[test]
+    fn test_anchor_on_deleted_character() {
+        let mut rga = replica(0, "abcde");
+        let left = rga.anchor_at(3, Gravity::Left).unwrap();
+        let right = rga.anchor_at(2, Gravity::Right).unwrap();
+
+        // Delete "bcd"; both anchors collapse onto the gap
+        for _ in 0..3 {
+            rga.delete_local(1);
+        }
+        assert_eq!(rga.read(), vec!['a', 'e']);
+        assert_eq!(rga.resolve_anchor(&left), Some(1));
+        assert_eq!(rga.resolve_anchor(&right), Some(1));
+    }
+
+    #[test]
+    fn test_selection_follows_remote_edits() {
+        let mut site0 = replica(0, "hello world");
+        let mut site1 = Rga::<char>::new(1, 2);
+        for op in site0.ops_since(site1.vector_clock()).unwrap() {
+            site1.apply_remote(op).unwrap();
+        }
+
+        // Site 1 selects "world"
+        let start = site1.anchor_at(6, Gravity::Right).unwrap();
+        let end = site1.anchor_at(11, Gravity::Left).unwrap();
+
+        // Site 0 prepends text and deletes the space
+        site1
+            .apply_remote(site0.insert_local(0, '>').unwrap())
+            .unwrap();
+        site1.apply_remote(site0.delete_local(6).unwrap()).unwrap();
+
+        // Anchors are plain values, so they can travel to other replicas too
+        for rga in [&site0, &site1] {
+            assert_eq!(rga.resolve_anchor(&start), Some(6));
+            assert_eq!(rga.resolve_anchor(&end), Some(11));
+        }
+    }
+}
@@ -0,0 +1,202 @@
+// Atomic multi-op transactions
+//
+// Edits made inside `Rga::transact` are applied locally as usual, but leave
+// the replica as a single RemoteOp::Batch. Its ops take consecutive sequence
+// numbers of this site, so the batch is one causal unit: a receiver buffers
+// it until everything it refers to outside itself has arrived, validates
+// every op in it, and only then applies them all together. Other replicas
+// therefore never show half of a transaction, and undo reverts it in one
+// step.
+
+use crate::remote_op::RemoteOp;
+use crate::rga::Rga;
+use crate::undo::LocalEdit;
+
+impl<T: Clone> Rga<T> {
+    /// Run `edits` as one transaction
+    ///
+    /// The local operations `edits` makes (inserts, deletes, updates, moves,
+    /// undo and redo) are applied immediately and their RemoteOps can be
+    /// ignored: the returned op covers all of them and is the only one to
+    /// broadcast. It is a Batch when there were several, the op itself when
+    /// there was one, and None when `edits` changed nothing. A transaction
+    /// inside another one joins it and returns None.
+    pub fn transact(&mut self, edits: impl FnOnce(&mut Self)) -> Option<RemoteOp<T>> {
+        if self.history.batch.is_some() {
+            edits(self);
+            return None;
+        }
+
+        let log_start = self.log.ops.len();
+        self.history.batch = Some(Vec::new());
+        edits(self);
+        let mut recorded = self.history.batch.take().unwrap_or_default();
+
+        let op = self.seal_batch(log_start)?;
+        match recorded.len() {
+            0 => {}
+            1 => self.record_local(recorded.remove(0)),
+            _ => self.record_local(LocalEdit::Batch { edits: recorded }),
+        }
+        Some(op)
+    }
+
+    // Replace the ops this site logged since `log_start` by one op covering
+    // them all, logged after any remote ops applied in the meantime
+    // Returns that op, or None if this site logged nothing
+    pub(crate) fn seal_batch(&mut self, log_start: usize) -> Option<RemoteOp<T>> {
+        let logged = self.log.ops.split_off(log_start);
+        let (own, others): (Vec<_>, Vec<_>) = logged
+            .into_iter()
+            .partition(|op| op.s4v().sid == self.site_id);
+        self.log.ops.extend(others);
+
+        // Batches do not nest, so an undone transaction is flattened into
+        // the one around it
+        let mut ops = Vec::with_capacity(own.len());
+        for op in own {
+            match op {
+                RemoteOp::Batch { ops: inner, .. } => ops.extend(inner),
+                op => ops.push(op),
+            }
+        }
+
+        let op = if ops.len() > 1 {
+            RemoteOp::Batch {
+                s4v: ops.first()?.s4v(),
+                vector_clock: ops.last()?.vector_clock().to_vec(),
+                ops,
+            }
+        } else {
+            ops.pop()?
+        };
+        self.log.push(op.clone());
+        Some(op)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ApplyError, RemoteOp, Rga};
+
+    fn text(rga: &Rga<char>) -> String {
+        rga.read().into_iter().collect()
+    }
+
+    #[test]
+    fn test_transaction_applies_as_one_op() {
+        let mut site0 = Rga::<char>::new(0, 2);
+        let mut site1 = Rga::<char>::new(1, 2);
+        site1
+            .apply_remote(
+                site0
+                    .insert_run_local(0, "hello world".chars().collect())
+                    .unwrap(),
+            )
+            .unwrap();
+
+        // Replace "world" and capitalise the result
+        let op = site0
+            .transact(|rga| {
+                rga.delete_range_local(6, 5);
+                rga.insert_run_local(6, "there".chars().collect());
+                rga.update_local(0, 'H');
+                rga.insert_local(11, '!');
+            })
+            .unwrap();
+        assert!(matches!(&op, RemoteOp::Batch { ops, .. } if ops.len() == 4));
+        assert_eq!(text(&site0), "Hello there!");
+
+        // Peers catching up later get the same single op
+        assert_eq!(site0.ops_since(&[11, 0]).unwrap().len(), 1);
+
+        site1.apply_remote(op.clone()).unwrap();
+        assert_eq!(text(&site1), "Hello there!");
+        assert!(site1.has_applied(&op));
+        assert_eq!(site1.apply_remote(op).unwrap(), Vec::new());
+
+        // Undo reverts the whole transaction in one step
+        site1.apply_remote(site0.undo_local().unwrap()).unwrap();
+        assert_eq!(text(&site0), "hello world");
+        assert_eq!(text(&site1), "hello world");
+        site1.apply_remote(site0.redo_local().unwrap()).unwrap();
+        assert_eq!(text(&site1), "Hello there!");
+        assert_eq!(text(&site0), text(&site1));
+    }
+
+    #[test]
+    fn test_batch_waits_for_its_dependencies() {
+        let mut site0 = Rga::<char>::new(0, 3);
+        let mut site1 = Rga::<char>::new(1, 3);
+        let mut site2 = Rga::<char>::new(2, 3);
+
+        let base = site0.insert_run_local(0, "ab".chars().collect()).unwrap();
+        site1.apply_remote(base.clone()).unwrap();
+        let batch = site1
+            .transact(|rga| {
+                rga.insert_local(1, 'x');
+                rga.delete_local(0);
+            })
+            .unwrap();
+
+        // Nothing of the batch shows before the text it edits arrives
+        site2.apply_remote(batch).unwrap();
+        assert_eq!(site2.pending_count(), 1);
+        assert_eq!(text(&site2), "");
+
+        site2.apply_remote(base).unwrap();
+        assert_eq!(site2.pending_count(), 0);
+        assert_eq!(text(&site2), "xb");
+        assert_eq!(text(&site2), text(&site1));
+    }
+
+    #[test]
+    fn test_broken_batch_is_rejected() {
+        let mut site0 = Rga::<char>::new(0, 2);
+        let mut site1 = Rga::<char>::new(1, 2);
+
+        let RemoteOp::Batch {
+            ops,
+            s4v,
+            vector_clock,
+        } = site0
+            .transact(|rga| {
+                rga.insert_local(0, 'a');
+                rga.insert_local(1, 'b');
+                rga.delete_local(0);
+            })
+            .unwrap()
+        else {
+            panic!("expected a batch");
+        };
+
+        // Dropping the first insert leaves a gap and an unknown element
+        let gapped = RemoteOp::Batch {
+            ops: ops[1..].to_vec(),
+            s4v,
+            vector_clock: vector_clock.clone(),
+        };
+        assert!(matches!(
+            site1.apply_remote(gapped),
+            Err(ApplyError::BrokenBatch(_))
+        ));
+
+        // Deleting 'a' before inserting it
+        let reordered = RemoteOp::Batch {
+            ops: vec![ops[2].clone(), ops[0].clone(), ops[1].clone()],
+            s4v: ops[2].s4v(),
+            vector_clock: ops[1].vector_clock().to_vec(),
+        };
+        assert!(site1.apply_remote(reordered).is_err());
+        assert_eq!(site1.pending_count(), 0);
+        assert_eq!(text(&site1), "");
+
+        let batch = RemoteOp::Batch {
+            ops,
+            s4v,
+            vector_clock,
+        };
+        site1.apply_remote(batch).unwrap();
+        assert_eq!(text(&site1), "b");
+    }
+}
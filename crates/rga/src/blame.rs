@@ -0,0 +1,124 @@
+// Per-element authorship derived from RGA identifiers
+//
+// Every element already records who created it (the sid of its insert
+// S4Vector) and who last changed it (the s_p of an updated node), so blame
+// needs no extra bookkeeping: it is a walk over the visible runs.
+
+use crate::rga::Rga;
+use crate::s4vector::S4Vector;
+use serde::{Deserialize, Serialize};
+
+/// Contiguous visible range inserted by one site
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuthorSpan {
+    /// Visible index of the first element of the range
+    pub index: usize,
+
+    /// Number of elements in the range
+    pub len: usize,
+
+    /// Site that inserted the range
+    pub site: u32,
+
+    /// Insert S4Vector of the first element; element k was created by
+    /// `inserted.offset(k)`
+    pub inserted: S4Vector,
+
+    /// Update that last changed the range, if any (made by site `sid`)
+    pub last_update: Option<S4Vector>,
+}
+
+impl<T: Clone> Rga<T> {
+    /// Authorship of the visible document as spans in document order
+    ///
+    /// Adjacent visible elements with consecutive insert S4Vectors share a
+    /// span, however many operations inserted them. Updated elements get a
+    /// span of their own carrying the winning update.
+    pub fn authorship(&self) -> Vec<AuthorSpan> {
+        let mut spans: Vec<AuthorSpan> = Vec::new();
+        let mut index = 0;
+        let mut current = self.head;
+
+        while let Some(slot) = current {
+            let node = &self.nodes[slot];
+            current = node.link;
+
+            if node.is_tombstone() {
+                continue;
+            }
+            let len = node.len as usize;
+            let last_update = (!node.is_untouched()).then_some(node.s_p);
+
+            match spans.last_mut() {
+                Some(span)
+                    if last_update.is_none()
+                        && span.last_update.is_none()
+                        && span.inserted.offset(span.len as u32) == node.s_k =>
+                {
+                    span.len += len;
+                }
+                _ => spans.push(AuthorSpan {
+                    index,
+                    len,
+                    site: node.s_k.sid,
+                    inserted: node.s_k,
+                    last_update,
+                }),
+            }
+            index += len;
+        }
+
+        spans
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Rga;
+
+    #[test]
+    fn test_authorship_spans() {
+        let mut site0 = Rga::<char>::new(0, 2);
+        let mut site1 = Rga::<char>::new(1, 2);
+
+        site1
+            .apply_remote(
+                site0
+                    .insert_run_local(0, "hello".chars().collect())
+                    .unwrap(),
+            )
+            .unwrap();
+        site0
+            .apply_remote(
+                site1
+                    .insert_run_local(5, " world".chars().collect())
+                    .unwrap(),
+            )
+            .unwrap();
+        site1.apply_remote(site0.delete_local(2).unwrap()).unwrap();
+        site0
+            .apply_remote(site1.update_local(0, 'H').unwrap())
+            .unwrap();
+
+        let spans = site0.authorship();
+        assert_eq!(spans, site1.authorship());
+
+        // "H" | "e" | "lo" after the deleted 'l' | " world"
+        let layout: Vec<(usize, usize, u32, bool)> = spans
+            .iter()
+            .map(|s| (s.index, s.len, s.site, s.last_update.is_some()))
+            .collect();
+        assert_eq!(
+            layout,
+            vec![
+                (0, 1, 0, true),
+                (1, 1, 0, false),
+                (2, 2, 0, false),
+                (4, 6, 1, false)
+            ]
+        );
+        assert_eq!(spans[0].last_update.map(|s4v| s4v.sid), Some(1));
+        assert_eq!(spans[2].inserted, site0.id_at(2).unwrap());
+        assert_eq!(spans.iter().map(|s| s.len).sum::<usize>(), site0.len());
+    }
+}
@@ -0,0 +1,176 @@
+// Visible effects of operations on the document
+//
+// Applying a remote op reports where in the visible sequence it landed, so
+// editors can patch their buffers instead of re-reading the whole document.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Arc;
+
+/// A change to the visible document
+///
+/// Indices refer to the document as it was right before the change, so a
+/// list of changes must be applied in order.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Change<T> {
+    /// `values` were inserted, the first one now at visible index `index`
+    Insert { index: usize, values: Vec<T> },
+
+    /// `len` visible elements starting at `index` were removed
+    Delete { index: usize, len: usize },
+
+    /// The element at `index` now holds `value`
+    Update { index: usize, value: T },
+}
+
+// Callback notified of every change caused by remote operations
+type Notify<T> = dyn Fn(&Change<T>) + Send + Sync;
+
+// Shared so that cloning a replica keeps its observer
+#[derive(Clone)]
+pub(crate) struct Observer<T>(pub(crate) Arc<Notify<T>>);
+
+impl<T> fmt::Debug for Observer<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Observer")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Change, Rga};
+    use std::sync::{Arc, Mutex};
+
+    // Apply a change to a plain buffer, as an editor would
+    fn patch(buffer: &mut Vec<char>, change: &Change<char>) {
+        match change {
+            Change::Insert { index, values } => {
+                buffer.splice(index..index, values.iter().copied());
+            }
+            Change::Delete { index, len } => {
+                buffer.drain(*index..index + len);
+            }
+            Change::Update { index, value } => buffer[*index] = *value,
+        }
+    }
+
+    #[test]
+    fn test_remote_ops_report_positions() {
+        let mut site0 = Rga::<char>::new(0, 2);
+        let mut site1 = Rga::<char>::new(1, 2);
+
+        let op = site0
+            .insert_run_local(0, "hello".chars().collect())
+            .unwrap();
+        assert_eq!(
+            site1.apply_remote(op).unwrap(),
+            vec![Change::Insert {
+                index: 0,
+                values: "hello".chars().collect(),
+            }]
+        );
+
+        let op = site0.insert_local(5, '!').unwrap();
+        assert_eq!(
+            site1.apply_remote(op).unwrap(),
+            vec![Change::Insert {
+                index: 5,
+                values: vec!['!'],
+            }]
+        );
+
+        let op = site0.delete_range_local(1, 3).unwrap();
+        assert_eq!(
+            site1.apply_remote(op).unwrap(),
+            vec![Change::Delete { index: 1, len: 3 }]
+        );
+
+        let op = site0.update_local(0, 'H').unwrap();
+        assert_eq!(
+            site1.apply_remote(op).unwrap(),
+            vec![Change::Update {
+                index: 0,
+                value: 'H',
+            }]
+        );
+        assert_eq!(site1.read(), vec!['H', 'o', '!']);
+    }
+
+    #[test]
+    fn test_invisible_ops_report_nothing() {
+        let mut site0 = Rga::<char>::new(0, 2);
+        let mut site1 = Rga::<char>::new(1, 2);
+
+        let op_a = site0.insert_local(0, 'a').unwrap();
+        let op_b = site0.insert_local(1, 'b').unwrap();
+
+        // Buffered until its left cobject arrives, then reported with it
+        assert!(site1.apply_remote(op_b).unwrap().is_empty());
+        assert_eq!(site1.apply_remote(op_a).unwrap().len(), 2);
+
+        // Deleting a tombstone and a losing update have no visible effect
+        let op_update = site1.update_local(0, 'x').unwrap();
+        let op_del0 = site0.delete_local(0).unwrap();
+        let op_del1 = site1.delete_local(0).unwrap();
+        assert!(site0.apply_remote(op_del1).unwrap().is_empty());
+        assert!(site0.apply_remote(op_update).unwrap().is_empty());
+        assert!(site1.apply_remote(op_del0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_changes_patch_a_mirror_buffer() {
+        let mut site0 = Rga::<char>::new(0, 2);
+        let mut site1 = Rga::<char>::new(1, 2);
+        let mut mirror = Vec::new();
+
+        let mut ops = vec![site0
+            .insert_run_local(0, "the quick brown fox".chars().collect())
+            .unwrap()];
+        site1.apply_remote(ops[0].clone()).unwrap();
+        ops.push(site1.delete_range_local(4, 6).unwrap());
+        ops.push(
+            site0
+                .insert_run_local(10, "red ".chars().collect())
+                .unwrap(),
+        );
+        ops.push(site0.delete_range_local(2, 12).unwrap());
+        ops.push(site1.update_local(0, 'T').unwrap());
+
+        let mut observer = Rga::<char>::new(2, 3);
+        for op in ops {
+            for change in observer.apply_remote(op).unwrap() {
+                patch(&mut mirror, &change);
+            }
+        }
+
+        for op in site0.ops_since(site1.vector_clock()).unwrap() {
+            site1.apply_remote(op).unwrap();
+        }
+        assert_eq!(mirror, observer.read());
+        assert_eq!(mirror, site1.read());
+    }
+
+    #[test]
+    fn test_observer_is_notified() {
+        let mut site0 = Rga::<char>::new(0, 2);
+        let mut site1 = Rga::<char>::new(1, 2);
+
+        let mirror = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&mirror);
+        site1.set_observer(move |change| patch(&mut sink.lock().unwrap(), change));
+
+        site1
+            .apply_remote(site0.insert_run_local(0, "abc".chars().collect()).unwrap())
+            .unwrap();
+        site1.apply_remote(site0.delete_local(1).unwrap()).unwrap();
+        assert_eq!(*mirror.lock().unwrap(), vec!['a', 'c']);
+
+        // Local edits are already known to the caller
+        site1.insert_local(0, 'x');
+        assert_eq!(*mirror.lock().unwrap(), vec!['a', 'c']);
+
+        site1.clear_observer();
+        site1.apply_remote(site0.delete_local(0).unwrap()).unwrap();
+        assert_eq!(*mirror.lock().unwrap(), vec!['a', 'c']);
+    }
+}
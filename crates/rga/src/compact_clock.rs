@@ -0,0 +1,170 @@
+// Compact wire form of vector clocks
+//
+// Every op carries its site's vector clock, with one counter per site the
+// room has ever had. In a large room most of those sites only read or left
+// long ago, so most counters are zero: on the wire a clock is written as
+// (site, counter) pairs of its non-zero entries instead. Decoding yields the
+// dense form without trailing zeros, which compares, covers and merges
+// exactly like the original.
+//
+// Readable formats (JSON) still accept the dense form, so ops stored or
+// sent by older peers keep loading.
+
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+// Highest number of sites a decoded clock may cover, so that a short
+// message cannot make a replica allocate a huge dense clock
+pub const MAX_CLOCK_SITES: u32 = 1 << 16;
+
+/// A vector clock as (site, counter) pairs of its non-zero entries, in
+/// increasing site order
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CompactClock(Vec<(u32, u32)>);
+
+impl CompactClock {
+    /// Compact form of a dense vector clock
+    pub fn from_dense(clock: &[u32]) -> Self {
+        CompactClock(
+            clock
+                .iter()
+                .enumerate()
+                .filter(|&(_, &count)| count > 0)
+                .map(|(site, &count)| (site as u32, count))
+                .collect(),
+        )
+    }
+
+    /// Dense form, up to the last non-zero entry
+    ///
+    /// Returns None if the sites are not strictly increasing or reach
+    /// `MAX_CLOCK_SITES`.
+    pub fn to_dense(&self) -> Option<Vec<u32>> {
+        let mut clock = Vec::new();
+        for &(site, count) in &self.0 {
+            if site >= MAX_CLOCK_SITES || (site as usize) < clock.len() {
+                return None;
+            }
+            clock.resize(site as usize, 0);
+            clock.push(count);
+        }
+        while clock.last() == Some(&0) {
+            clock.pop();
+        }
+        Some(clock)
+    }
+
+    /// The (site, counter) pairs
+    pub fn entries(&self) -> &[(u32, u32)] {
+        &self.0
+    }
+}
+
+// `#[serde(with = "...")]` adapter writing a dense `Vec<u32>` clock as
+// sparse (site, counter) pairs
+pub(crate) mod sparse_clock {
+    use super::*;
+
+    pub(crate) fn serialize<S: Serializer>(
+        clock: &[u32],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        CompactClock::from_dense(clock).serialize(serializer)
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<u32>, D::Error> {
+        let compact = if deserializer.is_human_readable() {
+            // Either all pairs (compact) or all counters (dense)
+            #[derive(Deserialize)]
+            #[serde(untagged)]
+            enum Entry {
+                Pair(u32, u32),
+                Count(u32),
+            }
+
+            let entries = Vec::<Entry>::deserialize(deserializer)?;
+            if entries.iter().all(|entry| matches!(entry, Entry::Count(_))) {
+                let counts: Vec<u32> = entries
+                    .into_iter()
+                    .filter_map(|entry| match entry {
+                        Entry::Count(count) => Some(count),
+                        Entry::Pair(..) => None,
+                    })
+                    .collect();
+                CompactClock::from_dense(&counts)
+            } else {
+                let pairs: Option<Vec<(u32, u32)>> = entries
+                    .into_iter()
+                    .map(|entry| match entry {
+                        Entry::Pair(site, count) => Some((site, count)),
+                        Entry::Count(_) => None,
+                    })
+                    .collect();
+                CompactClock(pairs.ok_or_else(|| {
+                    D::Error::custom("vector clock mixes dense and compact entries")
+                })?)
+            }
+        } else {
+            CompactClock::deserialize(deserializer)?
+        };
+
+        compact
+            .to_dense()
+            .ok_or_else(|| D::Error::custom("vector clock sites out of order or out of range"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CompactClock, MAX_CLOCK_SITES};
+    use crate::{RemoteOp, S4Vector};
+
+    #[test]
+    fn test_dense_round_trip() {
+        let compact = CompactClock::from_dense(&[3, 0, 0, 5, 0]);
+        assert_eq!(compact.entries(), &[(0, 3), (3, 5)]);
+        assert_eq!(compact.to_dense(), Some(vec![3, 0, 0, 5]));
+        assert_eq!(
+            CompactClock::from_dense(&[0, 0]).to_dense(),
+            Some(Vec::new())
+        );
+
+        assert_eq!(CompactClock(vec![(2, 1), (1, 1)]).to_dense(), None);
+        assert_eq!(CompactClock(vec![(1, 1), (1, 2)]).to_dense(), None);
+        assert_eq!(CompactClock(vec![(MAX_CLOCK_SITES, 1)]).to_dense(), None);
+    }
+
+    #[test]
+    fn test_ops_carry_compact_clocks() {
+        // Site 40 types in a room where most sites never did
+        let mut vector_clock = vec![0; 41];
+        vector_clock[0] = 12;
+        vector_clock[40] = 1;
+        let op = RemoteOp::Insert {
+            left_id: None,
+            value: 'x',
+            s4v: S4Vector::new(1, 40, 13, 1),
+            vector_clock: vector_clock.clone(),
+        };
+
+        let json = serde_json::to_string(&op).unwrap();
+        assert!(json.contains("\"vector_clock\":[[0,12],[40,1]]"));
+        let decoded: RemoteOp<char> = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.vector_clock(), &vector_clock[..]);
+
+        let bytes = bincode::serialize(&op).unwrap();
+        let decoded: RemoteOp<char> = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(decoded.vector_clock(), &vector_clock[..]);
+
+        // Ops written with dense clocks still load
+        let legacy = json.replace(
+            "[[0,12],[40,1]]",
+            &format!("{vector_clock:?}").replace(' ', ""),
+        );
+        let decoded: RemoteOp<char> = serde_json::from_str(&legacy).unwrap();
+        assert_eq!(decoded.vector_clock(), &vector_clock[..]);
+        assert!(serde_json::from_str::<RemoteOp<char>>(&json.replace("[[0,12],", "[5,")).is_err());
+    }
+}
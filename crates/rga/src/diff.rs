@@ -0,0 +1,361 @@
+// Whole-text replacement as minimal edits
+//
+// Integrations that only know the old and the new content (file watchers,
+// formatters, full-content saves) should not delete and retype the whole
+// document: that would give every character a new identity, so concurrent
+// edits, anchors, marks and blame would all lose track of it. Instead the
+// visible content is diffed against the new one with Myers' O(ND)
+// algorithm, and only the differing stretches become local deletes and
+// inserts; every character common to both keeps its S4Vector.
+
+use crate::remote_op::RemoteOp;
+use crate::rga::Rga;
+use crate::validation::ApplyError;
+use std::ops::Range;
+
+// One differing stretch: `old_len` elements of the old content from
+// `old_start` are replaced by `new` of the new content
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Hunk {
+    old_start: usize,
+    old_len: usize,
+    new: Range<usize>,
+}
+
+// Step of an edit script, in order along both sequences
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Edit {
+    Keep,
+    Delete,
+    Insert,
+}
+
+// Furthest-reaching x on diagonal `k` of round `d`, before following the
+// snake, and whether it was reached by an insertion (a step down)
+// `previous` holds the x of every diagonal -(d-1), -(d-1)+2, ..., d-1 after
+// round d-1, None where the diagonal cannot be reached inside the grid
+fn step(
+    previous: &[Option<usize>],
+    d: isize,
+    k: isize,
+    n: usize,
+    m: usize,
+) -> Option<(usize, bool)> {
+    let at = |k: isize| {
+        if k.abs() > d - 1 {
+            return None;
+        }
+        previous.get(((k + d - 1) / 2) as usize).copied().flatten()
+    };
+    let fits = |x: usize, k: isize| x <= n && x as isize - k >= 0 && x as isize - k <= m as isize;
+
+    let down = at(k + 1).filter(|&x| fits(x, k));
+    let right = at(k - 1).map(|x| x + 1).filter(|&x| fits(x, k));
+    match (down, right) {
+        (Some(down), Some(right)) if right > down => Some((right, false)),
+        (Some(down), _) => Some((down, true)),
+        (None, Some(right)) => Some((right, false)),
+        (None, None) => None,
+    }
+}
+
+// Shortest edit script turning `old` into `new`
+fn edit_script<T: PartialEq>(old: &[T], new: &[T]) -> Vec<Edit> {
+    let (n, m) = (old.len(), new.len());
+    let snake = |mut x: usize, k: isize| {
+        while x < n && ((x as isize - k) as usize) < m && old[x] == new[(x as isize - k) as usize] {
+            x += 1;
+        }
+        x
+    };
+
+    // rounds[d][(k + d) / 2]: furthest x on diagonal k with d edits
+    let mut rounds: Vec<Vec<Option<usize>>> = Vec::new();
+    let mut d = 0isize;
+    'search: loop {
+        let mut round = Vec::with_capacity(d as usize + 1);
+        for k in (-d..=d).step_by(2) {
+            let start = if d == 0 {
+                Some(0)
+            } else {
+                step(&rounds[d as usize - 1], d, k, n, m).map(|(x, _)| x)
+            };
+            let end = start.map(|x| snake(x, k));
+            round.push(end);
+            if end.is_some_and(|x| x == n && x as isize - k == m as isize) {
+                rounds.push(round);
+                break 'search;
+            }
+        }
+        rounds.push(round);
+        d += 1;
+    }
+
+    // Walk back from (n, m) to (0, 0)
+    let mut edits = Vec::with_capacity(n + m);
+    let (mut x, mut y) = (n, m);
+    for d in (0..rounds.len() as isize).rev() {
+        let k = x as isize - y as isize;
+        let (start, insert) = match d {
+            0 => (0, false),
+            _ => step(&rounds[d as usize - 1], d, k, n, m).unwrap_or((0, false)),
+        };
+        edits.extend(std::iter::repeat_n(Edit::Keep, x - start));
+        (x, y) = (start, (start as isize - k) as usize);
+        if d > 0 {
+            if insert {
+                edits.push(Edit::Insert);
+                y -= 1;
+            } else {
+                edits.push(Edit::Delete);
+                x -= 1;
+            }
+        }
+    }
+    edits.reverse();
+    edits
+}
+
+// Differing stretches between `old` and `new`, in order
+fn hunks<T: PartialEq>(old: &[T], new: &[T]) -> Vec<Hunk> {
+    // Common prefix and suffix are cheap to skip and usually most of a
+    // document, which keeps the quadratic part of the search small
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (old_mid, new_mid) = (
+        &old[prefix..old.len() - suffix],
+        &new[prefix..new.len() - suffix],
+    );
+
+    let mut hunks: Vec<Hunk> = Vec::new();
+    let (mut x, mut y) = (prefix, prefix);
+    let mut open = false;
+    for edit in edit_script(old_mid, new_mid) {
+        if edit == Edit::Keep {
+            open = false;
+            x += 1;
+            y += 1;
+            continue;
+        }
+        if !open {
+            hunks.push(Hunk {
+                old_start: x,
+                old_len: 0,
+                new: y..y,
+            });
+            open = true;
+        }
+        let Some(hunk) = hunks.last_mut() else {
+            continue;
+        };
+        if edit == Edit::Delete {
+            hunk.old_len += 1;
+            x += 1;
+        } else {
+            hunk.new.end += 1;
+            y += 1;
+        }
+    }
+    hunks
+}
+
+impl<T: Clone + PartialEq> Rga<T> {
+    /// Change the visible content to `new` with as few edits as possible
+    ///
+    /// Elements common to the current content and `new` keep their
+    /// identity; each differing stretch becomes a local delete and/or
+    /// insert, made from the end of the document backwards. Returns the
+    /// RemoteOps to broadcast, in order, and none if the content is
+    /// already `new`. Each op is a separate undo step unless the call is
+    /// made inside `transact`, which also makes the change atomic for
+    /// other replicas. If this site has too few sequence numbers left for
+    /// the whole change, nothing is changed and `CounterOverflow` is
+    /// returned; only a new session (see `start_session`) gives it room.
+    pub fn apply_text_change(&mut self, new: &[T]) -> Result<Vec<RemoteOp<T>>, ApplyError> {
+        let old = self.read();
+        let hunks = hunks(&old, new);
+
+        // A delete takes one sequence number, an inserted element one each
+        let needed: u64 = hunks
+            .iter()
+            .map(|hunk| u64::from(hunk.old_len > 0) + hunk.new.len() as u64)
+            .sum();
+        let used = self
+            .vector_clock
+            .get(self.site_id as usize)
+            .copied()
+            .unwrap_or(0);
+        if needed > u64::from(u32::MAX - used) {
+            return Err(ApplyError::CounterOverflow);
+        }
+
+        let mut ops = Vec::new();
+        for hunk in hunks.into_iter().rev() {
+            if hunk.old_len > 0 {
+                ops.extend(self.delete_range_local(hunk.old_start, hunk.old_len));
+            }
+            if !hunk.new.is_empty() {
+                ops.extend(self.insert_run_local(hunk.old_start, new[hunk.new].to_vec()));
+            }
+        }
+        Ok(ops)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{edit_script, hunks, Edit, Hunk};
+    use crate::{ApplyError, Rga};
+
+    fn chars(s: &str) -> Vec<char> {
+        s.chars().collect()
+    }
+
+    fn text(rga: &Rga<char>) -> String {
+        rga.read().into_iter().collect()
+    }
+
+    // Length of a longest common subsequence, by dynamic programming
+    fn lcs(a: &[char], b: &[char]) -> usize {
+        let mut table = vec![vec![0; b.len() + 1]; a.len() + 1];
+        for (i, x) in a.iter().enumerate() {
+            for (j, y) in b.iter().enumerate() {
+                table[i + 1][j + 1] = if x == y {
+                    table[i][j] + 1
+                } else {
+                    table[i][j + 1].max(table[i + 1][j])
+                };
+            }
+        }
+        table[a.len()][b.len()]
+    }
+
+    #[test]
+    fn test_edit_script_is_minimal() {
+        let pairs = [
+            ("", ""),
+            ("", "abc"),
+            ("abc", ""),
+            ("abcabba", "cbabac"),
+            ("kitten", "sitting"),
+            ("aaaa", "aa"),
+            ("xyz", "abc"),
+            ("the quick brown fox", "a quick brown cat jumps"),
+        ];
+        for (old, new) in pairs {
+            let (old, new) = (chars(old), chars(new));
+            let edits = edit_script(&old, &new);
+
+            // Replaying the script rebuilds `new` from `old`
+            let (mut x, mut y) = (0, 0);
+            let mut rebuilt = Vec::new();
+            for edit in &edits {
+                match edit {
+                    Edit::Keep => {
+                        assert_eq!(old[x], new[y]);
+                        rebuilt.push(old[x]);
+                        x += 1;
+                        y += 1;
+                    }
+                    Edit::Delete => x += 1,
+                    Edit::Insert => {
+                        rebuilt.push(new[y]);
+                        y += 1;
+                    }
+                }
+            }
+            assert_eq!((x, y), (old.len(), new.len()));
+            assert_eq!(rebuilt, new);
+
+            let keeps = edits.iter().filter(|&&edit| edit == Edit::Keep).count();
+            assert_eq!(keeps, lcs(&old, &new));
+        }
+    }
+
+    #[test]
+    fn test_hunks_skip_common_text() {
+        assert_eq!(
+            hunks(&chars("hello world"), &chars("hello there world!")),
+            vec![
+                Hunk {
+                    old_start: 6,
+                    old_len: 0,
+                    new: 6..12,
+                },
+                Hunk {
+                    old_start: 11,
+                    old_len: 0,
+                    new: 17..18,
+                },
+            ]
+        );
+        assert!(hunks(&chars("same"), &chars("same")).is_empty());
+    }
+
+    #[test]
+    fn test_text_change_keeps_identities() {
+        let mut site0 = Rga::<char>::new(0, 2);
+        let mut site1 = Rga::<char>::new(1, 2);
+        site1
+            .apply_remote(site0.insert_run_local(0, chars("fn main() {}")).unwrap())
+            .unwrap();
+        let brace = site0.id_at(10).unwrap();
+
+        // A formatter rewrites the file while site 1 types concurrently
+        let ops = site0.apply_text_change(&chars("fn main() {\n}\n")).unwrap();
+        assert_eq!(ops.len(), 2);
+        assert_eq!(text(&site0), "fn main() {\n}\n");
+        assert_eq!(site0.id_at(10), Some(brace));
+
+        let typed = site1.insert_local(3, 'x').unwrap();
+        for op in ops {
+            site1.apply_remote(op).unwrap();
+        }
+        site0.apply_remote(typed).unwrap();
+        assert_eq!(text(&site0), "fn xmain() {\n}\n");
+        assert_eq!(text(&site1), text(&site0));
+
+        assert!(site0.apply_text_change(&site0.read()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_text_change_replaces_and_undoes() {
+        let mut rga = Rga::<char>::new(0, 1);
+        rga.insert_run_local(0, chars("one two three"));
+
+        let op = rga.transact(|rga| {
+            rga.apply_text_change(&chars("one 2 three four")).unwrap();
+        });
+        assert!(op.is_some());
+        assert_eq!(text(&rga), "one 2 three four");
+
+        rga.undo_local().unwrap();
+        assert_eq!(text(&rga), "one two three");
+
+        rga.apply_text_change(&[]).unwrap();
+        assert!(rga.is_empty());
+    }
+
+    #[test]
+    fn test_text_change_needs_all_its_seqs() {
+        let mut rga = Rga::<char>::new(0, 1);
+        rga.insert_run_local(0, chars("one two"));
+
+        // Replacing "two" takes a delete and three inserted elements
+        rga.vector_clock[0] = u32::MAX - 3;
+        assert_eq!(
+            rga.apply_text_change(&chars("one six")).unwrap_err(),
+            ApplyError::CounterOverflow
+        );
+        assert_eq!(text(&rga), "one two");
+
+        rga.vector_clock[0] = u32::MAX - 4;
+        assert_eq!(rga.apply_text_change(&chars("one six")).unwrap().len(), 2);
+        assert_eq!(text(&rga), "one six");
+    }
+}
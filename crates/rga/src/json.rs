@@ -0,0 +1,667 @@
+// JSON-like nested document built from RGAs and RHTs
+//
+// A document is a tree of objects: maps (an `Rht` from keys to entries),
+// lists (an `Rga` of entries) and text (an `Rga<char>`). The root is a map.
+// A map value or list element is either a scalar or a reference to a nested
+// object, which is created by the Put or Insert that places it.
+//
+// Every object is a replica of its own, with its own vector clock, so an
+// operation is simply an inner RHT/RGA op tagged with the object it targets.
+// Ops on different objects are not causally ordered with respect to each
+// other; an op on an object that has not been created here yet is held back
+// until the op creating it arrives.
+
+use crate::change::Change;
+use crate::remote_op::RemoteOp;
+use crate::rga::Rga;
+use crate::rht::{Rht, RhtOp};
+use crate::validation::ApplyError;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+
+/// Identifier of an object in a `JsonDoc`
+///
+/// Objects are numbered per site in creation order; `ObjectId::ROOT` is the
+/// root map every replica starts with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ObjectId {
+    pub site: u32,
+    pub seq: u32,
+}
+
+impl ObjectId {
+    pub const ROOT: ObjectId = ObjectId { site: 0, seq: 0 };
+}
+
+/// Kind of a nested object
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ObjectKind {
+    Map,
+    List,
+    Text,
+}
+
+/// Value of a map key or list element
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Entry {
+    /// Atomic value; JSON arrays and objects stored here are replaced as a
+    /// whole, never merged
+    Scalar(serde_json::Value),
+
+    /// Reference to a nested object
+    Object(ObjectId, ObjectKind),
+}
+
+/// Step of a path from the root: a map key or a list index
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+// Inner operation on one object
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ObjectOp {
+    Map(RhtOp<String, Entry>),
+    List(RemoteOp<Entry>),
+    Text(RemoteOp<char>),
+}
+
+// Remote operation on a JsonDoc - serializable for network transmission
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JsonOp {
+    pub object: ObjectId,
+    pub op: ObjectOp,
+}
+
+// Reasons an edit or remote operation is rejected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JsonError {
+    // No object at the path: a key or index is missing, or names a scalar
+    PathNotFound(Vec<Segment>),
+
+    // The object at the path is not of the kind the edit needs
+    WrongKind {
+        expected: ObjectKind,
+        found: ObjectKind,
+    },
+
+    // List or text index outside the object
+    IndexOutOfRange {
+        index: usize,
+        len: usize,
+    },
+
+    // Remote op whose kind differs from the object it targets
+    KindMismatch(ObjectId),
+
+    // The inner op was rejected by the object's replica
+    Apply(ApplyError),
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JsonError::PathNotFound(path) => write!(f, "no object at path {path:?}"),
+            JsonError::WrongKind { expected, found } => {
+                write!(f, "expected a {expected:?} object, found a {found:?}")
+            }
+            JsonError::IndexOutOfRange { index, len } => {
+                write!(f, "index {index} is outside an object of {len} elements")
+            }
+            JsonError::KindMismatch(id) => {
+                write!(f, "operation does not match the kind of object {id:?}")
+            }
+            JsonError::Apply(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for JsonError {}
+
+impl From<ApplyError> for JsonError {
+    fn from(e: ApplyError) -> Self {
+        JsonError::Apply(e)
+    }
+}
+
+// Replica of one object of the tree
+#[derive(Debug, Clone)]
+enum Object {
+    Map(Rht<String, Entry>),
+    List(Rga<Entry>),
+    Text(Rga<char>),
+}
+
+impl Object {
+    fn new(kind: ObjectKind, site_id: u32, num_sites: usize) -> Self {
+        match kind {
+            ObjectKind::Map => Object::Map(Rht::new(site_id, num_sites)),
+            ObjectKind::List => Object::List(Rga::new(site_id, num_sites)),
+            ObjectKind::Text => Object::Text(Rga::new(site_id, num_sites)),
+        }
+    }
+
+    fn kind(&self) -> ObjectKind {
+        match self {
+            Object::Map(_) => ObjectKind::Map,
+            Object::List(_) => ObjectKind::List,
+            Object::Text(_) => ObjectKind::Text,
+        }
+    }
+}
+
+// Objects referenced by the entries a list op inserted
+fn inserted_objects(changes: Vec<Change<Entry>>) -> Vec<(ObjectId, ObjectKind)> {
+    changes
+        .into_iter()
+        .filter_map(|change| match change {
+            Change::Insert { values, .. } => Some(values),
+            _ => None,
+        })
+        .flatten()
+        .filter_map(|entry| match entry {
+            Entry::Object(id, kind) => Some((id, kind)),
+            Entry::Scalar(_) => None,
+        })
+        .collect()
+}
+
+/// Collaborative JSON-like document
+///
+/// Edits address the object they change by its path from the root, and
+/// each returns the `JsonOp` to broadcast. Concurrent edits to one object
+/// merge as its RGA or RHT does; edits to different objects are
+/// independent.
+#[derive(Debug, Clone)]
+pub struct JsonDoc {
+    objects: HashMap<ObjectId, Object>,
+    site_id: u32,
+    num_sites: usize,
+
+    // Number of objects this site has created
+    created: u32,
+
+    // Remote ops waiting for the object they target to be created
+    pending: HashMap<ObjectId, Vec<JsonOp>>,
+}
+
+impl JsonDoc {
+    // Create a new document with an empty root map for the given site
+    pub fn new(site_id: u32, num_sites: usize) -> Self {
+        let mut objects = HashMap::new();
+        objects.insert(
+            ObjectId::ROOT,
+            Object::new(ObjectKind::Map, site_id, num_sites),
+        );
+
+        JsonDoc {
+            objects,
+            site_id,
+            num_sites,
+            created: 0,
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Contents of the document as JSON
+    pub fn to_json(&self) -> serde_json::Value {
+        self.materialize(ObjectId::ROOT)
+    }
+
+    /// Contents of the object at `path` as JSON
+    pub fn get(&self, path: &[Segment]) -> Option<serde_json::Value> {
+        self.resolve(path).ok().map(|id| self.materialize(id))
+    }
+
+    /// Number of remote operations waiting for their object to be created
+    pub fn pending_count(&self) -> usize {
+        self.pending.values().map(Vec::len).sum()
+    }
+
+    /// Set `key` of the map at `path` to a scalar
+    pub fn put(
+        &mut self,
+        path: &[Segment],
+        key: &str,
+        value: serde_json::Value,
+    ) -> Result<JsonOp, JsonError> {
+        self.put_entry(path, key, Entry::Scalar(value))
+    }
+
+    /// Set `key` of the map at `path` to a new, empty object
+    pub fn put_object(
+        &mut self,
+        path: &[Segment],
+        key: &str,
+        kind: ObjectKind,
+    ) -> Result<JsonOp, JsonError> {
+        let id = self.next_object_id();
+        let op = self.put_entry(path, key, Entry::Object(id, kind))?;
+        self.created += 1;
+        self.create(id, kind);
+        Ok(op)
+    }
+
+    /// Remove `key` from the map at `path`
+    pub fn remove(&mut self, path: &[Segment], key: &str) -> Result<JsonOp, JsonError> {
+        let (object, map) = self.map_at(path)?;
+        let op = map.remove_local(&key.to_string()).ok_or_else(|| {
+            let mut missing = path.to_vec();
+            missing.push(Segment::Key(key.to_string()));
+            JsonError::PathNotFound(missing)
+        })?;
+
+        Ok(JsonOp {
+            object,
+            op: ObjectOp::Map(op),
+        })
+    }
+
+    /// Insert a scalar at `index` of the list at `path`
+    pub fn insert(
+        &mut self,
+        path: &[Segment],
+        index: usize,
+        value: serde_json::Value,
+    ) -> Result<JsonOp, JsonError> {
+        self.insert_entry(path, index, Entry::Scalar(value))
+    }
+
+    /// Insert a new, empty object at `index` of the list at `path`
+    pub fn insert_object(
+        &mut self,
+        path: &[Segment],
+        index: usize,
+        kind: ObjectKind,
+    ) -> Result<JsonOp, JsonError> {
+        let id = self.next_object_id();
+        let op = self.insert_entry(path, index, Entry::Object(id, kind))?;
+        self.created += 1;
+        self.create(id, kind);
+        Ok(op)
+    }
+
+    /// Insert `text` at character `index` of the text at `path`
+    pub fn insert_text(
+        &mut self,
+        path: &[Segment],
+        index: usize,
+        text: &str,
+    ) -> Result<JsonOp, JsonError> {
+        let (object, rga) = self.text_at(path)?;
+        let len = rga.len();
+        if index > len {
+            return Err(JsonError::IndexOutOfRange { index, len });
+        }
+        if text.is_empty() {
+            return Err(JsonError::Apply(ApplyError::Empty));
+        }
+        let op = rga
+            .insert_run_local(index, text.chars().collect())
+            .ok_or(JsonError::Apply(ApplyError::CounterOverflow))?;
+
+        Ok(JsonOp {
+            object,
+            op: ObjectOp::Text(op),
+        })
+    }
+
+    /// Delete `len` elements starting at `index` of the list or text at `path`
+    pub fn delete(
+        &mut self,
+        path: &[Segment],
+        index: usize,
+        len: usize,
+    ) -> Result<JsonOp, JsonError> {
+        let object = self.resolve(path)?;
+        let out_of_range = |total| JsonError::IndexOutOfRange { index, len: total };
+        let op = match self.objects.get_mut(&object) {
+            Some(Object::List(rga)) => ObjectOp::List(
+                rga.delete_range_local(index, len)
+                    .ok_or_else(|| out_of_range(rga.len()))?,
+            ),
+            Some(Object::Text(rga)) => ObjectOp::Text(
+                rga.delete_range_local(index, len)
+                    .ok_or_else(|| out_of_range(rga.len()))?,
+            ),
+            Some(Object::Map(_)) => {
+                return Err(JsonError::WrongKind {
+                    expected: ObjectKind::List,
+                    found: ObjectKind::Map,
+                })
+            }
+            None => return Err(JsonError::PathNotFound(path.to_vec())),
+        };
+
+        Ok(JsonOp { object, op })
+    }
+
+    /// Apply an operation received from another site
+    ///
+    /// Ops on objects not created here yet are buffered and applied once
+    /// the op creating the object arrives.
+    pub fn apply_remote(&mut self, op: JsonOp) -> Result<(), JsonError> {
+        let mut ready = vec![op];
+
+        while let Some(op) = ready.pop() {
+            let Some(object) = self.objects.get_mut(&op.object) else {
+                self.pending.entry(op.object).or_default().push(op);
+                continue;
+            };
+
+            let created = match (object, op.op) {
+                (Object::Map(map), ObjectOp::Map(inner)) => {
+                    // Objects are created even by Puts that lose, so ops on
+                    // them never wait forever
+                    let created = match &inner {
+                        RhtOp::Put {
+                            value: Entry::Object(id, kind),
+                            ..
+                        } => vec![(*id, *kind)],
+                        _ => Vec::new(),
+                    };
+                    map.apply_remote(inner)?;
+                    created
+                }
+                (Object::List(list), ObjectOp::List(inner)) => {
+                    inserted_objects(list.apply_remote(inner)?)
+                }
+                (Object::Text(text), ObjectOp::Text(inner)) => {
+                    text.apply_remote(inner)?;
+                    Vec::new()
+                }
+                _ => return Err(JsonError::KindMismatch(op.object)),
+            };
+
+            for (id, kind) in created {
+                self.create(id, kind);
+                ready.extend(self.pending.remove(&id).unwrap_or_default());
+            }
+        }
+
+        Ok(())
+    }
+
+    // Id of the next object this site creates
+    fn next_object_id(&self) -> ObjectId {
+        ObjectId {
+            site: self.site_id,
+            seq: self.created + 1,
+        }
+    }
+
+    fn create(&mut self, id: ObjectId, kind: ObjectKind) {
+        let (site_id, num_sites) = (self.site_id, self.num_sites);
+        self.objects
+            .entry(id)
+            .or_insert_with(|| Object::new(kind, site_id, num_sites));
+    }
+
+    fn put_entry(
+        &mut self,
+        path: &[Segment],
+        key: &str,
+        entry: Entry,
+    ) -> Result<JsonOp, JsonError> {
+        let (object, map) = self.map_at(path)?;
+        let op = map
+            .put_local(key.to_string(), entry)
+            .ok_or(JsonError::Apply(ApplyError::CounterOverflow))?;
+
+        Ok(JsonOp {
+            object,
+            op: ObjectOp::Map(op),
+        })
+    }
+
+    fn insert_entry(
+        &mut self,
+        path: &[Segment],
+        index: usize,
+        entry: Entry,
+    ) -> Result<JsonOp, JsonError> {
+        let (object, list) = self.list_at(path)?;
+        let len = list.len();
+        if index > len {
+            return Err(JsonError::IndexOutOfRange { index, len });
+        }
+        let op = list
+            .insert_local(index, entry)
+            .ok_or(JsonError::Apply(ApplyError::CounterOverflow))?;
+
+        Ok(JsonOp {
+            object,
+            op: ObjectOp::List(op),
+        })
+    }
+
+    // Id of the object at `path`
+    fn resolve(&self, path: &[Segment]) -> Result<ObjectId, JsonError> {
+        let mut current = ObjectId::ROOT;
+
+        for (depth, segment) in path.iter().enumerate() {
+            let entry = match (self.objects.get(&current), segment) {
+                (Some(Object::Map(map)), Segment::Key(key)) => map.get(key),
+                (Some(Object::List(list)), Segment::Index(index)) => list.get(*index),
+                _ => None,
+            };
+            match entry {
+                Some(Entry::Object(id, _)) => current = *id,
+                _ => return Err(JsonError::PathNotFound(path[..=depth].to_vec())),
+            }
+        }
+
+        Ok(current)
+    }
+
+    // Object at `path`, which must be of kind `expected`
+    fn object_at(
+        &mut self,
+        path: &[Segment],
+        expected: ObjectKind,
+    ) -> Result<(ObjectId, &mut Object), JsonError> {
+        let id = self.resolve(path)?;
+        let object = self
+            .objects
+            .get_mut(&id)
+            .ok_or_else(|| JsonError::PathNotFound(path.to_vec()))?;
+        if object.kind() != expected {
+            return Err(JsonError::WrongKind {
+                expected,
+                found: object.kind(),
+            });
+        }
+        Ok((id, object))
+    }
+
+    fn map_at(
+        &mut self,
+        path: &[Segment],
+    ) -> Result<(ObjectId, &mut Rht<String, Entry>), JsonError> {
+        match self.object_at(path, ObjectKind::Map)? {
+            (id, Object::Map(map)) => Ok((id, map)),
+            (_, object) => Err(JsonError::WrongKind {
+                expected: ObjectKind::Map,
+                found: object.kind(),
+            }),
+        }
+    }
+
+    fn list_at(&mut self, path: &[Segment]) -> Result<(ObjectId, &mut Rga<Entry>), JsonError> {
+        match self.object_at(path, ObjectKind::List)? {
+            (id, Object::List(list)) => Ok((id, list)),
+            (_, object) => Err(JsonError::WrongKind {
+                expected: ObjectKind::List,
+                found: object.kind(),
+            }),
+        }
+    }
+
+    fn text_at(&mut self, path: &[Segment]) -> Result<(ObjectId, &mut Rga<char>), JsonError> {
+        match self.object_at(path, ObjectKind::Text)? {
+            (id, Object::Text(text)) => Ok((id, text)),
+            (_, object) => Err(JsonError::WrongKind {
+                expected: ObjectKind::Text,
+                found: object.kind(),
+            }),
+        }
+    }
+
+    fn materialize_entry(&self, entry: &Entry) -> serde_json::Value {
+        match entry {
+            Entry::Scalar(value) => value.clone(),
+            Entry::Object(id, _) => self.materialize(*id),
+        }
+    }
+
+    fn materialize(&self, id: ObjectId) -> serde_json::Value {
+        match self.objects.get(&id) {
+            Some(Object::Map(map)) => serde_json::Value::Object(
+                map.iter()
+                    .map(|(key, entry)| (key.clone(), self.materialize_entry(entry)))
+                    .collect(),
+            ),
+            Some(Object::List(list)) => serde_json::Value::Array(
+                list.read()
+                    .iter()
+                    .map(|entry| self.materialize_entry(entry))
+                    .collect(),
+            ),
+            Some(Object::Text(text)) => {
+                serde_json::Value::String(text.read().into_iter().collect())
+            }
+            None => serde_json::Value::Null,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{JsonDoc, JsonError, ObjectKind, Segment};
+    use serde_json::json;
+
+    fn key(key: &str) -> Segment {
+        Segment::Key(key.to_string())
+    }
+
+    #[test]
+    fn test_concurrent_outline_edits_converge() {
+        let mut site0 = JsonDoc::new(0, 2);
+        let mut site1 = JsonDoc::new(1, 2);
+
+        let mut ops = vec![
+            site0.put_object(&[], "title", ObjectKind::Text).unwrap(),
+            site0.put_object(&[], "tasks", ObjectKind::List).unwrap(),
+        ];
+        ops.push(site0.insert_text(&[key("title")], 0, "Plan").unwrap());
+        ops.push(
+            site0
+                .insert_object(&[key("tasks")], 0, ObjectKind::Map)
+                .unwrap(),
+        );
+        let task = [key("tasks"), Segment::Index(0)];
+        ops.push(site0.put(&task, "done", json!(false)).unwrap());
+        for op in ops {
+            site1.apply_remote(op).unwrap();
+        }
+
+        // Both edit the title and the task list at once
+        let edits0 = vec![
+            site0.insert_text(&[key("title")], 4, "s").unwrap(),
+            site0.put(&task, "done", json!(true)).unwrap(),
+        ];
+        let edits1 = vec![
+            site1.insert_text(&[key("title")], 0, "Big ").unwrap(),
+            site1
+                .insert(&[key("tasks")], 1, json!("write tests"))
+                .unwrap(),
+            site1.put(&[], "tags", json!(["draft"])).unwrap(),
+        ];
+        for op in edits1 {
+            site0.apply_remote(op).unwrap();
+        }
+        for op in edits0 {
+            site1.apply_remote(op).unwrap();
+        }
+
+        let expected = json!({
+            "title": "Big Plans",
+            "tasks": [{"done": true}, "write tests"],
+            "tags": ["draft"],
+        });
+        assert_eq!(site0.to_json(), expected);
+        assert_eq!(site1.to_json(), expected);
+        assert_eq!(site1.get(&task), Some(json!({"done": true})));
+    }
+
+    #[test]
+    fn test_ops_wait_for_their_object() {
+        let mut site0 = JsonDoc::new(0, 2);
+        let mut site1 = JsonDoc::new(1, 2);
+
+        let create = site0.put_object(&[], "cells", ObjectKind::List).unwrap();
+        let cell = site0
+            .insert_object(&[key("cells")], 0, ObjectKind::Text)
+            .unwrap();
+        let source = site0
+            .insert_text(&[key("cells"), Segment::Index(0)], 0, "print(1)")
+            .unwrap();
+
+        // Delivered in reverse: each op waits for the one creating its object
+        site1.apply_remote(source).unwrap();
+        site1.apply_remote(cell).unwrap();
+        assert_eq!(site1.pending_count(), 2);
+        site1.apply_remote(create).unwrap();
+
+        assert_eq!(site1.pending_count(), 0);
+        assert_eq!(site1.to_json(), json!({"cells": ["print(1)"]}));
+        assert_eq!(site1.to_json(), site0.to_json());
+    }
+
+    #[test]
+    fn test_rejects_invalid_paths() {
+        let mut doc = JsonDoc::new(0, 1);
+        doc.put(&[], "count", json!(1)).unwrap();
+        doc.put_object(&[], "body", ObjectKind::Text).unwrap();
+
+        assert_eq!(
+            doc.insert_text(&[key("count")], 0, "x").unwrap_err(),
+            JsonError::PathNotFound(vec![key("count")])
+        );
+        assert_eq!(
+            doc.insert(&[key("body")], 0, json!(1)).unwrap_err(),
+            JsonError::WrongKind {
+                expected: ObjectKind::List,
+                found: ObjectKind::Text
+            }
+        );
+        assert_eq!(
+            doc.delete(&[key("body")], 0, 1).unwrap_err(),
+            JsonError::IndexOutOfRange { index: 0, len: 0 }
+        );
+        assert!(matches!(
+            doc.remove(&[], "missing"),
+            Err(JsonError::PathNotFound(_))
+        ));
+        assert_eq!(doc.to_json(), json!({"count": 1, "body": ""}));
+
+        // Inserting past the end is an error, not an insert at the start
+        doc.insert_text(&[key("body")], 0, "abc").unwrap();
+        assert_eq!(
+            doc.insert_text(&[key("body")], 50, "Z").unwrap_err(),
+            JsonError::IndexOutOfRange { index: 50, len: 3 }
+        );
+        doc.put_object(&[], "items", ObjectKind::List).unwrap();
+        assert_eq!(
+            doc.insert(&[key("items")], 1, json!(1)).unwrap_err(),
+            JsonError::IndexOutOfRange { index: 1, len: 0 }
+        );
+        doc.insert_text(&[key("body")], 3, "Z").unwrap();
+        assert_eq!(
+            doc.to_json(),
+            json!({"count": 1, "body": "abcZ", "items": []})
+        );
+    }
+}
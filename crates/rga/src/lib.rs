@@ -1,10 +1,49 @@
 // Replicated Growable Array (RGA) Implementation
 // Based on "Replicated Abstract Data Types: Building Blocks for Collaborative Applications"
 // by Roh et al., 2011
+//
+// The same paper's Replicated Fixed-size Array and Replicated Hash Table are
+// provided as `Rfa` and `Rht`, for data that needs no ordering, and `JsonDoc`
+// composes all of them into a nested document
 
+pub mod anchor;
+mod batch;
+pub mod blame;
+pub mod change;
+pub mod compact_clock;
+mod diff;
+pub mod json;
+pub mod marks;
+mod merge;
+mod moves;
 pub mod node;
+mod op_log;
+mod position_index;
 pub mod remote_op;
+pub mod rfa;
 pub mod rga;
+pub mod rht;
 pub mod s4vector;
+mod session;
+mod site_clock;
+pub mod snapshot;
+mod time_travel;
+mod undo;
+mod validation;
 
-pub use {node::Node, remote_op::RemoteOp, rga::Rga, s4vector::S4Vector};
+pub use {
+    anchor::{Anchor, Gravity},
+    blame::AuthorSpan,
+    change::Change,
+    compact_clock::CompactClock,
+    json::{Entry, JsonDoc, JsonError, JsonOp, ObjectId, ObjectKind, ObjectOp, Segment},
+    marks::{Expand, FormatSpan, MarkOp, Marks},
+    node::Node,
+    remote_op::RemoteOp,
+    rfa::{Rfa, RfaOp},
+    rga::Rga,
+    rht::{Rht, RhtOp},
+    s4vector::S4Vector,
+    snapshot::SnapshotError,
+    validation::ApplyError,
+};
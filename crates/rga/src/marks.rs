@@ -0,0 +1,369 @@
+// Rich-text formatting marks anchored to RGA elements
+//
+// A mark sets one attribute (bold, italic, link, heading or any custom key)
+// over a range given by two anchors, so it follows its text through any
+// concurrent edit. The gravity of each anchor decides whether text typed
+// exactly at that boundary joins the mark (see `Expand`).
+//
+// Marks are never edited or removed: clearing an attribute is a mark whose
+// value is null. Where marks of one key overlap, the one with the greatest
+// S4Vector wins, so marks made later (causally) override earlier ones and
+// concurrent ones are resolved the same way on every replica. Marks live in
+// a replica of their own next to the text; one whose anchors have not
+// arrived yet is simply not shown until they do.
+
+use crate::anchor::{Anchor, Gravity};
+use crate::rga::Rga;
+use crate::s4vector::S4Vector;
+use crate::site_clock::SiteClock;
+use crate::validation::{self, ApplyError};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+/// Which boundaries of a mark take in text inserted exactly at them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Expand {
+    None,
+    Before,
+    After,
+    Both,
+}
+
+impl Expand {
+    /// Usual behaviour for `key`: links keep their extent, while other
+    /// formatting grows as the writer keeps typing at its end
+    pub fn default_for(key: &str) -> Self {
+        match key {
+            "link" => Expand::None,
+            _ => Expand::After,
+        }
+    }
+
+    fn start_gravity(self) -> Gravity {
+        match self {
+            Expand::Before | Expand::Both => Gravity::Left,
+            Expand::None | Expand::After => Gravity::Right,
+        }
+    }
+
+    fn end_gravity(self) -> Gravity {
+        match self {
+            Expand::After | Expand::Both => Gravity::Right,
+            Expand::None | Expand::Before => Gravity::Left,
+        }
+    }
+}
+
+// Mark(start, end, key, value) - sets key to value between the anchors
+// A null value clears the attribute
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MarkOp {
+    pub start: Anchor,
+    pub end: Anchor,
+    pub key: String,
+    pub value: serde_json::Value,
+    pub expand: Expand,
+    pub s4v: S4Vector,
+    #[serde(with = "crate::compact_clock::sparse_clock")]
+    pub vector_clock: Vec<u32>,
+}
+
+/// Range of visible elements sharing the same formatting
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FormatSpan {
+    pub index: usize,
+    pub len: usize,
+
+    /// Attributes set over the whole range (cleared ones are left out)
+    pub attributes: BTreeMap<String, serde_json::Value>,
+}
+
+/// Formatting marks of one `Rga`, replicated alongside it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Marks {
+    // Every mark received, sorted by S4Vector
+    ops: Vec<MarkOp>,
+    clock: SiteClock,
+}
+
+impl Marks {
+    // Create an empty set of marks for the given site
+    pub fn new(site_id: u32, num_sites: usize) -> Self {
+        Marks {
+            ops: Vec::new(),
+            clock: SiteClock::new(site_id, num_sites),
+        }
+    }
+
+    /// Every mark, oldest first; applying them to another replica (in any
+    /// order) reproduces this formatting
+    pub fn ops(&self) -> &[MarkOp] {
+        &self.ops
+    }
+
+    /// Local Mark operation over the visible range `range` of `rga`
+    /// Returns None if the range is empty or past the end of the document
+    pub fn mark_local<T: Clone>(
+        &mut self,
+        rga: &Rga<T>,
+        range: Range<usize>,
+        key: &str,
+        value: serde_json::Value,
+        expand: Expand,
+    ) -> Option<MarkOp> {
+        if range.is_empty() {
+            return None;
+        }
+        let start = rga.anchor_at(range.start, expand.start_gravity())?;
+        let end = rga.anchor_at(range.end, expand.end_gravity())?;
+
+        let op = MarkOp {
+            start,
+            end,
+            key: key.to_string(),
+            value,
+            expand,
+            s4v: self.clock.tick()?,
+            vector_clock: self.clock.vector_clock.clone(),
+        };
+        self.insert(op.clone());
+        Some(op)
+    }
+
+    /// Apply a mark received from another site
+    /// Returns false if it was already applied
+    pub fn apply_remote(&mut self, op: MarkOp) -> Result<bool, ApplyError> {
+        validation::check_stamp(op.s4v, &op.vector_clock)?;
+        self.clock.merge(&op.vector_clock);
+        Ok(self.insert(op))
+    }
+
+    /// Formatting of the visible document of `rga`, as consecutive spans
+    /// covering all of it
+    pub fn format_spans<T: Clone>(&self, rga: &Rga<T>) -> Vec<FormatSpan> {
+        let len = rga.len();
+
+        // Current extent of every mark whose anchors are known here
+        let resolved: Vec<(usize, usize, &MarkOp)> = self
+            .ops
+            .iter()
+            .filter_map(|op| {
+                let start = rga.resolve_anchor(&op.start)?;
+                let end = rga.resolve_anchor(&op.end)?;
+                (start < end).then_some((start, end, op))
+            })
+            .collect();
+
+        let mut bounds: Vec<usize> = resolved
+            .iter()
+            .flat_map(|&(start, end, _)| [start, end])
+            .chain([0, len])
+            .collect();
+        bounds.sort_unstable();
+        bounds.dedup();
+
+        let mut spans: Vec<FormatSpan> = Vec::new();
+        for window in bounds.windows(2) {
+            let (from, to) = (window[0], window[1]);
+
+            // Marks are sorted, so the greatest S4Vector is written last
+            let mut attributes = BTreeMap::new();
+            for &(_, _, op) in resolved
+                .iter()
+                .filter(|&&(start, end, _)| start <= from && to <= end)
+            {
+                attributes.insert(op.key.clone(), op.value.clone());
+            }
+            attributes.retain(|_, value| !value.is_null());
+
+            match spans.last_mut() {
+                Some(span) if span.attributes == attributes => span.len += to - from,
+                _ => spans.push(FormatSpan {
+                    index: from,
+                    len: to - from,
+                    attributes,
+                }),
+            }
+        }
+
+        spans
+    }
+
+    /// Start the next session of `rga` (see `Rga::start_session`), moving
+    /// every mark onto the renumbered text at the same place
+    ///
+    /// Marks whose anchors were not known here are dropped, since they can
+    /// no longer be resolved. Returns the new session number.
+    pub fn start_session<T: Clone>(
+        &mut self,
+        rga: &mut Rga<T>,
+        checkpoint: &[u32],
+    ) -> Result<u32, ApplyError> {
+        let extents: Vec<Option<(usize, usize)>> = self
+            .ops
+            .iter()
+            .map(|op| Some((rga.resolve_anchor(&op.start)?, rga.resolve_anchor(&op.end)?)))
+            .collect();
+        let session = rga.start_session(checkpoint)?;
+
+        // The visible text is unchanged, so every index is still in range
+        let ops = std::mem::take(&mut self.ops);
+        for (mut op, extent) in ops.into_iter().zip(extents) {
+            let Some((start, end)) = extent else {
+                continue;
+            };
+            let (Some(start), Some(end)) = (
+                rga.anchor_at(start, op.start.gravity),
+                rga.anchor_at(end, op.end.gravity),
+            ) else {
+                continue;
+            };
+            op.start = start;
+            op.end = end;
+            self.ops.push(op);
+        }
+        Ok(session)
+    }
+
+    // Keep `op` in S4Vector order; false if it is already here
+    fn insert(&mut self, op: MarkOp) -> bool {
+        match self.ops.binary_search_by(|known| known.s4v.cmp(&op.s4v)) {
+            Ok(_) => false,
+            Err(position) => {
+                self.ops.insert(position, op);
+                true
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Expand, Marks, Rga};
+    use serde_json::{json, Value};
+
+    fn replica(site_id: u32, text: &str) -> Rga<char> {
+        let mut rga = Rga::new(site_id, 2);
+        rga.insert_run_local(0, text.chars().collect());
+        rga
+    }
+
+    // (text, attribute value) of each maximal range with `key` set
+    fn marked(rga: &Rga<char>, marks: &Marks, key: &str) -> Vec<(String, Value)> {
+        let text = rga.read();
+        let mut ranges: Vec<(usize, usize, Value)> = Vec::new();
+        for span in marks.format_spans(rga) {
+            let value = span.attributes.get(key).cloned();
+            match (ranges.last_mut(), value) {
+                (Some((_, end, last)), Some(value)) if *end == span.index && *last == value => {
+                    *end += span.len;
+                }
+                (_, Some(value)) => ranges.push((span.index, span.index + span.len, value)),
+                (_, None) => {}
+            }
+        }
+        ranges
+            .into_iter()
+            .map(|(start, end, value)| (text[start..end].iter().collect(), value))
+            .collect()
+    }
+
+    #[test]
+    fn test_expand_at_boundaries() {
+        let mut rga = replica(0, "see docs here");
+        let mut marks = Marks::new(0, 1);
+
+        marks.mark_local(&rga, 0..3, "bold", json!(true), Expand::default_for("bold"));
+        let link = json!("https://example.com");
+        marks.mark_local(
+            &rga,
+            4..8,
+            "link",
+            link.clone(),
+            Expand::default_for("link"),
+        );
+
+        // Typing at the end of each mark and at the start of the bold one
+        rga.insert_local(8, '!');
+        rga.insert_local(3, 'n');
+        rga.insert_local(0, '>');
+
+        assert_eq!(rga.read().iter().collect::<String>(), ">seen docs! here");
+        assert_eq!(
+            marked(&rga, &marks, "bold"),
+            vec![("seen".to_string(), json!(true))]
+        );
+        assert_eq!(
+            marked(&rga, &marks, "link"),
+            vec![("docs".to_string(), link)]
+        );
+    }
+
+    #[test]
+    fn test_concurrent_marks_converge() {
+        let text0 = replica(0, "hello world");
+        let mut text1 = Rga::<char>::new(1, 2);
+        for op in text0.ops_since(&[]).unwrap() {
+            text1.apply_remote(op).unwrap();
+        }
+        let mut marks0 = Marks::new(0, 2);
+        let mut marks1 = Marks::new(1, 2);
+
+        // Site 0 bolds everything while site 1 clears bold on "world" and
+        // makes "hello" a heading
+        let bold = marks0
+            .mark_local(&text0, 0..11, "bold", json!(true), Expand::After)
+            .unwrap();
+        let unbold = marks1
+            .mark_local(&text1, 6..11, "bold", Value::Null, Expand::After)
+            .unwrap();
+        let heading = marks1
+            .mark_local(&text1, 0..5, "heading", json!(1), Expand::None)
+            .unwrap();
+
+        assert!(marks0.apply_remote(unbold.clone()).unwrap());
+        assert!(marks0.apply_remote(heading).unwrap());
+        assert!(marks1.apply_remote(bold).unwrap());
+        assert!(!marks1.apply_remote(unbold).unwrap());
+
+        // The later clear (same sum, higher site) wins on both replicas
+        let spans = marks0.format_spans(&text0);
+        assert_eq!(spans, marks1.format_spans(&text1));
+        assert_eq!(
+            marked(&text0, &marks0, "bold"),
+            vec![("hello ".to_string(), json!(true))]
+        );
+        assert_eq!(spans[0].attributes.get("heading"), Some(&json!(1)));
+        assert_eq!(spans.iter().map(|span| span.len).sum::<usize>(), 11);
+    }
+
+    #[test]
+    fn test_mark_waits_for_its_text() {
+        let mut text0 = replica(0, "ab");
+        let mut text1 = Rga::<char>::new(1, 2);
+        let mut marks0 = Marks::new(0, 2);
+        let mut marks1 = Marks::new(1, 2);
+
+        let italic = marks0
+            .mark_local(&text0, 1..2, "italic", json!(true), Expand::None)
+            .unwrap();
+        marks1.apply_remote(italic).unwrap();
+        assert_eq!(marks1.format_spans(&text1), Vec::new());
+
+        for op in text0.ops_since(&[]).unwrap() {
+            text1.apply_remote(op).unwrap();
+        }
+        assert_eq!(
+            marked(&text1, &marks1, "italic"),
+            vec![("b".to_string(), json!(true))]
+        );
+        assert!(marks0
+            .mark_local(&text0, 2..2, "bold", json!(true), Expand::After)
+            .is_none());
+        assert!(text0.insert_local(2, 'c').is_some());
+        assert!(marks0
+            .mark_local(&text0, 0..4, "bold", json!(true), Expand::After)
+            .is_none());
+    }
+}
@@ -0,0 +1,303 @@
+// State-based merge of two Rga replicas
+//
+// Every node carries enough to replay what created and changed it: its
+// insert S4Vectors, its original values and (see `node::History`) the
+// S4Vectors of every Delete and Update it has seen. Merging walks the other
+// replica's list and, for each element this replica lacks, inserts it with
+// the closest preceding element both replicas share as left cobject. All
+// elements between an element's real left cobject and itself succeed it, so
+// the RGA scan places it exactly where the original Insert would have; Delete
+// and Update stamps are then applied with the usual precedence rules. Moves
+// are merged before the stamps, so that a stamp left by a Move is not taken
+// for a Delete of the element it moved.
+
+use crate::change::Change;
+use crate::node::Node;
+use crate::rga::Rga;
+use crate::s4vector::S4Vector;
+use crate::validation::ApplyError;
+
+impl<T: Clone> Rga<T> {
+    /// Bring this replica up to date with the full state of `other`
+    ///
+    /// Takes every element, tombstone, Update, clock entry and buffered op
+    /// `other` has, without needing the operations that produced them, so a
+    /// replica edited offline can be reconciled with another copy (or two
+    /// snapshots combined). Merging is commutative and idempotent: replicas
+    /// that merge each other end up identical to replicas that exchanged
+    /// every operation.
+    ///
+    /// Elements this replica has already purged stay purged. Replicas of
+    /// different sessions cannot be merged. Returns the resulting changes
+    /// to the visible document, which the observer (if set) also sees.
+    pub fn merge(&mut self, other: &Rga<T>) -> Result<Vec<Change<T>>, ApplyError> {
+        if other.session != self.session {
+            return Err(ApplyError::WrongSession {
+                expected: self.session,
+                found: other.session,
+            });
+        }
+        let mut changes = Vec::new();
+
+        // Last element of `other`'s list that this replica has
+        let mut prev: Option<S4Vector> = None;
+        let mut current = other.head;
+        while let Some(slot) = current {
+            let node = &other.nodes[slot];
+            current = node.link;
+            prev = self.merge_elements(node, prev, &mut changes)?;
+        }
+
+        let moved = self.merge_moves(other);
+        let mut current = other.head;
+        while let Some(slot) = current {
+            let node = &other.nodes[slot];
+            current = node.link;
+            self.merge_stamps(node, &mut changes)?;
+        }
+        changes.extend(self.settle_moves(&moved)?);
+        self.replay_updates(&moved, &mut changes)?;
+
+        // Ops `other` integrated but this replica has not, kept for anti-entropy
+        for op in &other.log.ops {
+            if !self.has_applied(op) {
+                self.log.push(op.clone());
+            }
+        }
+
+        self.grow_vector_clock(other.vector_clock.len());
+        for (count, &other_count) in self.vector_clock.iter_mut().zip(&other.vector_clock) {
+            *count = (*count).max(other_count);
+        }
+        self.acknowledge(other.site_id, &other.vector_clock);
+        for (&site, clock) in &other.site_clocks {
+            self.acknowledge(site, clock);
+        }
+        for (site, first, last) in other.applied.ranges() {
+            self.applied.insert(site, first, last);
+        }
+
+        // The merged clock may release buffered ops, here or from `other`
+        let mut ready: Vec<_> = self.pending.drain().flat_map(|(_, ops)| ops).collect();
+        ready.extend(
+            other
+                .pending_ops()
+                .filter(|op| self.validate(op).is_ok())
+                .cloned(),
+        );
+        self.integrate_ready(ready, &mut changes)?;
+
+        self.notify(&changes);
+        Ok(changes)
+    }
+
+    // Insert the elements of `node` this replica lacks, each after the
+    // closest preceding element already here (`prev`)
+    // Returns the last element of `node` this replica now has
+    fn merge_elements(
+        &mut self,
+        node: &Node<T>,
+        mut prev: Option<S4Vector>,
+        changes: &mut Vec<Change<T>>,
+    ) -> Result<Option<S4Vector>, ApplyError> {
+        let Some(values) = node.inserted_values() else {
+            return Ok(prev);
+        };
+
+        let mut k = 0;
+        while k < node.len {
+            let id = node.element_id(k);
+            if let Some((slot, offset)) = self.find_by_s4vector(&id) {
+                // Skip everything both runs share
+                k += (node.len - k).min(self.nodes[slot].len - offset);
+                prev = Some(node.element_id(k - 1));
+                continue;
+            }
+
+            // Purged here: the delete is stable, so it must not come back
+            if self.applied.count(id.sid, id.seq, id.seq) == 1 {
+                k += 1;
+                continue;
+            }
+
+            let mut end = k + 1;
+            while end < node.len && !self.knows_element(&node.element_id(end)) {
+                end += 1;
+            }
+
+            let run = values[k as usize..end as usize].to_vec();
+            let index = self.remote_insert(prev, run.clone(), id)?;
+            match (index, node.history.as_ref()) {
+                // Deleted there: insert it already tombstoned, which the
+                // visible document never notices
+                (_, Some(history)) if node.is_tombstone() => {
+                    if let Some(&stamp) = history.deletes.first() {
+                        self.delete_elements(&[(id, end - k)], stamp)?;
+                    }
+                }
+                (Some(index), _) => changes.push(Change::Insert { index, values: run }),
+                (None, _) => {}
+            }
+
+            prev = Some(node.element_id(end - 1));
+            k = end;
+        }
+
+        Ok(prev)
+    }
+
+    // Apply the Deletes and Updates `node` has seen to this replica's copy
+    fn merge_stamps(
+        &mut self,
+        node: &Node<T>,
+        changes: &mut Vec<Change<T>>,
+    ) -> Result<(), ApplyError> {
+        let Some(history) = &node.history else {
+            return Ok(());
+        };
+
+        // Elements purged here are already deleted for good
+        let present: Vec<(S4Vector, u32)> = (0..node.len)
+            .map(|k| node.element_id(k))
+            .filter(|id| self.find_by_s4vector(id).is_some())
+            .fold(Vec::new(), |mut spans, id| {
+                crate::remote_op::push_span(&mut spans, id);
+                spans
+            });
+        if present.is_empty() {
+            return Ok(());
+        }
+
+        for &stamp in &history.deletes {
+            changes.extend(self.remote_delete(&present, stamp)?);
+        }
+        for (stamp, value) in &history.updates {
+            if let Some(index) = self.remote_update(node.s_k, value.clone(), *stamp)? {
+                changes.push(Change::Update {
+                    index,
+                    value: value.clone(),
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    // Whether this replica has, or had and purged, element `id`
+    fn knows_element(&self, id: &S4Vector) -> bool {
+        self.find_by_s4vector(id).is_some() || self.applied.count(id.sid, id.seq, id.seq) == 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Change, Rga};
+
+    // Two replicas that share "hello world" and then edit concurrently
+    fn diverged() -> (Rga<char>, Rga<char>) {
+        let mut server = Rga::<char>::new(0, 2);
+        let mut laptop = Rga::<char>::new(1, 2);
+        laptop
+            .apply_remote(
+                server
+                    .insert_run_local(0, "hello world".chars().collect())
+                    .unwrap(),
+            )
+            .unwrap();
+
+        server.delete_range_local(0, 6);
+        server.insert_run_local(0, "goodbye ".chars().collect());
+        server.update_local(8, 'W');
+
+        laptop.insert_run_local(5, ", dear".chars().collect());
+        laptop.update_local(0, 'H');
+        laptop.delete_range_local(13, 4);
+        laptop.update_local(11, 'W');
+
+        (server, laptop)
+    }
+
+    #[test]
+    fn test_merge_matches_op_exchange() {
+        let (server, laptop) = diverged();
+
+        let mut expected = server.clone();
+        for op in laptop.ops_since(server.vector_clock()).unwrap() {
+            expected.apply_remote(op).unwrap();
+        }
+
+        let mut merged = server.clone();
+        merged.merge(&laptop).unwrap();
+        assert_eq!(merged.read(), expected.read());
+        assert_eq!(merged.vector_clock(), expected.vector_clock());
+
+        let mut other_way = laptop.clone();
+        other_way.merge(&server).unwrap();
+        assert_eq!(other_way.read(), expected.read());
+
+        // Merged replicas keep accepting each other's ops
+        let op = merged.insert_local(0, '>').unwrap();
+        other_way.apply_remote(op).unwrap();
+        assert_eq!(other_way.read(), merged.read());
+    }
+
+    #[test]
+    fn test_merge_is_idempotent() {
+        let (mut server, laptop) = diverged();
+
+        server.merge(&laptop).unwrap();
+        let (read, nodes) = (server.read(), server.node_count());
+
+        assert_eq!(server.merge(&laptop).unwrap(), Vec::new());
+        let itself = server.clone();
+        assert_eq!(server.merge(&itself).unwrap(), Vec::new());
+        assert_eq!(server.read(), read);
+        assert_eq!(server.node_count(), nodes);
+
+        // Ops already covered by the merge are recognised as duplicates
+        for op in laptop.ops_since(&[]).unwrap() {
+            assert_eq!(server.apply_remote(op).unwrap(), Vec::new());
+        }
+        assert_eq!(server.read(), read);
+    }
+
+    #[test]
+    fn test_merge_changes_patch_a_mirror() {
+        let (mut server, laptop) = diverged();
+        let mut mirror = server.read();
+
+        for change in server.merge(&laptop).unwrap() {
+            match change {
+                Change::Insert { index, values } => {
+                    mirror.splice(index..index, values);
+                }
+                Change::Delete { index, len } => {
+                    mirror.drain(index..index + len);
+                }
+                Change::Update { index, value } => mirror[index] = value,
+            }
+        }
+        assert_eq!(mirror, server.read());
+    }
+
+    #[test]
+    fn test_merge_snapshots_with_history() {
+        let (server, laptop) = diverged();
+        let before = server.vector_clock().to_vec();
+
+        let mut merged = Rga::<char>::from_bytes(&server.to_bytes().unwrap()).unwrap();
+        merged
+            .merge(&Rga::from_bytes(&laptop.to_bytes().unwrap()).unwrap())
+            .unwrap();
+
+        // Time travel still sees the server's own past
+        assert_eq!(
+            merged.read_at(merged.session(), &before),
+            Some(server.read())
+        );
+        assert_eq!(
+            merged.read_at(merged.session(), merged.vector_clock()),
+            Some(merged.read())
+        );
+    }
+}
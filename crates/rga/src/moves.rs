@@ -0,0 +1,593 @@
+// Move operation for Rga
+//
+// RGA orders elements by their insert S4Vector, so an element cannot change
+// place without changing identifier. A Move therefore inserts each element
+// it relocates again at the destination, under the Move's own S4Vectors, and
+// tombstones the old location. The move table remembers which element every
+// such location holds: Deletes and Updates addressed to any location of an
+// element apply to the element, and a Move made after seeing the new
+// location moves the element again.
+//
+// Concurrent moves of one element are resolved like concurrent Updates: the
+// location with the greatest S4Vector wins and every other location is a
+// tombstone, stamped with the Moves that superseded it and the Deletes of
+// the element. A location ends up with the same stamps whatever order the
+// operations arrived in, so replicas converge, and `read_at` finds the
+// element wherever it was at the given clock.
+
+use crate::change::Change;
+use crate::remote_op::{self, RemoteOp};
+use crate::rga::Rga;
+use crate::s4vector::S4Vector;
+use crate::undo::LocalEdit;
+use crate::validation::ApplyError;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+// Every location a moved element has had, and the Deletes that targeted it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Locations {
+    // The element's own insert ID, i.e. its first location
+    element: S4Vector,
+
+    // (location, S4Vector of the Move that created it) for every later
+    // location, in precedence order
+    moved: Vec<(S4Vector, S4Vector)>,
+
+    deletes: Vec<S4Vector>,
+}
+
+impl Locations {
+    // Where the element currently is (or was last, if deleted)
+    fn current(&self) -> S4Vector {
+        self.moved
+            .last()
+            .map_or(self.element, |&(location, _)| location)
+    }
+
+    // Every location, oldest first
+    fn all(&self) -> impl Iterator<Item = S4Vector> + '_ {
+        std::iter::once(self.element).chain(self.moved.iter().map(|&(location, _)| location))
+    }
+
+    // Stamps the location `i` of `all` must carry: the Moves that
+    // superseded it and every Delete of the element
+    fn stamps(&self, i: usize) -> impl Iterator<Item = S4Vector> + '_ {
+        self.moved[i..]
+            .iter()
+            .map(|&(_, mover)| mover)
+            .chain(self.deletes.iter().copied())
+    }
+
+    fn place(&mut self, location: S4Vector, mover: S4Vector) {
+        if let Err(position) = self
+            .moved
+            .binary_search_by(|(known, _)| known.cmp(&location))
+        {
+            self.moved.insert(position, (location, mover));
+        }
+    }
+
+    // Record a Delete; stamps of Moves are not Deletes of the element
+    fn add_delete(&mut self, s4v: S4Vector) {
+        let is_mover = self.moved.iter().any(|(_, mover)| *mover == s4v);
+        if !is_mover && !self.deletes.contains(&s4v) {
+            self.deletes.push(s4v);
+        }
+    }
+}
+
+// Locations of every element moved on this replica
+#[derive(Debug, Clone, Default)]
+pub(crate) struct MoveTable {
+    // Moved elements, by their own insert ID
+    elements: HashMap<S4Vector, Locations>,
+
+    // Element held by each location a Move created
+    origins: HashMap<S4Vector, S4Vector>,
+}
+
+impl MoveTable {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.elements.is_empty()
+    }
+
+    // Element held at location `id`
+    pub(crate) fn element_of(&self, id: &S4Vector) -> S4Vector {
+        self.origins.get(id).copied().unwrap_or(*id)
+    }
+
+    // Current location of the element held at `id`
+    pub(crate) fn current_location(&self, id: &S4Vector) -> S4Vector {
+        let element = self.element_of(id);
+        self.elements
+            .get(&element)
+            .map_or(element, Locations::current)
+    }
+
+    // Record the Delete `s4v` of `element` if it was ever moved
+    // Returns false for elements that never moved
+    pub(crate) fn record_delete(&mut self, element: &S4Vector, s4v: S4Vector) -> bool {
+        match self.elements.get_mut(element) {
+            Some(locations) => {
+                locations.add_delete(s4v);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Record that Move `mover` placed `element` at `location`
+    // `deletes` are Deletes the element has seen, which may include stamps
+    // of Moves of it that are now known
+    fn place(
+        &mut self,
+        element: S4Vector,
+        location: S4Vector,
+        mover: S4Vector,
+        deletes: &[S4Vector],
+    ) {
+        self.origins.insert(location, element);
+        let locations = self.elements.entry(element).or_insert_with(|| Locations {
+            element,
+            moved: Vec::new(),
+            deletes: Vec::new(),
+        });
+        locations.place(location, mover);
+        for &s4v in deletes {
+            locations.add_delete(s4v);
+        }
+    }
+
+    // Take in what another replica knows about one element, which has seen
+    // `deletes` here
+    fn merge(&mut self, other: &Locations, deletes: &[S4Vector]) {
+        for &(location, mover) in &other.moved {
+            self.place(other.element, location, mover, &[]);
+        }
+        if let Some(locations) = self.elements.get_mut(&other.element) {
+            for &s4v in deletes.iter().chain(&other.deletes) {
+                locations.add_delete(s4v);
+            }
+        }
+    }
+
+    // Moved elements sorted by insert ID, so identical tables encode identically
+    pub(crate) fn to_entries(&self) -> Vec<Locations> {
+        let mut entries: Vec<Locations> = self.elements.values().cloned().collect();
+        entries.sort_unstable_by_key(|locations| locations.element);
+        entries
+    }
+
+    pub(crate) fn from_entries(entries: Vec<Locations>) -> Self {
+        let mut table = MoveTable::default();
+        for locations in entries {
+            for &(location, _) in &locations.moved {
+                table.origins.insert(location, locations.element);
+            }
+            table.elements.insert(locations.element, locations);
+        }
+        table
+    }
+}
+
+impl<T: Clone> Rga<T> {
+    /// Move `len` visible elements starting at `index` to visible index
+    /// `dest`, counted before the move (`len()` moves them to the end)
+    ///
+    /// The elements keep their identity: concurrent Deletes and Updates of
+    /// them, as well as anchors to them, follow them to the new place. If
+    /// several sites move the same text concurrently, every replica keeps
+    /// the move with the greatest S4Vector. A `dest` within the range
+    /// leaves the text as it is, but is still a Move like any other. Undo
+    /// moves the elements back. Returns None if the range is empty or out
+    /// of bounds.
+    pub fn move_range_local(
+        &mut self,
+        index: usize,
+        len: usize,
+        dest: usize,
+    ) -> Option<RemoteOp<T>> {
+        let end = index.checked_add(len)?;
+        if len == 0 || end > self.len() || dest > self.len() {
+            return None;
+        }
+        let dest = if (index..=end).contains(&dest) {
+            index
+        } else {
+            dest
+        };
+
+        let targets = self.visible_ids(index, len)?;
+        let left_id = match dest.checked_sub(1) {
+            Some(left) => Some(self.id_at(left)?),
+            None => None,
+        };
+
+        let (op, edit) = self.generate_move(&targets, left_id)?;
+        self.record_local(edit);
+        Some(op)
+    }
+
+    // Move the visible elements `targets`, in document order, to just after
+    // `left_id`, which must not be one of them
+    // Returns the op plus the edit that would move them back
+    pub(crate) fn generate_move(
+        &mut self,
+        targets: &[S4Vector],
+        left_id: Option<S4Vector>,
+    ) -> Option<(RemoteOp<T>, LocalEdit<T>)> {
+        let first = self.index_of(targets.first()?)?;
+        let origin = match first.checked_sub(1) {
+            Some(left) => Some(self.id_at(left)?),
+            None => None,
+        };
+        let elements: Vec<S4Vector> = targets.iter().map(|id| self.moves.element_of(id)).collect();
+
+        let mut spans = Vec::new();
+        for &id in targets {
+            remote_op::push_span(&mut spans, id);
+        }
+        let s4v = self.generate_run_s4vector(u32::try_from(targets.len()).ok()?)?;
+        self.remote_move(&spans, left_id, s4v).ok()?;
+
+        let op = RemoteOp::Move {
+            spans,
+            left_id,
+            s4v,
+            vector_clock: self.vector_clock.clone(),
+        };
+        self.record_op(&op);
+        Some((
+            op,
+            LocalEdit::Move {
+                elements,
+                s4v,
+                origin,
+            },
+        ))
+    }
+
+    // Inverse of our Move `s4v` of `elements`: move those it still holds
+    // in place back to just after `origin`, or where it stood if deleted
+    // None if other sites have since moved or deleted all of them
+    pub(crate) fn invert_move(
+        &mut self,
+        elements: &[S4Vector],
+        s4v: S4Vector,
+        origin: Option<S4Vector>,
+    ) -> Option<(RemoteOp<T>, LocalEdit<T>)> {
+        let mut visible: Vec<(usize, S4Vector)> = elements
+            .iter()
+            .zip((0..).map(|k| s4v.offset(k)))
+            .filter(|(element, location)| self.moves.current_location(element) == *location)
+            .filter_map(|(_, location)| Some((self.index_of(&location)?, location)))
+            .collect();
+        visible.sort_unstable_by_key(|(index, _)| *index);
+        let targets: Vec<S4Vector> = visible.into_iter().map(|(_, id)| id).collect();
+
+        // Visible elements up to the origin, skipping our own
+        let mut dest = match origin {
+            Some(origin) => match self.element_position(&self.moves.current_location(&origin))? {
+                (index, true) => index + 1,
+                (index, false) => index,
+            },
+            None => 0,
+        };
+        while let Some(left) = dest.checked_sub(1).and_then(|left| self.id_at(left)) {
+            if !targets.contains(&left) {
+                break;
+            }
+            dest -= 1;
+        }
+        let left_id = match dest.checked_sub(1) {
+            Some(left) => Some(self.id_at(left)?),
+            None => None,
+        };
+
+        self.generate_move(&targets, left_id)
+    }
+
+    // Remote Move operation
+    // Element k of `spans` is placed after `left_id` under s4v.offset(k)
+    // Returns the visible changes: old locations vacated, then the elements
+    // that appeared at the destination
+    pub(crate) fn remote_move(
+        &mut self,
+        spans: &[(S4Vector, u32)],
+        left_id: Option<S4Vector>,
+        s4v: S4Vector,
+    ) -> Result<Vec<Change<T>>, ApplyError> {
+        // Element held by each target, and its value as first inserted
+        let mut elements = Vec::new();
+        let mut values = Vec::new();
+        for id in remote_op::span_elements(spans) {
+            let (slot, offset) = self
+                .find_by_s4vector(&id)
+                .ok_or(ApplyError::UnknownElement(id))?;
+            let value = self.nodes[slot]
+                .inserted_values()
+                .and_then(|values| values.get(offset as usize))
+                .ok_or(ApplyError::UnknownElement(id))?;
+            values.push(value.clone());
+            elements.push(self.moves.element_of(&id));
+        }
+
+        for (k, &element) in elements.iter().enumerate() {
+            let deletes = self.deletes_of(&element);
+            self.moves
+                .place(element, s4v.offset(k as u32), s4v, &deletes);
+        }
+        let mut changes = self.settle_moves(&elements)?;
+
+        // Elements that lost to another Move, or were deleted, are
+        // tombstoned before anyone sees them; Updates made at the old
+        // location come along and are part of the values reported below
+        let count = values.len() as u32;
+        self.remote_insert(left_id, values, s4v)?;
+        self.settle_moves(&elements)?;
+        self.replay_updates(&elements, &mut Vec::new())?;
+
+        for k in 0..count {
+            let Some(index) = self.index_of(&s4v.offset(k)) else {
+                continue;
+            };
+            let Some(value) = self.get(index).cloned() else {
+                continue;
+            };
+            match changes.last_mut() {
+                Some(Change::Insert {
+                    index: first,
+                    values,
+                }) if *first + values.len() == index => values.push(value),
+                _ => changes.push(Change::Insert {
+                    index,
+                    values: vec![value],
+                }),
+            }
+        }
+
+        Ok(changes)
+    }
+
+    // Give every location of `elements` the stamps its element's Moves and
+    // Deletes call for, so only the current location of each is visible
+    // Returns the visible ranges removed, in order
+    pub(crate) fn settle_moves(
+        &mut self,
+        elements: &[S4Vector],
+    ) -> Result<Vec<Change<T>>, ApplyError> {
+        // Locations to tombstone, grouped by stamp so runs stay whole
+        let mut stamped: BTreeMap<S4Vector, Vec<(S4Vector, u32)>> = BTreeMap::new();
+        for element in elements {
+            let Some(locations) = self.moves.elements.get(element) else {
+                continue;
+            };
+            for (i, location) in locations.all().enumerate() {
+                if self.find_by_s4vector(&location).is_none() {
+                    continue;
+                }
+                for stamp in locations.stamps(i) {
+                    remote_op::push_span(stamped.entry(stamp).or_default(), location);
+                }
+            }
+        }
+
+        let mut changes = Vec::new();
+        for (stamp, spans) in stamped {
+            changes.extend(self.delete_elements(&spans, stamp)?);
+        }
+        Ok(changes)
+    }
+
+    // Apply the Updates recorded at older locations of `elements` to their
+    // current location, recording the visible ones in `changes`
+    pub(crate) fn replay_updates(
+        &mut self,
+        elements: &[S4Vector],
+        changes: &mut Vec<Change<T>>,
+    ) -> Result<(), ApplyError> {
+        for element in elements {
+            let Some(locations) = self.moves.elements.get(element) else {
+                continue;
+            };
+            let current = locations.current();
+            let older: Vec<S4Vector> = locations
+                .all()
+                .filter(|location| *location != current)
+                .collect();
+            if self.find_by_s4vector(&current).is_none() {
+                continue;
+            }
+
+            let mut updates = Vec::new();
+            for location in older {
+                let Some((slot, _)) = self.find_by_s4vector(&location) else {
+                    continue;
+                };
+                if let Some(history) = &self.nodes[slot].history {
+                    updates.extend(history.updates.iter().cloned());
+                }
+            }
+
+            for (stamp, value) in updates {
+                if let Some(index) = self.update_element(current, value.clone(), stamp)? {
+                    changes.push(Change::Update { index, value });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Take in the moves `other` knows of, before its stamps are merged
+    // Returns the elements whose locations may have changed
+    pub(crate) fn merge_moves(&mut self, other: &Rga<T>) -> Vec<S4Vector> {
+        for locations in other.moves.to_entries() {
+            let deletes = self.deletes_of(&locations.element);
+            self.moves.merge(&locations, &deletes);
+        }
+
+        let mut elements: Vec<S4Vector> = self.moves.elements.keys().copied().collect();
+        elements.sort_unstable();
+        elements
+    }
+
+    // Deletes seen by `element` while it has never moved here
+    fn deletes_of(&self, element: &S4Vector) -> Vec<S4Vector> {
+        if self.moves.elements.contains_key(element) {
+            return Vec::new();
+        }
+        self.find_by_s4vector(element)
+            .and_then(|(slot, _)| self.nodes[slot].history.as_ref())
+            .map(|history| history.deletes.clone())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{RemoteOp, Rga};
+
+    fn text(rga: &Rga<char>) -> String {
+        rga.read().into_iter().collect()
+    }
+
+    // Two replicas sharing `content`
+    fn pair(content: &str) -> (Rga<char>, Rga<char>) {
+        let mut site0 = Rga::new(0, 2);
+        let mut site1 = Rga::new(1, 2);
+        let op = site0
+            .insert_run_local(0, content.chars().collect())
+            .unwrap();
+        site1.apply_remote(op).unwrap();
+        (site0, site1)
+    }
+
+    fn deliver(rga: &mut Rga<char>, ops: &[RemoteOp<char>]) {
+        for op in ops {
+            rga.apply_remote(op.clone()).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_move_keeps_identity() {
+        let (mut site0, mut site1) = pair("one two three");
+
+        // Site 0 moves "two " to the front while site 1 edits inside it
+        let moved = site0.move_range_local(4, 4, 0).unwrap();
+        assert_eq!(text(&site0), "two one three");
+        let edits = vec![
+            site1.update_local(4, 'T').unwrap(),
+            site1.insert_local(7, '!').unwrap(),
+            site1.delete_local(5).unwrap(),
+        ];
+        assert_eq!(text(&site1), "one To! three");
+
+        deliver(&mut site0, &edits);
+        let changes = site1.apply_remote(moved).unwrap();
+        assert_eq!(text(&site0), text(&site1));
+        assert_eq!(text(&site0), "To one !three");
+        assert!(!changes.is_empty());
+
+        // Moving the moved text again and deleting it still works
+        let again = site1.move_range_local(0, 3, 7).unwrap();
+        deliver(&mut site0, &[again]);
+        assert_eq!(text(&site0), "one To !three");
+        let removed = site0.delete_range_local(4, 3).unwrap();
+        deliver(&mut site1, &[removed]);
+        assert_eq!(text(&site1), "one !three");
+        assert_eq!(text(&site0), text(&site1));
+    }
+
+    #[test]
+    fn test_concurrent_moves_of_same_range_converge() {
+        let (mut site0, mut site1) = pair("abcdef");
+
+        let to_end = site0.move_range_local(0, 2, 6).unwrap();
+        let to_middle = site1.move_range_local(0, 3, 4).unwrap();
+        assert_eq!(text(&site0), "cdefab");
+        assert_eq!(text(&site1), "dabcef");
+
+        deliver(&mut site0, &[to_middle]);
+        deliver(&mut site1, &[to_end]);
+
+        // Same sum, so site 1's move wins for "ab"; "c" only moved there
+        assert_eq!(text(&site0), text(&site1));
+        assert_eq!(text(&site0), "dabcef");
+        assert_eq!(site0.len(), 6);
+        assert_eq!(
+            site0.read_at(site0.session(), &[6, 0]),
+            Some("abcdef".chars().collect::<Vec<_>>())
+        );
+    }
+
+    #[test]
+    fn test_move_survives_snapshot_and_merge() {
+        let (mut site0, mut site1) = pair("hello world");
+        site0.move_range_local(6, 5, 0).unwrap();
+        site0.insert_local(5, ' ');
+        site1.move_range_local(0, 5, 11).unwrap();
+        site1.update_local(0, 'W');
+
+        let restored = Rga::<char>::from_bytes(&site0.to_bytes().unwrap()).unwrap();
+        assert_eq!(text(&restored), text(&site0));
+
+        let mut merged0 = restored.clone();
+        let mut merged1 = site1.clone();
+        merged0.merge(&site1).unwrap();
+        merged1.merge(&site0).unwrap();
+        assert_eq!(text(&merged0), text(&merged1));
+
+        deliver(&mut site0, &site1.ops_since(&[1, 0]).unwrap());
+        assert_eq!(text(&merged0), text(&site0));
+        assert_eq!(text(&site0), "world Whello");
+    }
+
+    #[test]
+    fn test_undo_moves_back() {
+        let (mut site0, mut site1) = pair("one two three");
+
+        let moved = site0.move_range_local(4, 4, 0).unwrap();
+        assert_eq!(text(&site0), "two one three");
+        let undone = site0.undo_local().unwrap();
+        assert_eq!(text(&site0), "one two three");
+        let redone = site0.redo_local().unwrap();
+        assert_eq!(text(&site0), "two one three");
+        deliver(&mut site1, &[moved, undone, redone]);
+        assert_eq!(text(&site1), text(&site0));
+
+        // Site 1 then moves "one " to the end: undo still puts "two " back
+        // after it, wherever it is now
+        let again = site1.move_range_local(4, 4, 13).unwrap();
+        deliver(&mut site0, &[again]);
+        assert_eq!(text(&site0), "two threeone ");
+        let undone = site0.undo_local().unwrap();
+        deliver(&mut site1, &[undone]);
+        assert_eq!(text(&site0), "threeone two ");
+        assert_eq!(text(&site1), text(&site0));
+
+        // A move another site has overridden is skipped
+        let (mut site0, mut site1) = pair("abc");
+        site0.clear_undo_history();
+        let moved = site0.move_range_local(0, 1, 3).unwrap();
+        deliver(&mut site1, &[moved]);
+        let again = site1.move_range_local(2, 1, 0).unwrap();
+        deliver(&mut site0, &[again]);
+        assert!(site0.undo_local().is_none());
+        assert_eq!(text(&site0), "abc");
+    }
+
+    #[test]
+    fn test_move_onto_itself_keeps_text() {
+        let (mut site0, mut site1) = pair("abcdef");
+
+        for dest in [1, 2, 4] {
+            let op = site0.move_range_local(1, 3, dest).unwrap();
+            deliver(&mut site1, &[op]);
+            assert_eq!(text(&site0), "abcdef");
+        }
+        assert_eq!(text(&site1), "abcdef");
+        assert!(site0.move_range_local(1, 0, 4).is_none());
+        assert!(site0.move_range_local(4, 3, 0).is_none());
+    }
+}
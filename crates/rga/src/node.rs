@@ -1,37 +1,164 @@
 use crate::s4vector::S4Vector;
-use std::cell::RefCell;
-use std::rc::Rc;
+use serde::{Deserialize, Serialize};
 
 // Node in the RGA structure
-// Combines linked list for document order with hash table for O(1) lookup (SVI scheme)
-#[derive(Debug, Clone)]
+// Combines linked list for document order with an S4Vector index (SVI scheme)
+//
+// A node holds a run of `len` consecutive elements inserted by one site.
+// Element k of the run has insert ID s_k.offset(k); runs are split whenever
+// a concurrent insert lands inside them or only part of them is deleted or
+// updated, so every element of a node shares its tombstone state and s_p.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Node<T: Clone> {
-    // Object value (None indicates tombstone after deletion)
-    pub obj: Option<T>,
+    // Values of the run (None indicates tombstone after deletion)
+    pub obj: Option<Vec<T>>,
 
-    // Immutable insert ID - set once when node is created
-    // Used for: (1) S4Vector index in hash table, (2) precedence of Inserts
+    // Number of elements in the run, kept for tombstones
+    pub len: u32,
+
+    // Immutable insert ID of the first element - set once when node is created
+    // Used for: (1) S4Vector index lookup, (2) precedence of Inserts
     pub s_k: S4Vector,
 
     // Mutable precedence ID - updated by Delete and Update operations
-    // Used for precedence of Deletes and Updates
+    // Used for precedence of Deletes and Updates. Equal to s_k while the run
+    // is untouched, meaning each element's s_p is its own insert ID
     pub s_p: S4Vector,
 
-    // Link to next node in the linked list (document order)
-    pub link: Option<Rc<RefCell<Node<T>>>>,
+    // Arena slot of the next node in the linked list (document order)
+    // Not serialized: snapshots store nodes in list order instead
+    #[serde(skip)]
+    pub link: Option<usize>,
+
+    // Edits this run has seen, kept for reads at past vector clocks
+    // None while the run is untouched
+    pub history: Option<Box<History<T>>>,
+}
+
+// Non-destructive record of the Deletes and Updates applied to a run
+// `obj` and `s_p` only hold the current state; this keeps what they replaced
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct History<T: Clone> {
+    // Values of the run as originally inserted
+    pub inserted: Vec<T>,
+
+    // S4Vectors of every Delete that targeted the run, concurrent ones included
+    pub deletes: Vec<S4Vector>,
+
+    // Every Update received for the run, winning or not, in arrival order
+    // Updates target single elements, so only single-element runs have any
+    pub updates: Vec<(S4Vector, T)>,
 }
 
 impl<T: Clone> Node<T> {
-    pub fn new(obj: T, s4v: S4Vector) -> Self {
+    pub fn new(values: Vec<T>, s4v: S4Vector) -> Self {
         Node {
-            obj: Some(obj),
+            len: values.len() as u32,
+            obj: Some(values),
             s_k: s4v,
             s_p: s4v,
             link: None,
+            history: None,
         }
     }
 
     pub fn is_tombstone(&self) -> bool {
         self.obj.is_none()
     }
+
+    // Whether no Delete or Update has touched this run yet
+    pub fn is_untouched(&self) -> bool {
+        self.s_p == self.s_k
+    }
+
+    // Insert ID of element `k`
+    pub fn element_id(&self, k: u32) -> S4Vector {
+        self.s_k.offset(k)
+    }
+
+    // Precedence ID of element `k`
+    pub fn element_s_p(&self, k: u32) -> S4Vector {
+        if self.is_untouched() {
+            self.element_id(k)
+        } else {
+            self.s_p
+        }
+    }
+
+    // Mark the run deleted by `s4v`, keeping its values in the history
+    // Returns whether the run was visible before
+    // Deleting again with the same `s4v` changes nothing
+    pub fn delete(&mut self, s4v: S4Vector) -> bool {
+        if self.has_stamp(&s4v) {
+            return false;
+        }
+        let values = self.obj.take();
+        let was_visible = values.is_some();
+        self.history_mut(values.unwrap_or_default())
+            .deletes
+            .push(s4v);
+        self.s_p = s4v;
+        was_visible
+    }
+
+    // Record an Update `s4v` to `value` without applying it
+    // Returns false if it was already recorded
+    pub fn record_update(&mut self, s4v: S4Vector, value: T) -> bool {
+        if self.has_stamp(&s4v) {
+            return false;
+        }
+        let values = match self.history {
+            Some(_) => Vec::new(),
+            None => self.obj.clone().unwrap_or_default(),
+        };
+        self.history_mut(values).updates.push((s4v, value));
+        true
+    }
+
+    // Whether the Delete or Update `s4v` has already been applied to the run
+    fn has_stamp(&self, s4v: &S4Vector) -> bool {
+        self.history.as_ref().is_some_and(|history| {
+            history.deletes.contains(s4v) || history.updates.iter().any(|(id, _)| id == s4v)
+        })
+    }
+
+    // Values of the run as originally inserted (None for a tombstone
+    // without history, which only snapshots from before it existed have)
+    pub fn inserted_values(&self) -> Option<&[T]> {
+        match &self.history {
+            Some(history) => Some(&history.inserted),
+            None => self.obj.as_deref(),
+        }
+    }
+
+    // History of the run, created with `inserted` values if there is none yet
+    fn history_mut(&mut self, inserted: Vec<T>) -> &mut History<T> {
+        self.history.get_or_insert_with(|| {
+            Box::new(History {
+                inserted,
+                deletes: Vec::new(),
+                updates: Vec::new(),
+            })
+        })
+    }
+
+    // Split the history off at element `offset`, for a tail node starting there
+    pub fn split_history(&mut self, offset: u32) -> Option<Box<History<T>>> {
+        let history = self.history.as_mut()?;
+        Some(Box::new(History {
+            inserted: history.inserted.split_off(offset as usize),
+            deletes: history.deletes.clone(),
+            updates: history.updates.clone(),
+        }))
+    }
+
+    // Offset of `id` within this run, if the run contains it
+    pub fn offset_of(&self, id: &S4Vector) -> Option<u32> {
+        let k = id.seq.checked_sub(self.s_k.seq)?;
+        (id.ssn == self.s_k.ssn
+            && id.sid == self.s_k.sid
+            && k < self.len
+            && self.element_id(k) == *id)
+            .then_some(k)
+    }
 }
@@ -0,0 +1,184 @@
+// Operation log for anti-entropy
+//
+// Every operation integrated by a replica (local or remote) is appended in
+// the order it was applied, which is a causal order. A peer that reports its
+// vector clock can then be sent exactly the ops it has not seen yet.
+
+use crate::remote_op::RemoteOp;
+use crate::rga::Rga;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct OpLog<T: Clone> {
+    // Operations in the order they were integrated
+    pub(crate) ops: Vec<RemoteOp<T>>,
+
+    // Vector clock covering every op compacted out of the log
+    pub(crate) base: Vec<u32>,
+}
+
+impl<T: Clone> Default for OpLog<T> {
+    fn default() -> Self {
+        OpLog {
+            ops: Vec::new(),
+            base: Vec::new(),
+        }
+    }
+}
+
+impl<T: Clone> OpLog<T> {
+    pub(crate) fn push(&mut self, op: RemoteOp<T>) {
+        self.ops.push(op);
+    }
+}
+
+// Whether `vector_clock` has seen `op`
+fn covers<T: Clone>(vector_clock: &[u32], op: &RemoteOp<T>) -> bool {
+    let last = op.last_s4v();
+    vector_clock
+        .get(last.sid as usize)
+        .is_some_and(|&count| count >= last.seq)
+}
+
+impl<T: Clone> Rga<T> {
+    /// Operations a replica at `remote_vc` has not seen, in causal order
+    ///
+    /// `remote_vc` is that replica's `vector_clock`, which covers only the
+    /// ops it integrated: ops it holds in its pending buffer are sent again,
+    /// along with the ops they wait on. Ops pending here are not sent.
+    ///
+    /// Returns None if some of them were already compacted out of the log
+    /// (see `compact_log`); that replica needs a full snapshot instead.
+    pub fn ops_since(&self, remote_vc: &[u32]) -> Option<Vec<RemoteOp<T>>> {
+        let base_covered = self
+            .log
+            .base
+            .iter()
+            .enumerate()
+            .all(|(sid, &count)| remote_vc.get(sid).copied().unwrap_or(0) >= count);
+        if !base_covered {
+            return None;
+        }
+
+        let missing = self
+            .log
+            .ops
+            .iter()
+            .filter(|op| !covers(remote_vc, op))
+            .cloned()
+            .collect();
+        Some(missing)
+    }
+
+    /// Drop logged operations covered by `stable_vc`
+    ///
+    /// `stable_vc` should come from `stable_vector_clock`, so that every
+    /// listed site has seen the dropped ops. Returns the number removed.
+    pub fn compact_log(&mut self, stable_vc: &[u32]) -> usize {
+        let before = self.log.ops.len();
+        self.log.ops.retain(|op| !covers(stable_vc, op));
+
+        let base = &mut self.log.base;
+        if base.len() < stable_vc.len() {
+            base.resize(stable_vc.len(), 0);
+        }
+        for (b, &s) in base.iter_mut().zip(stable_vc) {
+            *b = (*b).max(s);
+        }
+
+        before - self.log.ops.len()
+    }
+
+    /// Number of operations held in the log
+    pub fn log_len(&self) -> usize {
+        self.log.ops.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Rga;
+
+    #[test]
+    fn test_ops_since_sends_only_unseen_ops() {
+        let mut site0 = Rga::<char>::new(0, 2);
+        let mut site1 = Rga::<char>::new(1, 2);
+
+        for (i, ch) in "abc".chars().enumerate() {
+            site1
+                .apply_remote(site0.insert_local(i, ch).unwrap())
+                .unwrap();
+        }
+        let seen = site1.vector_clock().to_vec();
+
+        // Both sides keep editing while disconnected
+        site0.delete_local(0).unwrap();
+        site0.insert_local(2, 'x').unwrap();
+        site1.update_local(1, 'B').unwrap();
+
+        let to_site1 = site0.ops_since(site1.vector_clock()).unwrap();
+        let to_site0 = site1.ops_since(site0.vector_clock()).unwrap();
+        assert_eq!(to_site1.len(), 2);
+        assert_eq!(to_site0.len(), 1);
+
+        for op in to_site1 {
+            site1.apply_remote(op).unwrap();
+        }
+        for op in to_site0 {
+            site0.apply_remote(op).unwrap();
+        }
+        assert_eq!(site0.read(), site1.read());
+        assert_eq!(site0.read(), vec!['B', 'c', 'x']);
+
+        // Nothing left to exchange
+        assert!(site0.ops_since(site1.vector_clock()).unwrap().is_empty());
+        assert_eq!(site0.ops_since(&seen).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_ops_since_releases_buffered_ops() {
+        let mut site0 = Rga::<char>::new(0, 3);
+        let mut site1 = Rga::<char>::new(1, 3);
+        let mut site2 = Rga::<char>::new(2, 3);
+
+        site2
+            .apply_remote(site0.insert_local(0, 'a').unwrap())
+            .unwrap();
+        let reply = site2.insert_local(1, 'b').unwrap();
+
+        // Site 1 got the reply without what it answers
+        site1.apply_remote(reply).unwrap();
+        assert_eq!(site1.pending_count(), 1);
+        assert!(site1.ops_since(&[]).unwrap().is_empty());
+
+        let missing = site2.ops_since(site1.vector_clock()).unwrap();
+        assert_eq!(missing.len(), 2);
+        for op in missing {
+            site1.apply_remote(op).unwrap();
+        }
+        assert_eq!(site1.pending_count(), 0);
+        assert_eq!(site1.read(), vec!['a', 'b']);
+        assert_eq!(site1.vector_clock(), site2.vector_clock());
+    }
+
+    #[test]
+    fn test_compacted_ops_require_snapshot() {
+        let mut site0 = Rga::<char>::new(0, 2);
+        let mut site1 = Rga::<char>::new(1, 2);
+
+        for (i, ch) in "ab".chars().enumerate() {
+            site1
+                .apply_remote(site0.insert_local(i, ch).unwrap())
+                .unwrap();
+        }
+        site0.acknowledge(1, site1.vector_clock());
+
+        let stable = site0.stable_vector_clock(&[1]);
+        assert_eq!(site0.compact_log(&stable), 2);
+        assert_eq!(site0.log_len(), 0);
+
+        // Site 1 is caught up; a fresh replica is not
+        assert!(site0.ops_since(site1.vector_clock()).unwrap().is_empty());
+        assert!(site0.ops_since(&[]).is_none());
+    }
+}
@@ -0,0 +1,359 @@
+use crate::s4vector::S4Vector;
+use std::collections::HashMap;
+
+// Order-statistic index over the RGA's linked list
+//
+// An implicit treap (ordered by list position, not by key) whose subtrees
+// carry both their number of nodes and their number of visible elements
+// (a node holds a run of `len` elements). Together with parent links this
+// gives expected O(log n):
+// - visible index -> node and offset (`nth_visible`)
+// - node -> visible index (`visible_index`)
+// - insert after a node / remove a node / toggle visibility / resize a run
+// and O(1) visible length.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct PositionIndex {
+    // Arena of treap entries, addressed by slot
+    entries: Vec<Entry>,
+
+    // Slots released by `remove`, reused by later inserts
+    free: Vec<usize>,
+
+    // Maps node s_k -> slot
+    slots: HashMap<S4Vector, usize>,
+
+    root: Option<usize>,
+
+    // xorshift state for treap priorities
+    seed: u32,
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    id: S4Vector,
+    priority: u32,
+    parent: Option<usize>,
+    left: Option<usize>,
+    right: Option<usize>,
+
+    // Whether this node is visible (not a tombstone)
+    visible: bool,
+
+    // Number of elements in this node's run
+    len: usize,
+
+    // Number of entries in this subtree
+    size: usize,
+
+    // Number of visible elements in this subtree
+    visible_count: usize,
+}
+
+impl PositionIndex {
+    pub(crate) fn new() -> Self {
+        PositionIndex {
+            seed: 0x9E37_79B9,
+            ..Default::default()
+        }
+    }
+
+    // Number of visible elements
+    pub(crate) fn visible_len(&self) -> usize {
+        self.visible_count(self.root)
+    }
+
+    // Insert `id` with a run of `len` elements directly after `prev` in list
+    // order (None = at the head)
+    pub(crate) fn insert_after(
+        &mut self,
+        prev: Option<&S4Vector>,
+        id: S4Vector,
+        len: usize,
+        visible: bool,
+    ) {
+        let position = match prev.and_then(|p| self.slots.get(p)) {
+            Some(&slot) => self.position(slot) + 1,
+            None => 0,
+        };
+
+        let priority = self.next_priority();
+        let entry = Entry {
+            id,
+            priority,
+            parent: None,
+            left: None,
+            right: None,
+            visible,
+            len,
+            size: 1,
+            visible_count: if visible { len } else { 0 },
+        };
+        let slot = match self.free.pop() {
+            Some(slot) => {
+                self.entries[slot] = entry;
+                slot
+            }
+            None => {
+                self.entries.push(entry);
+                self.entries.len() - 1
+            }
+        };
+        self.slots.insert(id, slot);
+
+        let (left, right) = self.split(self.root, position);
+        let left = self.merge(left, Some(slot));
+        self.root = self.merge(left, right);
+    }
+
+    // Remove `id` from the index
+    pub(crate) fn remove(&mut self, id: &S4Vector) {
+        let Some(slot) = self.slots.remove(id) else {
+            return;
+        };
+
+        let position = self.position(slot);
+        let (left, rest) = self.split(self.root, position);
+        let (_, right) = self.split(rest, 1);
+        self.root = self.merge(left, right);
+        self.free.push(slot);
+    }
+
+    // Mark `id` as visible or tombstoned
+    pub(crate) fn set_visible(&mut self, id: &S4Vector, visible: bool) {
+        let Some(&slot) = self.slots.get(id) else {
+            return;
+        };
+
+        self.entries[slot].visible = visible;
+        self.recompute_path(slot);
+    }
+
+    // Change the run length of `id`
+    pub(crate) fn set_len(&mut self, id: &S4Vector, len: usize) {
+        let Some(&slot) = self.slots.get(id) else {
+            return;
+        };
+
+        self.entries[slot].len = len;
+        self.recompute_path(slot);
+    }
+
+    // S4Vector of the node holding visible element `index`, and the
+    // element's offset within that node's run
+    pub(crate) fn nth_visible(&self, mut index: usize) -> Option<(S4Vector, usize)> {
+        let mut current = self.root;
+
+        while let Some(x) = current {
+            let entry = &self.entries[x];
+            let left_count = self.visible_count(entry.left);
+            let own = if entry.visible { entry.len } else { 0 };
+
+            if index < left_count {
+                current = entry.left;
+            } else if index < left_count + own {
+                return Some((entry.id, index - left_count));
+            } else {
+                index -= left_count + own;
+                current = entry.right;
+            }
+        }
+
+        None
+    }
+
+    // Number of visible elements strictly before node `id` in list order
+    pub(crate) fn visible_index(&self, id: &S4Vector) -> Option<usize> {
+        let &slot = self.slots.get(id)?;
+        let mut count = self.visible_count(self.entries[slot].left);
+
+        let mut child = slot;
+        while let Some(parent) = self.entries[child].parent {
+            let entry = &self.entries[parent];
+            if entry.right == Some(child) {
+                count += self.visible_count(entry.left) + self.own_visible(parent);
+            }
+            child = parent;
+        }
+
+        Some(count)
+    }
+
+    // Total number of entries strictly before `slot` in list order
+    fn position(&self, slot: usize) -> usize {
+        let mut count = self.size(self.entries[slot].left);
+
+        let mut child = slot;
+        while let Some(parent) = self.entries[child].parent {
+            let entry = &self.entries[parent];
+            if entry.right == Some(child) {
+                count += self.size(entry.left) + 1;
+            }
+            child = parent;
+        }
+
+        count
+    }
+
+    // Split a subtree into its first `k` entries and the rest
+    fn split(&mut self, tree: Option<usize>, k: usize) -> (Option<usize>, Option<usize>) {
+        let Some(x) = tree else {
+            return (None, None);
+        };
+
+        let left_size = self.size(self.entries[x].left);
+        let (left, right) = if k <= left_size {
+            let (l, r) = self.split(self.entries[x].left, k);
+            self.set_left(x, r);
+            (l, Some(x))
+        } else {
+            let (l, r) = self.split(self.entries[x].right, k - left_size - 1);
+            self.set_right(x, l);
+            (Some(x), r)
+        };
+
+        self.recompute(x);
+        self.detach(left);
+        self.detach(right);
+        (left, right)
+    }
+
+    // Concatenate two subtrees, `a` before `b`
+    fn merge(&mut self, a: Option<usize>, b: Option<usize>) -> Option<usize> {
+        let root = match (a, b) {
+            (None, tree) | (tree, None) => return tree,
+            (Some(x), Some(y)) if self.entries[x].priority > self.entries[y].priority => {
+                let merged = self.merge(self.entries[x].right, b);
+                self.set_right(x, merged);
+                x
+            }
+            (Some(_), Some(y)) => {
+                let merged = self.merge(a, self.entries[y].left);
+                self.set_left(y, merged);
+                y
+            }
+        };
+
+        self.recompute(root);
+        self.detach(Some(root));
+        Some(root)
+    }
+
+    fn set_left(&mut self, x: usize, child: Option<usize>) {
+        self.entries[x].left = child;
+        if let Some(c) = child {
+            self.entries[c].parent = Some(x);
+        }
+    }
+
+    fn set_right(&mut self, x: usize, child: Option<usize>) {
+        self.entries[x].right = child;
+        if let Some(c) = child {
+            self.entries[c].parent = Some(x);
+        }
+    }
+
+    fn detach(&mut self, tree: Option<usize>) {
+        if let Some(x) = tree {
+            self.entries[x].parent = None;
+        }
+    }
+
+    // Recompute aggregates from `x` up to the root
+    fn recompute_path(&mut self, slot: usize) {
+        let mut current = Some(slot);
+        while let Some(x) = current {
+            self.recompute(x);
+            current = self.entries[x].parent;
+        }
+    }
+
+    fn recompute(&mut self, x: usize) {
+        let (left, right) = (self.entries[x].left, self.entries[x].right);
+        let size = 1 + self.size(left) + self.size(right);
+        let visible_count =
+            self.own_visible(x) + self.visible_count(left) + self.visible_count(right);
+
+        let entry = &mut self.entries[x];
+        entry.size = size;
+        entry.visible_count = visible_count;
+    }
+
+    // Visible elements held by entry `x` itself
+    fn own_visible(&self, x: usize) -> usize {
+        let entry = &self.entries[x];
+        if entry.visible {
+            entry.len
+        } else {
+            0
+        }
+    }
+
+    fn size(&self, tree: Option<usize>) -> usize {
+        tree.map_or(0, |x| self.entries[x].size)
+    }
+
+    fn visible_count(&self, tree: Option<usize>) -> usize {
+        tree.map_or(0, |x| self.entries[x].visible_count)
+    }
+
+    fn next_priority(&mut self) -> u32 {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 17;
+        self.seed ^= self.seed << 5;
+        self.seed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PositionIndex;
+    use crate::S4Vector;
+
+    fn id(n: u32) -> S4Vector {
+        S4Vector::new(1, 0, u64::from(n), n)
+    }
+
+    #[test]
+    fn test_matches_vec_model() {
+        let mut index = PositionIndex::new();
+        // (id, run length, visible) per node
+        let mut model: Vec<(S4Vector, usize, bool)> = Vec::new();
+
+        // Insert runs after pseudo-random predecessors, tombstone every third
+        for n in 1..200u32 {
+            let at = (n as usize * 7919) % (model.len() + 1);
+            let prev = at.checked_sub(1).map(|i| model[i].0);
+            let len = 1 + n as usize % 4;
+            index.insert_after(prev.as_ref(), id(n), len, true);
+            model.insert(at, (id(n), len, true));
+
+            if n % 3 == 0 {
+                let victim = model[(n as usize * 31) % model.len()].0;
+                index.set_visible(&victim, false);
+                model.iter_mut().find(|e| e.0 == victim).unwrap().2 = false;
+            }
+            if n % 7 == 0 {
+                let resized = model[(n as usize * 17) % model.len()].0;
+                index.set_len(&resized, 2);
+                model.iter_mut().find(|e| e.0 == resized).unwrap().1 = 2;
+            }
+            if n % 10 == 0 {
+                let victim = model.remove((n as usize * 13) % model.len()).0;
+                index.remove(&victim);
+            }
+        }
+
+        let mut expected = Vec::new();
+        for &(id, len, visible) in &model {
+            assert_eq!(index.visible_index(&id), Some(expected.len()));
+            if visible {
+                expected.extend((0..len).map(|k| (id, k)));
+            }
+        }
+        assert_eq!(index.visible_len(), expected.len());
+        for (i, element) in expected.iter().enumerate() {
+            assert_eq!(index.nth_visible(i), Some(*element));
+        }
+        assert_eq!(index.nth_visible(expected.len()), None);
+    }
+}
@@ -10,6 +10,18 @@ pub enum RemoteOp<T: Clone> {
         left_id: Option<S4Vector>,
         value: T,
         s4v: S4Vector,
+        #[serde(with = "crate::compact_clock::sparse_clock")]
+        vector_clock: Vec<u32>,
+    },
+
+    // InsertRun(left_id, values) - inserts consecutive values after left_id
+    // Value k gets S4Vector s4v.offset(k), so the run behaves exactly like
+    // values.len() Inserts each placed after the previous one
+    InsertRun {
+        left_id: Option<S4Vector>,
+        values: Vec<T>,
+        s4v: S4Vector,
+        #[serde(with = "crate::compact_clock::sparse_clock")]
         vector_clock: Vec<u32>,
     },
 
@@ -17,6 +29,16 @@ pub enum RemoteOp<T: Clone> {
     Delete {
         target_id: S4Vector,
         s4v: S4Vector,
+        #[serde(with = "crate::compact_clock::sparse_clock")]
+        vector_clock: Vec<u32>,
+    },
+
+    // DeleteRange(spans) - marks every element of each span as tombstone
+    // A span (first, count) covers first.offset(0) .. first.offset(count - 1)
+    DeleteRange {
+        spans: Vec<(S4Vector, u32)>,
+        s4v: S4Vector,
+        #[serde(with = "crate::compact_clock::sparse_clock")]
         vector_clock: Vec<u32>,
     },
 
@@ -25,6 +47,144 @@ pub enum RemoteOp<T: Clone> {
         target_id: S4Vector,
         value: T,
         s4v: S4Vector,
+        #[serde(with = "crate::compact_clock::sparse_clock")]
         vector_clock: Vec<u32>,
     },
+
+    // Move(spans, left_id) - relocates every element of the spans, in
+    // order, to just after left_id (None = head)
+    // Like an InsertRun, element k is placed under S4Vector s4v.offset(k);
+    // the elements keep their identity, so later Deletes and Updates of
+    // their old S4Vectors follow them
+    Move {
+        spans: Vec<(S4Vector, u32)>,
+        left_id: Option<S4Vector>,
+        s4v: S4Vector,
+        #[serde(with = "crate::compact_clock::sparse_clock")]
+        vector_clock: Vec<u32>,
+    },
+
+    // Batch(ops) - applies ops in order, all at once
+    // The ops are from one site and take consecutive sequence numbers from
+    // s4v on; vector_clock is that of the last one. Batches do not nest
+    Batch {
+        ops: Vec<RemoteOp<T>>,
+        s4v: S4Vector,
+        #[serde(with = "crate::compact_clock::sparse_clock")]
+        vector_clock: Vec<u32>,
+    },
+}
+
+impl<T: Clone> RemoteOp<T> {
+    // S4Vector identifying this operation
+    // For an InsertRun this is the S4Vector of its first value
+    pub fn s4v(&self) -> S4Vector {
+        match self {
+            RemoteOp::Insert { s4v, .. }
+            | RemoteOp::InsertRun { s4v, .. }
+            | RemoteOp::Delete { s4v, .. }
+            | RemoteOp::DeleteRange { s4v, .. }
+            | RemoteOp::Update { s4v, .. }
+            | RemoteOp::Move { s4v, .. }
+            | RemoteOp::Batch { s4v, .. } => *s4v,
+        }
+    }
+
+    // S4Vector of the last sequence number this operation takes
+    // Only an InsertRun, a Move or a Batch takes more than one
+    pub fn last_s4v(&self) -> S4Vector {
+        match self {
+            RemoteOp::InsertRun { values, s4v, .. } => {
+                s4v.offset((values.len() as u32).saturating_sub(1))
+            }
+            RemoteOp::Move { spans, s4v, .. } => {
+                let count = spans
+                    .iter()
+                    .fold(0u32, |total, &(_, count)| total.saturating_add(count));
+                s4v.offset(count.saturating_sub(1))
+            }
+            RemoteOp::Batch { ops, s4v, .. } => ops.last().map_or(*s4v, RemoteOp::last_s4v),
+            _ => self.s4v(),
+        }
+    }
+
+    // Vector clock of the originating site when the operation was generated
+    pub fn vector_clock(&self) -> &[u32] {
+        match self {
+            RemoteOp::Insert { vector_clock, .. }
+            | RemoteOp::InsertRun { vector_clock, .. }
+            | RemoteOp::Delete { vector_clock, .. }
+            | RemoteOp::DeleteRange { vector_clock, .. }
+            | RemoteOp::Update { vector_clock, .. }
+            | RemoteOp::Move { vector_clock, .. }
+            | RemoteOp::Batch { vector_clock, .. } => vector_clock,
+        }
+    }
+
+    // Elements this operation needs to exist before it can be applied
+    // (the left cobject of an insert, the targets of a delete/update, or
+    // both for a move), as (first S4Vector, count) spans
+    // A Batch depends on what its ops depend on, except its own elements
+    pub fn dependencies(&self) -> Vec<(S4Vector, u32)> {
+        match self {
+            RemoteOp::Insert { left_id, .. } | RemoteOp::InsertRun { left_id, .. } => {
+                left_id.iter().map(|id| (*id, 1)).collect()
+            }
+            RemoteOp::Delete { target_id, .. } | RemoteOp::Update { target_id, .. } => {
+                vec![(*target_id, 1)]
+            }
+            RemoteOp::DeleteRange { spans, .. } => spans.clone(),
+            RemoteOp::Move { spans, left_id, .. } => {
+                let mut dependencies = spans.clone();
+                dependencies.extend(left_id.iter().map(|id| (*id, 1)));
+                dependencies
+            }
+            RemoteOp::Batch { ops, .. } => {
+                let (first, last) = (self.s4v(), self.last_s4v());
+                let own = |id: &S4Vector| {
+                    id.ssn == first.ssn
+                        && id.sid == first.sid
+                        && (first.seq..=last.seq).contains(&id.seq)
+                };
+
+                let mut dependencies = Vec::new();
+                for op in ops {
+                    for id in span_elements(&op.dependencies()).filter(|id| !own(id)) {
+                        push_span(&mut dependencies, id);
+                    }
+                }
+                dependencies
+            }
+        }
+    }
+
+    // Elements this operation creates, as (first S4Vector, count) spans
+    pub(crate) fn created(&self) -> Vec<(S4Vector, u32)> {
+        match self {
+            RemoteOp::Insert { s4v, .. } => vec![(*s4v, 1)],
+            RemoteOp::InsertRun { values, s4v, .. } => vec![(*s4v, values.len() as u32)],
+            RemoteOp::Move { spans, s4v, .. } => {
+                vec![(*s4v, span_elements(spans).count() as u32)]
+            }
+            RemoteOp::Batch { ops, .. } => ops.iter().flat_map(RemoteOp::created).collect(),
+            RemoteOp::Delete { .. } | RemoteOp::DeleteRange { .. } | RemoteOp::Update { .. } => {
+                Vec::new()
+            }
+        }
+    }
+}
+
+// Every element S4Vector covered by `spans`, in span order
+pub(crate) fn span_elements(spans: &[(S4Vector, u32)]) -> impl Iterator<Item = S4Vector> + '_ {
+    spans
+        .iter()
+        .flat_map(|&(first, count)| (0..count).map(move |k| first.offset(k)))
+}
+
+// Append element `id` to `spans`, extending the last span if `id` follows it
+pub(crate) fn push_span(spans: &mut Vec<(S4Vector, u32)>, id: S4Vector) {
+    match spans.last_mut() {
+        Some((first, count)) if first.offset(*count) == id => *count += 1,
+        _ => spans.push((id, 1)),
+    }
 }
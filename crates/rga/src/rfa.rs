@@ -0,0 +1,209 @@
+// Replicated Fixed-size Array (RFA)
+// Based on "Replicated Abstract Data Types: Building Blocks for Collaborative Applications"
+// by Roh et al., 2011
+//
+// Every slot of the array is a register holding the value of the update with
+// the greatest S4Vector it has seen. Updates to one slot are therefore
+// resolved by S4Vector precedence alone, so replicas converge whatever order
+// the updates arrive in and need no causal buffering.
+
+use crate::s4vector::S4Vector;
+use crate::site_clock::SiteClock;
+use crate::validation::{self, ApplyError};
+use serde::{Deserialize, Serialize};
+
+// Remote RFA operations - serializable for network transmission
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum RfaOp<T: Clone> {
+    // Update(index, value) - writes value into the slot at index
+    Update {
+        index: usize,
+        value: T,
+        s4v: S4Vector,
+        #[serde(with = "crate::compact_clock::sparse_clock")]
+        vector_clock: Vec<u32>,
+    },
+}
+
+impl<T: Clone> RfaOp<T> {
+    // S4Vector identifying this operation
+    pub fn s4v(&self) -> S4Vector {
+        match self {
+            RfaOp::Update { s4v, .. } => *s4v,
+        }
+    }
+
+    pub fn vector_clock(&self) -> &[u32] {
+        match self {
+            RfaOp::Update { vector_clock, .. } => vector_clock,
+        }
+    }
+}
+
+// Slot of the array with the S4Vector of the update that wrote it
+// (None while it still holds its initial value)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Slot<T> {
+    value: T,
+    s_p: Option<S4Vector>,
+}
+
+/// Replicated array with a fixed number of slots
+///
+/// All replicas must be created with the same initial values. Concurrent
+/// updates of one slot are won by the one with the greatest S4Vector.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Rfa<T: Clone> {
+    slots: Vec<Slot<T>>,
+    clock: SiteClock,
+}
+
+impl<T: Clone> Rfa<T> {
+    // Create a new RFA for the given site holding `initial`
+    pub fn new(site_id: u32, num_sites: usize, initial: Vec<T>) -> Self {
+        Rfa {
+            slots: initial
+                .into_iter()
+                .map(|value| Slot { value, s_p: None })
+                .collect(),
+            clock: SiteClock::new(site_id, num_sites),
+        }
+    }
+
+    /// Value of the slot at `index`
+    pub fn get(&self, index: usize) -> Option<&T> {
+        self.slots.get(index).map(|slot| &slot.value)
+    }
+
+    /// Values of every slot, in order
+    pub fn read(&self) -> Vec<T> {
+        self.slots.iter().map(|slot| slot.value.clone()).collect()
+    }
+
+    /// Number of slots
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    /// Current vector clock
+    pub fn vector_clock(&self) -> &[u32] {
+        &self.clock.vector_clock
+    }
+
+    /// Local Update operation
+    /// Returns None if `index` is outside the array
+    pub fn update_local(&mut self, index: usize, value: T) -> Option<RfaOp<T>> {
+        if index >= self.slots.len() {
+            return None;
+        }
+
+        let s4v = self.clock.tick()?;
+        self.slots[index] = Slot {
+            value: value.clone(),
+            s_p: Some(s4v),
+        };
+
+        Some(RfaOp::Update {
+            index,
+            value,
+            s4v,
+            vector_clock: self.clock.vector_clock.clone(),
+        })
+    }
+
+    /// Apply an operation received from another site
+    ///
+    /// Returns whether the slot now holds the op's value; an update that
+    /// lost to a newer one (or was already applied) changes nothing.
+    pub fn apply_remote(&mut self, op: RfaOp<T>) -> Result<bool, ApplyError> {
+        validation::check_stamp(op.s4v(), op.vector_clock())?;
+
+        let RfaOp::Update {
+            index,
+            value,
+            s4v,
+            vector_clock,
+        } = op;
+        let len = self.slots.len();
+        let slot = self
+            .slots
+            .get_mut(index)
+            .ok_or(ApplyError::IndexOutOfRange { index, len })?;
+        self.clock.merge(&vector_clock);
+
+        if slot.s_p.is_some_and(|s_p| !s_p.precedes(&s4v)) {
+            return Ok(false);
+        }
+        *slot = Slot {
+            value,
+            s_p: Some(s4v),
+        };
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ApplyError, Rfa, RfaOp, S4Vector};
+
+    #[test]
+    fn test_concurrent_updates_converge() {
+        let initial = vec!["Untitled".to_string(), "en".to_string()];
+        let mut site0 = Rfa::new(0, 2, initial.clone());
+        let mut site1 = Rfa::new(1, 2, initial);
+
+        let rename0 = site0.update_local(0, "Notes".to_string()).unwrap();
+        let rename1 = site1.update_local(0, "Draft".to_string()).unwrap();
+        let language = site1.update_local(1, "fr".to_string()).unwrap();
+
+        // Same sum, so the higher site ID wins on both replicas
+        assert!(site0.apply_remote(language.clone()).unwrap());
+        assert!(site0.apply_remote(rename1.clone()).unwrap());
+        assert!(!site1.apply_remote(rename0.clone()).unwrap());
+        assert_eq!(site0.read(), site1.read());
+        assert_eq!(site0.get(0).map(String::as_str), Some("Draft"));
+
+        // Duplicates and stale updates change nothing
+        assert!(!site0.apply_remote(rename1).unwrap());
+        assert!(!site1.apply_remote(rename0).unwrap());
+        assert!(!site1.apply_remote(language).unwrap());
+
+        // An update that saw the current value replaces it
+        let rename = site0.update_local(0, "Final".to_string()).unwrap();
+        assert!(site1.apply_remote(rename).unwrap());
+        assert_eq!(site0.read(), site1.read());
+    }
+
+    #[test]
+    fn test_rejects_invalid_updates() {
+        let mut rfa = Rfa::new(0, 2, vec![0u8; 2]);
+
+        let outside = RfaOp::Update {
+            index: 2,
+            value: 1,
+            s4v: S4Vector::new(1, 1, 1, 1),
+            vector_clock: vec![0, 1],
+        };
+        assert_eq!(
+            rfa.apply_remote(outside),
+            Err(ApplyError::IndexOutOfRange { index: 2, len: 2 })
+        );
+
+        let bad_sum = RfaOp::Update {
+            index: 0,
+            value: 1,
+            s4v: S4Vector::new(1, 1, 5, 1),
+            vector_clock: vec![0, 1],
+        };
+        assert!(matches!(
+            rfa.apply_remote(bad_sum),
+            Err(ApplyError::SumMismatch { .. })
+        ));
+        assert_eq!(rfa.read(), vec![0, 0]);
+        assert_eq!(rfa.update_local(2, 1).map(|op| op.s4v()), None);
+    }
+}
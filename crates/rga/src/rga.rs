@@ -1,179 +1,656 @@
+use crate::change::{Change, Observer};
+use crate::moves::MoveTable;
 use crate::node::Node;
-use crate::remote_op::RemoteOp;
-use crate::s4vector::S4Vector;
-use std::cell::RefCell;
-use std::collections::HashMap;
-use std::rc::Rc;
+use crate::op_log::OpLog;
+use crate::position_index::PositionIndex;
+use crate::remote_op::{self, RemoteOp};
+use crate::s4vector::{self, S4Vector};
+use crate::undo::{LocalEdit, UndoHistory};
+use crate::validation::{AppliedSeqs, ApplyError};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+
+// Key of a run in the S4Vector index: (ssn, sid, seq) of its first element
+// Elements of a run share ssn and sid and have consecutive seq, so the run
+// holding any element is the closest key at or below the element's own key
+pub(crate) type RunKey = (u32, u32, u32);
+
+pub(crate) fn run_key(id: &S4Vector) -> RunKey {
+    (id.ssn, id.sid, id.seq)
+}
+
+// Record the removal of `len` visible elements at `index`, merging it with
+// the previous removal when they are adjacent
+fn push_delete<T>(changes: &mut Vec<Change<T>>, index: usize, len: usize) {
+    match changes.last_mut() {
+        Some(Change::Delete {
+            index: last,
+            len: last_len,
+        }) if *last == index => *last_len += len,
+        _ => changes.push(Change::Delete { index, len }),
+    }
+}
 
 /// Main RGA structure
 ///
 /// Uses:
-/// - Linked list (via Node.link) for maintaining document order
-/// - Hash map (SVI scheme) for O(1) lookup by S4Vector
-/// - Rc<RefCell<>> for shared mutable access (justified: need multiple references
-///   from both linked list and hash map, with interior mutability for updates)
-#[derive(Debug)]
+/// - Node arena (`Vec<Node>`) owning every node, addressed by slot
+/// - Linked list (via Node.link slots) for maintaining document order
+/// - Ordered S4Vector index (SVI scheme) for O(log n) lookup of any element,
+///   including elements in the middle of a run
+/// - Order-statistic index for O(log n) visible index <-> node lookups
+///
+/// Each node holds a run of consecutive values inserted by one site, so bulk
+/// inserts and deletes cost one node and one operation. Runs are split on
+/// demand when concurrent edits touch part of them.
+///
+/// Since nodes refer to each other by slot rather than by pointer, `Rga<T>`
+/// is `Send + Sync` whenever `T` is, and cloning a replica is a flat copy.
+#[derive(Debug, Clone)]
 pub struct Rga<T: Clone> {
+    // Arena of nodes; slots of purged nodes are recycled via `free_slots`
+    pub(crate) nodes: Vec<Node<T>>,
+    pub(crate) free_slots: Vec<usize>,
+
     // Head of the linked list
-    head: Option<Rc<RefCell<Node<T>>>>,
+    pub(crate) head: Option<usize>,
+
+    // S4Vector Index (SVI) scheme - maps the first element of each run to
+    // its node slot
+    pub(crate) runs: BTreeMap<RunKey, usize>,
 
-    // Hash map for S4Vector Index (SVI) scheme - enables O(1) lookup
-    // Maps s_k -> Node reference
-    hash_map: HashMap<S4Vector, Rc<RefCell<Node<T>>>>,
+    // Visible positions of list nodes, kept in sync with the linked list
+    pub(crate) index: PositionIndex,
 
     // Local site configuration
-    site_id: u32,
-    session: u32,
-    vector_clock: Vec<u32>,
+    pub(crate) site_id: u32,
+    pub(crate) session: u32,
+    pub(crate) vector_clock: Vec<u32>,
+
+    // Cemetery for tombstone management (s_k of tombstoned nodes)
+    pub(crate) cemetery: Vec<S4Vector>,
+
+    // Latest vector clock known to have been seen by each remote site
+    // (from ops it sent or explicit acknowledgements), used for purging
+    pub(crate) site_clocks: HashMap<u32, Vec<u32>>,
 
-    // Cemetery for tombstone management
-    cemetery: Vec<S4Vector>,
+    // Causal delivery buffer - remote ops that saw an op this replica has
+    // not integrated yet, keyed by the (site, seq) of that missing op
+    pub(crate) pending: HashMap<(u32, u32), Vec<RemoteOp<T>>>,
+
+    // Local edits available to undo_local/redo_local
+    pub(crate) history: UndoHistory<T>,
+
+    // Integrated operations kept for anti-entropy (see `ops_since`)
+    pub(crate) log: OpLog<T>,
+
+    // Sequence numbers of every op integrated here, for duplicate detection
+    pub(crate) applied: AppliedSeqs,
+
+    // Locations of elements relocated by Move operations
+    pub(crate) moves: MoveTable,
+
+    // Notified of the visible changes caused by remote operations
+    pub(crate) observer: Option<Observer<T>>,
 }
 
 impl<T: Clone> Rga<T> {
     // Create a new RGA for the given site
+    // `num_sites` only sizes the initial vector clock; it grows on demand as
+    // new site IDs are seen locally or in remote operations
     pub fn new(site_id: u32, num_sites: usize) -> Self {
         Rga {
+            nodes: Vec::new(),
+            free_slots: Vec::new(),
             head: None,
-            hash_map: HashMap::new(),
+            runs: BTreeMap::new(),
+            index: PositionIndex::new(),
             site_id,
             session: 1,
             vector_clock: vec![0; num_sites],
             cemetery: Vec::new(),
+            site_clocks: HashMap::new(),
+            pending: HashMap::new(),
+            history: UndoHistory::default(),
+            log: OpLog::default(),
+            applied: AppliedSeqs::default(),
+            moves: MoveTable::default(),
+            observer: None,
         }
     }
 
     // Generate S4Vector for current operation
-    fn generate_s4vector(&mut self) -> S4Vector {
-        self.vector_clock[self.site_id as usize] += 1;
-        let sum: u32 = self.vector_clock.iter().sum();
-        let seq = self.vector_clock[self.site_id as usize];
+    fn generate_s4vector(&mut self) -> Option<S4Vector> {
+        self.generate_run_s4vector(1)
+    }
+
+    // Generate the S4Vector of the first of `count` consecutive elements
+    // The clock advances past all of them, as if each were a separate op
+    // Returns None, leaving the clock untouched, if this site's sequence
+    // numbers would overflow; only a new session (see `start_session`)
+    // gives it room again
+    pub(crate) fn generate_run_s4vector(&mut self, count: u32) -> Option<S4Vector> {
+        let site = self.site_id as usize;
+        self.grow_vector_clock(site + 1);
+        let seq = self.vector_clock[site].checked_add(1)?;
+        let last = self.vector_clock[site].checked_add(count.max(1))?;
+
+        self.vector_clock[site] = seq;
+        let sum = s4vector::clock_sum(&self.vector_clock);
+        self.vector_clock[site] = last;
+
+        Some(S4Vector::new(self.session, self.site_id, sum, seq))
+    }
 
-        S4Vector::new(self.session, self.site_id, sum, seq)
+    // Extend the vector clock with zero entries for newly seen sites
+    // Trailing zeros leave every S4Vector sum unchanged
+    pub(crate) fn grow_vector_clock(&mut self, len: usize) {
+        if self.vector_clock.len() < len {
+            self.vector_clock.resize(len, 0);
+        }
     }
 
-    // Find node by index (for local operations)
+    /// Current vector clock of this replica, indexed by site ID
+    pub fn vector_clock(&self) -> &[u32] {
+        &self.vector_clock
+    }
+
+    // Find the node and offset of a visible element (for local operations)
     // Skips tombstones to match visible document order
-    fn find_by_index(&self, index: usize) -> Option<Rc<RefCell<Node<T>>>> {
-        let mut current = self.head.clone();
-        let mut count = 0;
+    fn find_by_index(&self, index: usize) -> Option<(usize, u32)> {
+        let (id, offset) = self.index.nth_visible(index)?;
+        let slot = self.runs.get(&run_key(&id))?;
+        Some((*slot, offset as u32))
+    }
 
-        while let Some(node_rc) = current {
-            let node = node_rc.borrow();
+    /// S4Vector of the visible element at `index`
+    pub fn id_at(&self, index: usize) -> Option<S4Vector> {
+        let (slot, offset) = self.find_by_index(index)?;
+        Some(self.nodes[slot].element_id(offset))
+    }
 
-            // Skip tombstones (they don't count in visible indices)
-            if !node.is_tombstone() {
-                if count == index {
-                    drop(node);
-                    return Some(node_rc.clone());
-                }
-                count += 1;
+    /// Visible element at `index`
+    pub fn get(&self, index: usize) -> Option<&T> {
+        let (slot, offset) = self.find_by_index(index)?;
+        self.nodes[slot].obj.as_ref()?.get(offset as usize)
+    }
+
+    /// Current visible index of the element inserted as `id`
+    /// Returns None for tombstones and unknown elements
+    pub fn index_of(&self, id: &S4Vector) -> Option<usize> {
+        match self.element_position(id)? {
+            (index, true) => Some(index),
+            (_, false) => None,
+        }
+    }
+
+    // Number of visible elements before `id`, and whether `id` is visible
+    // Tombstones report the position between their surviving neighbours
+    pub(crate) fn element_position(&self, id: &S4Vector) -> Option<(usize, bool)> {
+        let (slot, offset) = self.find_by_s4vector(id)?;
+        let node = &self.nodes[slot];
+        let before = self.index.visible_index(&node.s_k)?;
+
+        if node.is_tombstone() {
+            Some((before, false))
+        } else {
+            Some((before + offset as usize, true))
+        }
+    }
+
+    // Precedence ID (s_p) of element `id`
+    pub(crate) fn element_s_p(&self, id: &S4Vector) -> Option<S4Vector> {
+        let (slot, offset) = self.find_by_s4vector(id)?;
+        Some(self.nodes[slot].element_s_p(offset))
+    }
+
+    // Find the node holding element `s4v` and its offset in the run
+    // (for remote operations)
+    pub(crate) fn find_by_s4vector(&self, s4v: &S4Vector) -> Option<(usize, u32)> {
+        let (_, &slot) = self.runs.range(..=run_key(s4v)).next_back()?;
+        let offset = self.nodes[slot].offset_of(s4v)?;
+        Some((slot, offset))
+    }
+
+    // Store a node in the arena, reusing a purged slot if possible
+    fn alloc(&mut self, node: Node<T>) -> usize {
+        match self.free_slots.pop() {
+            Some(slot) => {
+                self.nodes[slot] = node;
+                slot
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
             }
+        }
+    }
 
-            current = node.link.clone();
+    // Split the run in `slot` so that a new node starts at element `offset`
+    // (0 < offset < len). Returns the slot of the new tail node
+    fn split(&mut self, slot: usize, offset: u32) -> usize {
+        let node = &mut self.nodes[slot];
+        let tail_id = node.element_id(offset);
+        let tail_values = node
+            .obj
+            .as_mut()
+            .map(|values| values.split_off(offset as usize));
+        let tail_len = node.len - offset;
+        let tail_s_p = if node.is_untouched() {
+            tail_id
+        } else {
+            node.s_p
+        };
+        let tail_history = node.split_history(offset);
+        node.len = offset;
+        let (head_id, tombstone, link) = (node.s_k, node.is_tombstone(), node.link);
+
+        let tail = self.alloc(Node {
+            obj: tail_values,
+            len: tail_len,
+            s_k: tail_id,
+            s_p: tail_s_p,
+            link,
+            history: tail_history,
+        });
+        self.nodes[slot].link = Some(tail);
+
+        self.runs.insert(run_key(&tail_id), tail);
+        self.index.set_len(&head_id, offset as usize);
+        self.index
+            .insert_after(Some(&head_id), tail_id, tail_len as usize, !tombstone);
+        if tombstone {
+            self.cemetery.push(tail_id);
         }
 
-        None
+        tail
     }
 
-    // Find node by S4Vector (for remote operations)
-    fn find_by_s4vector(&self, s4v: &S4Vector) -> Option<Rc<RefCell<Node<T>>>> {
-        self.hash_map.get(s4v).cloned()
+    // Split runs so that the `count` elements starting at `id` form whole
+    // nodes. Returns the slot of the node starting at `id`, which holds at
+    // most `count` elements
+    fn isolate(&mut self, id: &S4Vector, count: u32) -> Option<usize> {
+        let (mut slot, offset) = self.find_by_s4vector(id)?;
+        if offset > 0 {
+            slot = self.split(slot, offset);
+        }
+        if self.nodes[slot].len > count {
+            self.split(slot, count);
+        }
+        Some(slot)
     }
 
     // Local Insert operation
     // Returns RemoteOp for broadcasting
+    // Local operations return None once this site has used up its sequence
+    // numbers, until the next session (see `start_session`)
     pub fn insert_local(&mut self, index: usize, value: T) -> Option<RemoteOp<T>> {
-        let s4v = self.generate_s4vector();
+        let (op, edit) = self.generate_insert(index, vec![value])?;
+        self.record_local(edit);
+        Some(op)
+    }
+
+    /// Insert a run of values starting at visible index `index`
+    ///
+    /// The whole run is a single node and a single RemoteOp, however long it
+    /// is. Returns None if `values` is empty, or if this site has too few
+    /// sequence numbers left for it.
+    pub fn insert_run_local(&mut self, index: usize, values: Vec<T>) -> Option<RemoteOp<T>> {
+        let (op, edit) = self.generate_insert(index, values)?;
+        self.record_local(edit);
+        Some(op)
+    }
+
+    // Local Delete operation
+    pub fn delete_local(&mut self, index: usize) -> Option<RemoteOp<T>> {
+        let (op, edit) = self.generate_delete(index, 1)?;
+        self.record_local(edit);
+        Some(op)
+    }
+
+    /// Delete up to `len` visible elements starting at `index`
+    ///
+    /// Produces a single RemoteOp covering the whole range. Returns None if
+    /// there is nothing to delete.
+    pub fn delete_range_local(&mut self, index: usize, len: usize) -> Option<RemoteOp<T>> {
+        let (op, edit) = self.generate_delete(index, len)?;
+        self.record_local(edit);
+        Some(op)
+    }
+
+    // Local Update operation
+    pub fn update_local(&mut self, index: usize, value: T) -> Option<RemoteOp<T>> {
+        let (op, edit) = self.generate_update(index, value)?;
+        self.record_local(edit);
+        Some(op)
+    }
+
+    // Insert a run at a visible index without touching undo history
+    // Returns the op and the local edit describing it
+    pub(crate) fn generate_insert(
+        &mut self,
+        index: usize,
+        mut values: Vec<T>,
+    ) -> Option<(RemoteOp<T>, LocalEdit<T>)> {
+        if values.is_empty() {
+            return None;
+        }
+        let count = u32::try_from(values.len()).ok()?;
+        let s4v = self.generate_run_s4vector(count)?;
 
         // Find left cobject
-        let left_id = if index == 0 {
-            None
+        let left_id = match index.checked_sub(1) {
+            Some(left) => self.id_at(left),
+            None => None,
+        };
+
+        self.remote_insert(left_id, values.clone(), s4v).ok()?;
+
+        let vector_clock = self.vector_clock.clone();
+        let op = if count == 1 {
+            RemoteOp::Insert {
+                left_id,
+                value: values.pop()?,
+                s4v,
+                vector_clock,
+            }
         } else {
-            self.find_by_index(index - 1).map(|n| n.borrow().s_k)
+            RemoteOp::InsertRun {
+                left_id,
+                values,
+                s4v,
+                vector_clock,
+            }
         };
+        self.record_op(&op);
+        Some((
+            op,
+            LocalEdit::Insert {
+                spans: vec![(s4v, count)],
+            },
+        ))
+    }
 
-        self.remote_insert(left_id, value.clone(), s4v);
+    // Delete up to `len` visible elements from `index` without touching undo
+    // history. Returns the op and the local edit describing it
+    pub(crate) fn generate_delete(
+        &mut self,
+        index: usize,
+        len: usize,
+    ) -> Option<(RemoteOp<T>, LocalEdit<T>)> {
+        let targets = self.visible_ids(index, len)?;
+        self.generate_delete_elements(&targets)
+    }
 
-        Some(RemoteOp::Insert {
-            left_id,
-            value,
-            s4v,
-            vector_clock: self.vector_clock.clone(),
-        })
+    // S4Vectors of up to `len` visible elements from `index`, in order
+    pub(crate) fn visible_ids(&self, index: usize, len: usize) -> Option<Vec<S4Vector>> {
+        let mut targets = Vec::new();
+        let (mut current, mut offset) = self.find_by_index(index)?;
+
+        'collect: loop {
+            let node = &self.nodes[current];
+            if !node.is_tombstone() {
+                for k in offset..node.len {
+                    if targets.len() == len {
+                        break 'collect;
+                    }
+                    targets.push(node.element_id(k));
+                }
+            }
+            match node.link {
+                Some(next) => (current, offset) = (next, 0),
+                None => break,
+            }
+        }
+
+        Some(targets)
     }
 
-    // Local Delete operation
-    pub fn delete_local(&mut self, index: usize) -> Option<RemoteOp<T>> {
-        let target = self.find_by_index(index)?;
-        let target_id = target.borrow().s_k;
-        let s4v = self.generate_s4vector();
+    // Delete the given visible elements (in document order) without
+    // touching undo history. Returns the op and the local edit describing it
+    pub(crate) fn generate_delete_elements(
+        &mut self,
+        targets: &[S4Vector],
+    ) -> Option<(RemoteOp<T>, LocalEdit<T>)> {
+        let mut spans: Vec<(S4Vector, u32)> = Vec::new();
+        let mut values = Vec::with_capacity(targets.len());
+        let mut last_updates = Vec::with_capacity(targets.len());
+
+        for id in targets {
+            let (slot, offset) = self.find_by_s4vector(id)?;
+            let node = &self.nodes[slot];
+            values.push(node.obj.as_ref()?[offset as usize].clone());
+            last_updates.push(node.element_s_p(offset));
+
+            remote_op::push_span(&mut spans, *id);
+        }
 
-        // Make tombstone
-        target.borrow_mut().obj = None;
-        target.borrow_mut().s_p = s4v;
+        let &(first, _) = spans.first()?;
+        let s4v = self.generate_s4vector()?;
+        self.remote_delete(&spans, s4v).ok()?;
 
-        // Enroll in cemetery for later purging
-        self.cemetery.push(target_id);
+        let vector_clock = self.vector_clock.clone();
+        let op = if targets.len() == 1 {
+            RemoteOp::Delete {
+                target_id: first,
+                s4v,
+                vector_clock,
+            }
+        } else {
+            RemoteOp::DeleteRange {
+                spans: spans.clone(),
+                s4v,
+                vector_clock,
+            }
+        };
+        self.record_op(&op);
 
-        Some(RemoteOp::Delete {
-            target_id,
-            s4v,
-            vector_clock: self.vector_clock.clone(),
-        })
+        let edit = LocalEdit::Delete {
+            spans,
+            values,
+            last_updates,
+        };
+        Some((op, edit))
     }
 
-    // Local Update operation
-    pub fn update_local(&mut self, index: usize, value: T) -> Option<RemoteOp<T>> {
-        let target = self.find_by_index(index)?;
-        let target_id = target.borrow().s_k;
-        let s4v = self.generate_s4vector();
+    // Perform an Update at a visible index without touching undo history
+    // Returns the op and the local edit describing it
+    pub(crate) fn generate_update(
+        &mut self,
+        index: usize,
+        value: T,
+    ) -> Option<(RemoteOp<T>, LocalEdit<T>)> {
+        let target_id = self.id_at(index)?;
+        let target = self.isolate(&target_id, 1)?;
+        let s4v = self.generate_s4vector()?;
 
         // Update the node
-        target.borrow_mut().obj = Some(value.clone());
-        target.borrow_mut().s_p = s4v;
+        let node = &mut self.nodes[target];
+        node.record_update(s4v, value.clone());
+        let previous = node.obj.replace(vec![value.clone()])?.pop()?;
+        node.s_p = s4v;
 
-        Some(RemoteOp::Update {
+        let op = RemoteOp::Update {
             target_id,
             value,
             s4v,
             vector_clock: self.vector_clock.clone(),
-        })
+        };
+        self.record_op(&op);
+
+        let edit = LocalEdit::Update {
+            id: target_id,
+            s4v,
+            previous,
+        };
+        Some((op, edit))
+    }
+
+    /// Visible elements in document order, without copying them
+    pub fn iter(&self) -> impl Iterator<Item = &T> + '_ {
+        std::iter::successors(self.head, |&slot| self.nodes[slot].link)
+            .filter_map(|slot| self.nodes[slot].obj.as_deref())
+            .flatten()
+    }
+
+    /// Visible elements from `index` on, in document order
+    ///
+    /// The start is found through the position index, so the elements
+    /// before it are not walked.
+    pub fn iter_from(&self, index: usize) -> impl Iterator<Item = &T> + '_ {
+        let (first, offset) = self
+            .find_by_index(index)
+            .map_or((None, 0), |(slot, offset)| (Some(slot), offset as usize));
+        std::iter::successors(first, |&slot| self.nodes[slot].link)
+            .filter_map(|slot| self.nodes[slot].obj.as_deref())
+            .enumerate()
+            .flat_map(move |(k, values)| &values[if k == 0 { offset } else { 0 }..])
     }
 
     // Read the current visible document state
     pub fn read(&self) -> Vec<T> {
-        let mut result = Vec::new();
-        let mut current = self.head.clone();
+        let mut result = Vec::with_capacity(self.len());
+        let mut current = self.head;
 
-        while let Some(node_rc) = current {
-            let node = node_rc.borrow();
+        while let Some(slot) = current {
+            let node = &self.nodes[slot];
 
             // Only include non-tombstone nodes
-            if let Some(ref obj) = node.obj {
-                result.push(obj.clone());
+            if let Some(ref values) = node.obj {
+                result.extend(values.iter().cloned());
             }
 
-            current = node.link.clone();
+            current = node.link;
         }
 
         result
     }
 
-    // Apply remote operation (dispatches to specific handlers)
-    pub fn apply_remote(&mut self, op: RemoteOp<T>) {
-        let op_vc = match &op {
-            RemoteOp::Insert { vector_clock, .. } => vector_clock,
-            RemoteOp::Delete { vector_clock, .. } => vector_clock,
-            RemoteOp::Update { vector_clock, .. } => vector_clock,
-        };
+    /// Apply a remote operation
+    ///
+    /// Operations are delivered causally: if the sender had seen an
+    /// operation this replica has not integrated yet (its vector clock is
+    /// ahead of ours anywhere but the sender's own entry, or that entry skips
+    /// a sequence number), the op is held in the pending buffer and applied
+    /// automatically once everything it saw has arrived. The vector clock
+    /// therefore only ever covers integrated operations.
+    ///
+    /// Applying an op again (e.g. a retransmission after a reconnect) has
+    /// no effect. Malformed ops, and ops that conflict with what this replica
+    /// has applied, are rejected without changing anything.
+    ///
+    /// Returns the resulting changes to the visible document, in order,
+    /// including those of any buffered ops this one released. An empty list
+    /// means no visible effect (buffered op, duplicate, delete of a
+    /// tombstone, update that lost to a newer one). The observer, if set,
+    /// sees the same changes.
+    pub fn apply_remote(&mut self, op: RemoteOp<T>) -> Result<Vec<Change<T>>, ApplyError> {
+        self.validate(&op)?;
+
+        let mut changes = Vec::new();
+        self.integrate_ready(vec![op], &mut changes)?;
+        self.notify(&changes);
+        Ok(changes)
+    }
+
+    // Integrate validated ops, buffering those whose causal predecessors are
+    // missing and releasing buffered ops as their predecessors arrive
+    pub(crate) fn integrate_ready(
+        &mut self,
+        mut ready: Vec<RemoteOp<T>>,
+        changes: &mut Vec<Change<T>>,
+    ) -> Result<(), ApplyError> {
+        while let Some(op) = ready.pop() {
+            // The same op may have been buffered more than once
+            if self.has_applied(&op) {
+                continue;
+            }
+            if let Some(missing) = self.missing_op(&op) {
+                self.pending.entry(missing).or_default().push(op);
+                continue;
+            }
 
-        for (i, &op_count) in op_vc.iter().enumerate() {
-            if i < self.vector_clock.len() {
-                self.vector_clock[i] = self.vector_clock[i].max(op_count);
+            // Integrating an op unblocks the ops waiting on its seqs
+            let (sid, last) = (op.s4v().sid, op.last_s4v().seq);
+            self.integrate_remote(op, changes)?;
+            let released: Vec<_> = self
+                .pending
+                .keys()
+                .filter(|&&(site, seq)| site == sid && seq <= last)
+                .copied()
+                .collect();
+            for key in released {
+                ready.extend(self.pending.remove(&key).into_iter().flatten().rev());
             }
         }
+        Ok(())
+    }
+
+    // Report remote changes to the observer, if any
+    pub(crate) fn notify(&self, changes: &[Change<T>]) {
+        if let Some(Observer(notify)) = &self.observer {
+            changes.iter().for_each(|change| notify(change));
+        }
+    }
+
+    /// Call `observer` with every visible change caused by remote operations
+    ///
+    /// Replaces any previous observer. Local edits are not reported, since
+    /// the caller made them.
+    pub fn set_observer(&mut self, observer: impl Fn(&Change<T>) + Send + Sync + 'static) {
+        self.observer = Some(Observer(Arc::new(observer)));
+    }
+
+    /// Stop notifying the current observer
+    pub fn clear_observer(&mut self) {
+        self.observer = None;
+    }
+
+    // Next op the sender of `op` had seen that has not been integrated here,
+    // as (site, seq): the sender's earlier seqs, and everything else its
+    // vector clock covers
+    pub(crate) fn missing_op(&self, op: &RemoteOp<T>) -> Option<(u32, u32)> {
+        let first = op.s4v();
+        op.vector_clock()
+            .iter()
+            .enumerate()
+            .find_map(|(site, &seen)| {
+                let needed = if site == first.sid as usize {
+                    first.seq.saturating_sub(1)
+                } else {
+                    seen
+                };
+                let have = self.vector_clock.get(site).copied().unwrap_or(0);
+                (have < needed).then(|| (site as u32, have + 1))
+            })
+    }
+
+    // Merge the op's vector clock and dispatch to the specific handler,
+    // recording its visible effect in `changes`
+    fn integrate_remote(
+        &mut self,
+        op: RemoteOp<T>,
+        changes: &mut Vec<Change<T>>,
+    ) -> Result<(), ApplyError> {
+        self.grow_vector_clock(op.vector_clock().len());
+        for (count, &op_count) in self.vector_clock.iter_mut().zip(op.vector_clock()) {
+            *count = (*count).max(op_count);
+        }
 
+        // The sender has seen everything covered by the op's clock
+        self.acknowledge(op.s4v().sid, op.vector_clock());
+        self.record_op(&op);
+        self.apply_op(op, changes)
+    }
+
+    // Apply an integrated op to the list, recording its visible effect in
+    // `changes`. The ops of a Batch were validated and their dependencies
+    // found along with it, so none of them fails once the first is applied
+    fn apply_op(
+        &mut self,
+        op: RemoteOp<T>,
+        changes: &mut Vec<Change<T>>,
+    ) -> Result<(), ApplyError> {
         match op {
             RemoteOp::Insert {
                 left_id,
@@ -181,10 +658,26 @@ impl<T: Clone> Rga<T> {
                 s4v,
                 ..
             } => {
-                self.remote_insert(left_id, value, s4v);
+                let values = vec![value];
+                if let Some(index) = self.remote_insert(left_id, values.clone(), s4v)? {
+                    changes.push(Change::Insert { index, values });
+                }
+            }
+            RemoteOp::InsertRun {
+                left_id,
+                values,
+                s4v,
+                ..
+            } => {
+                if let Some(index) = self.remote_insert(left_id, values.clone(), s4v)? {
+                    changes.push(Change::Insert { index, values });
+                }
             }
             RemoteOp::Delete { target_id, s4v, .. } => {
-                self.remote_delete(target_id, s4v);
+                changes.extend(self.remote_delete(&[(target_id, 1)], s4v)?);
+            }
+            RemoteOp::DeleteRange { spans, s4v, .. } => {
+                changes.extend(self.remote_delete(&spans, s4v)?);
             }
             RemoteOp::Update {
                 target_id,
@@ -192,131 +685,398 @@ impl<T: Clone> Rga<T> {
                 s4v,
                 ..
             } => {
-                self.remote_update(target_id, value, s4v);
+                if let Some(index) = self.remote_update(target_id, value.clone(), s4v)? {
+                    changes.push(Change::Update { index, value });
+                }
+            }
+            RemoteOp::Move {
+                spans,
+                left_id,
+                s4v,
+                ..
+            } => {
+                changes.extend(self.remote_move(&spans, left_id, s4v)?);
+            }
+            RemoteOp::Batch { ops, .. } => {
+                for op in ops {
+                    self.apply_op(op, changes)?;
+                }
             }
         }
+        Ok(())
     }
 
-    // Remote Insert operation
-    fn remote_insert(&mut self, left_id: Option<S4Vector>, value: T, s4v: S4Vector) {
-        let new_node = Rc::new(RefCell::new(Node::new(value, s4v)));
-
-        // (i) Find left cobject via hash map
-        if let Some(left_s4v) = left_id {
-            let left_node = match self.find_by_s4vector(&left_s4v) {
-                Some(n) => n,
-                None => {
-                    // Cobject not found - should not happen with proper causality
-                    eprintln!("Warning: Left cobject not found for Insert");
-                    return;
-                }
-            };
-            // Scan forward to find correct insertion point
-            let mut ref_node = left_node.clone();
-            loop {
-                let next = ref_node.borrow().link.clone();
-
-                match next {
-                    Some(ref next_rc) => {
-                        let next_node = next_rc.borrow();
-
-                        if s4v.precedes(&next_node.s_k) {
-                            // Insert precedes next, continue scanning
-                            drop(next_node);
-                            ref_node = next_rc.clone();
-                        } else {
-                            // Next precedes or equals insert, stop here
-                            drop(next_node);
-                            break;
-                        }
-                    }
-                    None => break,
+    // Keep an integrated op for anti-entropy and duplicate detection
+    pub(crate) fn record_op(&mut self, op: &RemoteOp<T>) {
+        self.log.push(op.clone());
+        self.mark_applied(op);
+    }
+
+    /// Remote operations waiting for a causal predecessor to arrive
+    pub fn pending_ops(&self) -> impl Iterator<Item = &RemoteOp<T>> {
+        self.pending.values().flatten()
+    }
+
+    /// Number of remote operations waiting for a causal predecessor
+    pub fn pending_count(&self) -> usize {
+        self.pending.values().map(Vec::len).sum()
+    }
+
+    /// Operations that buffered operations are waiting on, as the site and
+    /// sequence number of the first one missing from each site
+    pub fn missing_ops(&self) -> impl Iterator<Item = (u32, u32)> + '_ {
+        self.pending.keys().copied()
+    }
+
+    /// Record that `site` has seen every operation covered by `vector_clock`
+    ///
+    /// An acknowledgement covering ops of `site` itself that this replica has
+    /// not integrated yet is ignored: ops `site` sent before seeing a delete
+    /// may still be on their way, so the delete is not stable here. The site
+    /// is expected to acknowledge again.
+    pub fn acknowledge(&mut self, site: u32, vector_clock: &[u32]) {
+        let own = |clock: &[u32]| clock.get(site as usize).copied().unwrap_or(0);
+        if own(vector_clock) > own(&self.vector_clock) {
+            return;
+        }
+
+        let known = self.site_clocks.entry(site).or_default();
+        if known.len() < vector_clock.len() {
+            known.resize(vector_clock.len(), 0);
+        }
+        for (k, &v) in known.iter_mut().zip(vector_clock) {
+            *k = (*k).max(v);
+        }
+    }
+
+    /// Minimum vector clock acknowledged by this site and all of `sites`
+    ///
+    /// Every operation covered by the result has been seen by every listed
+    /// site. Sites we have never heard from contribute an all-zero clock.
+    pub fn stable_vector_clock(&self, sites: &[u32]) -> Vec<u32> {
+        let mut stable = self.vector_clock.clone();
+
+        for site in sites.iter().filter(|&&site| site != self.site_id) {
+            let known = self.site_clocks.get(site).map_or(&[][..], Vec::as_slice);
+            for (i, count) in stable.iter_mut().enumerate() {
+                *count = (*count).min(known.get(i).copied().unwrap_or(0));
+            }
+        }
+
+        stable
+    }
+
+    /// Physically remove tombstones whose deletion is causally stable
+    ///
+    /// A tombstone is purged once the operation that deleted it is covered by
+    /// `stable_vc` (see `stable_vector_clock`), no buffered op or local undo
+    /// entry still refers to it, and the insert of the node following it is
+    /// stable too, so that a later insert scanning past the gap stops at the
+    /// same place.
+    /// Returns the number of nodes removed.
+    pub fn purge_tombstones(&mut self, stable_vc: &[u32]) -> usize {
+        let is_stable = |s4v: &S4Vector| {
+            stable_vc
+                .get(s4v.sid as usize)
+                .is_some_and(|&count| count >= s4v.seq)
+        };
+
+        // Tombstones that buffered ops wait on or that undo may still
+        // resurrect next to must stay put
+        let waited_on: Vec<S4Vector> = self
+            .pending_ops()
+            .flat_map(|op| remote_op::span_elements(&op.dependencies()).collect::<Vec<_>>())
+            .collect();
+        let protected: HashSet<usize> = waited_on
+            .iter()
+            .chain(&self.history.deleted_ids())
+            .filter_map(|id| self.find_by_s4vector(id))
+            .map(|(slot, _)| slot)
+            .collect();
+
+        let purgeable: HashSet<S4Vector> = self
+            .cemetery
+            .iter()
+            .filter(|id| {
+                let slot = self.runs[&run_key(id)];
+                let node = &self.nodes[slot];
+                let next_stable = node
+                    .link
+                    .is_none_or(|next| is_stable(&self.nodes[next].s_k));
+                !protected.contains(&slot) && is_stable(&node.s_p) && next_stable
+            })
+            .copied()
+            .collect();
+
+        if purgeable.is_empty() {
+            return 0;
+        }
+
+        self.unlink_all(&purgeable);
+        self.cemetery.retain(|id| !purgeable.contains(id));
+
+        purgeable.len()
+    }
+
+    // Remove nodes from the linked list, SVI and position index in one pass,
+    // releasing their arena slots
+    fn unlink_all(&mut self, ids: &HashSet<S4Vector>) {
+        let mut prev: Option<usize> = None;
+        let mut current = self.head;
+
+        while let Some(slot) = current {
+            let next = self.nodes[slot].link;
+
+            if ids.contains(&self.nodes[slot].s_k) {
+                match prev {
+                    Some(p) => self.nodes[p].link = next,
+                    None => self.head = next,
                 }
+                self.nodes[slot].link = None;
+                self.free_slots.push(slot);
+            } else {
+                prev = Some(slot);
             }
 
-            // Link new node into list (before the first preceding node)
-            let next = ref_node.borrow().link.clone();
-            new_node.borrow_mut().link = next;
-            ref_node.borrow_mut().link = Some(new_node.clone());
-        } else {
-            // Insert at head (left_id = nil)
-            if let Some(ref head_rc) = self.head {
-                if head_rc.borrow().s_k.precedes(&s4v) {
-                    // Current head precedes new node, insert new node at head
-                    new_node.borrow_mut().link = self.head.clone();
-                    self.head = Some(new_node.clone());
-                } else {
-                    // New node precedes head, scan forward from head
-                    let mut ref_node = head_rc.clone();
-                    loop {
-                        let next = ref_node.borrow().link.clone();
-                        match next {
-                            Some(ref next_rc) => {
-                                if s4v.precedes(&next_rc.borrow().s_k) {
-                                    ref_node = next_rc.clone();
-                                } else {
-                                    break;
-                                }
-                            }
-                            None => break,
-                        }
-                    }
-                    let next = ref_node.borrow().link.clone();
-                    new_node.borrow_mut().link = next;
-                    ref_node.borrow_mut().link = Some(new_node.clone());
+            current = next;
+        }
+
+        for id in ids {
+            self.runs.remove(&run_key(id));
+            self.index.remove(id);
+        }
+    }
+
+    /// Number of deleted elements still held as tombstones
+    pub fn tombstone_count(&self) -> usize {
+        self.cemetery
+            .iter()
+            .map(|id| self.nodes[self.runs[&run_key(id)]].len as usize)
+            .sum()
+    }
+
+    /// Total number of nodes (runs) in the structure, including tombstones
+    pub fn node_count(&self) -> usize {
+        self.runs.len()
+    }
+
+    // Remote Insert operation
+    // Inserts `values` as one run whose first element is `s4v`
+    // Returns the visible index of the first inserted element
+    pub(crate) fn remote_insert(
+        &mut self,
+        left_id: Option<S4Vector>,
+        values: Vec<T>,
+        s4v: S4Vector,
+    ) -> Result<Option<usize>, ApplyError> {
+        if values.is_empty() {
+            return Ok(None);
+        }
+
+        // (i) Find left cobject via SVI, then the node the new run is
+        // linked behind (None = new head)
+        let after = match left_id {
+            Some(left_s4v) => {
+                // Cobject not found - should not happen with proper causality
+                let left = self
+                    .end_of_element(&left_s4v, &s4v)
+                    .ok_or(ApplyError::UnknownElement(left_s4v))?;
+                Some(self.scan_insert_position(left, &s4v))
+            }
+            // Insert at head (left_id = nil): new node goes first unless the
+            // current head succeeds it, in which case scan forward from head
+            None => match self.head {
+                Some(head) if !self.nodes[head].s_k.precedes(&s4v) => {
+                    Some(self.scan_insert_position(head, &s4v))
                 }
+                _ => None,
+            },
+        };
+
+        // Typing at the end of one's own run just extends it
+        if let Some(ref_slot) = after.filter(|&slot| self.extends_run(slot, &s4v)) {
+            let node = &mut self.nodes[ref_slot];
+            node.len += values.len() as u32;
+            let (id, len) = (node.s_k, node.len);
+            node.obj.get_or_insert_with(Vec::new).extend(values);
+            self.index.set_len(&id, len as usize);
+            return Ok(self.index_of(&s4v));
+        }
+
+        let len = values.len();
+        let slot = self.alloc(Node::new(values, s4v));
+        match after {
+            Some(ref_slot) => {
+                self.nodes[slot].link = self.nodes[ref_slot].link;
+                self.nodes[ref_slot].link = Some(slot);
+            }
+            None => {
+                self.nodes[slot].link = self.head;
+                self.head = Some(slot);
+            }
+        }
+
+        // Add to SVI and position index
+        self.runs.insert(run_key(&s4v), slot);
+        let after_id = after.map(|ref_slot| self.nodes[ref_slot].s_k);
+        self.index.insert_after(after_id.as_ref(), s4v, len, true);
+        Ok(self.index_of(&s4v))
+    }
+
+    // Node to start the insert scan from for left cobject `left_id`
+    // If the left cobject is inside a run, the rest of the run either all
+    // succeeds the new insert (skip it whole) or the run is split after it
+    fn end_of_element(&mut self, left_id: &S4Vector, s4v: &S4Vector) -> Option<usize> {
+        let (slot, offset) = self.find_by_s4vector(left_id)?;
+        if offset + 1 < self.nodes[slot].len && !s4v.precedes(&left_id.offset(1)) {
+            self.split(slot, offset + 1);
+        }
+        Some(slot)
+    }
+
+    // Whether a new run `s4v` placed right after `slot` continues it
+    fn extends_run(&self, slot: usize, s4v: &S4Vector) -> bool {
+        let node = &self.nodes[slot];
+        !node.is_tombstone() && node.is_untouched() && node.element_id(node.len) == *s4v
+    }
+
+    // Scan forward from `start` past every node whose s_k succeeds `s4v`
+    // Elements of a run succeed one another, so whole runs are skipped
+    // Returns the slot the new node must be linked behind
+    fn scan_insert_position(&self, start: usize, s4v: &S4Vector) -> usize {
+        let mut ref_slot = start;
+
+        while let Some(next) = self.nodes[ref_slot].link {
+            if s4v.precedes(&self.nodes[next].s_k) {
+                // Insert precedes next, continue scanning
+                ref_slot = next;
             } else {
-                // Empty list - new node becomes head
-                self.head = Some(new_node.clone());
+                // Next precedes or equals insert, stop here
+                break;
             }
         }
 
-        // Add to hash map
-        self.hash_map.insert(s4v, new_node);
+        ref_slot
     }
 
-    /// Remote Delete operation
+    /// Remote Delete operation over spans of elements
     /// Delete always wins regardless of s4vector order
-    fn remote_delete(&mut self, target_id: S4Vector, s4v: S4Vector) {
-        if let Some(target) = self.find_by_s4vector(&target_id) {
-            let mut target_mut = target.borrow_mut();
+    /// A moved element is deleted at every location it has had
+    /// Returns the visible ranges removed, in order
+    pub(crate) fn remote_delete(
+        &mut self,
+        spans: &[(S4Vector, u32)],
+        s4v: S4Vector,
+    ) -> Result<Vec<Change<T>>, ApplyError> {
+        if self.moves.is_empty() {
+            return self.delete_elements(spans, s4v);
+        }
 
-            // Mark as tombstone (preserves cobject for future operations)
-            if !target_mut.is_tombstone() {
-                self.cemetery.push(target_id);
+        let mut unmoved = Vec::new();
+        let mut moved = Vec::new();
+        for id in remote_op::span_elements(spans) {
+            let element = self.moves.element_of(&id);
+            if self.moves.record_delete(&element, s4v) {
+                moved.push(element);
+            } else {
+                remote_op::push_span(&mut unmoved, id);
             }
+        }
 
-            target_mut.obj = None;
-            target_mut.s_p = s4v;
-        } else {
-            eprintln!("Warning: Target not found for Delete");
+        let mut changes = self.delete_elements(&unmoved, s4v)?;
+        changes.extend(self.settle_moves(&moved)?);
+        Ok(changes)
+    }
+
+    // Tombstone the elements of `spans` in place with stamp `s4v`
+    // Returns the visible ranges removed, in order
+    pub(crate) fn delete_elements(
+        &mut self,
+        spans: &[(S4Vector, u32)],
+        s4v: S4Vector,
+    ) -> Result<Vec<Change<T>>, ApplyError> {
+        let mut changes = Vec::new();
+
+        for &(first, count) in spans {
+            let mut k = 0;
+            while k < count {
+                let id = first.offset(k);
+                let target = self
+                    .isolate(&id, count - k)
+                    .ok_or(ApplyError::UnknownElement(id))?;
+                let node = &mut self.nodes[target];
+                let (target_id, len) = (node.s_k, node.len);
+                let was_visible = node.delete(s4v);
+
+                // Mark as tombstone (preserves cobject for future operations)
+                if was_visible {
+                    self.cemetery.push(target_id);
+                    self.index.set_visible(&target_id, false);
+                    let index = self.index.visible_index(&target_id).unwrap_or(0);
+                    push_delete(&mut changes, index, len as usize);
+                }
+                k += len;
+            }
         }
+
+        Ok(changes)
     }
 
     /// Remote Update operation
     /// Update only succeeds if s4v succeeds current s_p AND target is not tombstone
-    fn remote_update(&mut self, target_id: S4Vector, value: T, s4v: S4Vector) {
-        if let Some(target) = self.find_by_s4vector(&target_id) {
-            let mut target_mut = target.borrow_mut();
-
-            // Don't update tombstones
-            if target_mut.is_tombstone() {
-                return;
-            }
+    /// Losing updates are still recorded in the node history for `read_at`
+    /// A moved element is updated at its current location
+    /// Returns the visible index of the updated element if the update won
+    pub(crate) fn remote_update(
+        &mut self,
+        target_id: S4Vector,
+        value: T,
+        s4v: S4Vector,
+    ) -> Result<Option<usize>, ApplyError> {
+        let location = self.moves.current_location(&target_id);
+
+        // Deleted at its current location and purged since
+        if location != target_id && self.find_by_s4vector(&location).is_none() {
+            return Ok(None);
+        }
+        self.update_element(location, value, s4v)
+    }
 
-            // Only update if new s4v succeeds current s_p
-            if target_mut.s_p.precedes(&s4v) {
-                target_mut.obj = Some(value);
-                target_mut.s_p = s4v;
-            }
+    // Update the element at `target_id` in place, if `s4v` wins
+    pub(crate) fn update_element(
+        &mut self,
+        target_id: S4Vector,
+        value: T,
+        s4v: S4Vector,
+    ) -> Result<Option<usize>, ApplyError> {
+        let target = self
+            .isolate(&target_id, 1)
+            .ok_or(ApplyError::UnknownElement(target_id))?;
+        let element = self.moves.element_of(&target_id);
+        let target_node = &mut self.nodes[target];
+        let recorded = target_node.record_update(s4v, value.clone());
+
+        // An element that was moved but never updated still ranks by its
+        // own insert ID rather than by where the Move put it
+        let s_p = if target_node.is_untouched() {
+            element
         } else {
-            eprintln!("Warning: Target not found for Update");
+            target_node.s_p
+        };
+
+        // Don't update tombstones; only update if new s4v succeeds current s_p
+        if !recorded || target_node.is_tombstone() || !s_p.precedes(&s4v) {
+            return Ok(None);
         }
+
+        target_node.obj = Some(vec![value]);
+        target_node.s_p = s4v;
+        Ok(self.index_of(&target_id))
     }
 
     /// Get current document length (excluding tombstones)
     pub fn len(&self) -> usize {
-        self.read().len()
+        self.index.visible_len()
     }
 
     pub fn is_empty(&self) -> bool {
@@ -326,7 +1086,7 @@ impl<T: Clone> Rga<T> {
 
 #[cfg(test)]
 mod tests {
-    use crate::Rga;
+    use crate::{RemoteOp, Rga};
 
     #[test]
     fn test_basic_insert_and_read() {
@@ -377,10 +1137,10 @@ mod tests {
         let op_a = site0.insert_local(0, 'a').unwrap();
         let op_b = site0.insert_local(1, 'b').unwrap();
 
-        site1.apply_remote(op_a.clone());
-        site1.apply_remote(op_b.clone());
-        site2.apply_remote(op_a.clone());
-        site2.apply_remote(op_b.clone());
+        site1.apply_remote(op_a.clone()).unwrap();
+        site1.apply_remote(op_b.clone()).unwrap();
+        site2.apply_remote(op_a.clone()).unwrap();
+        site2.apply_remote(op_b.clone()).unwrap();
 
         // Concurrent inserts after 'a' (index 1)
         let op1 = site0.insert_local(1, '1').unwrap(); // Site 0 inserts '1'
@@ -389,16 +1149,16 @@ mod tests {
 
         // Apply in different orders at each site
         // Site 0: local op1, then remote op3, op2
-        site0.apply_remote(op3.clone());
-        site0.apply_remote(op2.clone());
+        site0.apply_remote(op3.clone()).unwrap();
+        site0.apply_remote(op2.clone()).unwrap();
 
         // Site 1: local op2, then remote op3, op1
-        site1.apply_remote(op3.clone());
-        site1.apply_remote(op1.clone());
+        site1.apply_remote(op3.clone()).unwrap();
+        site1.apply_remote(op1.clone()).unwrap();
 
         // Site 2: local op3, then remote op2, op1
-        site2.apply_remote(op2.clone());
-        site2.apply_remote(op1.clone());
+        site2.apply_remote(op2.clone()).unwrap();
+        site2.apply_remote(op1.clone()).unwrap();
 
         // All sites should converge to same order
         let result0 = site0.read();
@@ -420,18 +1180,247 @@ mod tests {
 
         // Insert 'a'
         let op_insert = site0.insert_local(0, 'a').unwrap();
-        site1.apply_remote(op_insert);
+        site1.apply_remote(op_insert).unwrap();
 
         // Concurrent operations: site 0 deletes, site 1 updates
         let op_delete = site0.delete_local(0).unwrap();
         let op_update = site1.update_local(0, 'x').unwrap();
 
         // Apply in different orders
-        site0.apply_remote(op_update.clone());
-        site1.apply_remote(op_delete.clone());
+        site0.apply_remote(op_update.clone()).unwrap();
+        site1.apply_remote(op_delete.clone()).unwrap();
 
         // Both should have tombstone (delete wins)
         assert_eq!(site0.read(), vec![]);
         assert_eq!(site1.read(), vec![]);
     }
+
+    #[test]
+    fn test_out_of_order_ops_are_buffered() {
+        let mut site0 = Rga::<char>::new(0, 2);
+        let mut site1 = Rga::<char>::new(1, 2);
+
+        let op_a = site0.insert_local(0, 'a').unwrap();
+        let op_b = site0.insert_local(1, 'b').unwrap();
+        let op_del = site0.delete_local(0).unwrap();
+
+        // Deliver in reverse order: nothing can be applied yet
+        site1.apply_remote(op_del).unwrap();
+        site1.apply_remote(op_b).unwrap();
+        assert_eq!(site1.read(), vec![]);
+        assert_eq!(site1.pending_count(), 2);
+
+        // The missing insert releases the whole chain
+        site1.apply_remote(op_a).unwrap();
+        assert_eq!(site1.pending_count(), 0);
+        assert_eq!(site1.read(), site0.read());
+    }
+
+    #[test]
+    fn test_purge_requires_causal_stability() {
+        let mut site0 = Rga::<char>::new(0, 3);
+        let mut site1 = Rga::<char>::new(1, 3);
+        let mut site2 = Rga::<char>::new(2, 3);
+
+        for (i, ch) in "abc".chars().enumerate() {
+            let op = site0.insert_local(i, ch).unwrap();
+            site1.apply_remote(op.clone()).unwrap();
+            site2.apply_remote(op).unwrap();
+        }
+        let op_del = site1.delete_local(1).unwrap();
+        site0.apply_remote(op_del.clone()).unwrap();
+        assert_eq!(site0.tombstone_count(), 1);
+
+        // Site 2 has not seen the delete yet, so nothing is stable
+        let stable = site0.stable_vector_clock(&[1, 2]);
+        assert_eq!(site0.purge_tombstones(&stable), 0);
+        assert_eq!(site0.node_count(), 3);
+
+        // Site 2 receives the delete and replies with an op of its own
+        site2.apply_remote(op_del).unwrap();
+        let op_d = site2.insert_local(2, 'd').unwrap();
+        site0.apply_remote(op_d.clone()).unwrap();
+        site1.apply_remote(op_d).unwrap();
+
+        let stable = site0.stable_vector_clock(&[1, 2]);
+        assert_eq!(site0.purge_tombstones(&stable), 1);
+        assert_eq!(site0.tombstone_count(), 0);
+        assert_eq!(site0.node_count(), 3);
+        assert_eq!(site0.read(), vec!['a', 'c', 'd']);
+
+        // Purged replica keeps converging with the unpurged one
+        site1
+            .apply_remote(site0.insert_local(1, 'x').unwrap())
+            .unwrap();
+        site0
+            .apply_remote(site1.insert_local(1, 'y').unwrap())
+            .unwrap();
+        assert_eq!(site0.read(), site1.read());
+    }
+
+    #[test]
+    fn test_index_of_and_id_at() {
+        let mut site0 = Rga::<char>::new(0, 2);
+        let mut site1 = Rga::<char>::new(1, 2);
+
+        for (i, ch) in "abcd".chars().enumerate() {
+            site1
+                .apply_remote(site0.insert_local(i, ch).unwrap())
+                .unwrap();
+        }
+        let id_c = site0.id_at(2).unwrap();
+
+        // Remote delete ahead of 'c' shifts it left
+        site0.apply_remote(site1.delete_local(0).unwrap()).unwrap();
+        assert_eq!(site0.len(), 3);
+        assert_eq!(site0.index_of(&id_c), Some(1));
+        assert_eq!(site0.id_at(1), Some(id_c));
+
+        // Tombstones have no visible index
+        let id_b = site0.id_at(0).unwrap();
+        site0.delete_local(0);
+        assert_eq!(site0.index_of(&id_b), None);
+        assert_eq!(site0.index_of(&id_c), Some(0));
+        assert_eq!(site0.len(), site0.read().len());
+
+        // Iterating from an index starts inside its run, past tombstones
+        site0.insert_run_local(1, "xyz".chars().collect());
+        let text: String = site0.iter_from(2).collect();
+        assert_eq!(text, "yzd");
+        assert_eq!(site0.iter_from(site0.len()).count(), 0);
+    }
+
+    #[test]
+    fn test_replica_is_send_sync_and_cloneable() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Rga<char>>();
+
+        let mut rga = Rga::<char>::new(0, 1);
+        rga.insert_local(0, 'a');
+        rga.insert_local(1, 'b');
+
+        // Clones are independent replicas
+        let mut copy = rga.clone();
+        copy.delete_local(0);
+        assert_eq!(rga.read(), vec!['a', 'b']);
+        assert_eq!(copy.read(), vec!['b']);
+    }
+
+    #[test]
+    fn test_vector_clock_grows_for_new_sites() {
+        let mut site0 = Rga::<char>::new(0, 1);
+        // Site ID beyond the initial clock size must not panic
+        let mut site11 = Rga::<char>::new(11, 1);
+
+        let op_a = site0.insert_local(0, 'a').unwrap();
+        site11.apply_remote(op_a).unwrap();
+        let op_b = site11.insert_local(1, 'b').unwrap();
+        assert_eq!(op_b.vector_clock().len(), 12);
+
+        // Merging a longer remote clock extends the local one
+        site0.apply_remote(op_b).unwrap();
+        assert_eq!(site0.vector_clock()[11], 1);
+        assert_eq!(site0.read(), vec!['a', 'b']);
+        assert_eq!(site0.read(), site11.read());
+    }
+
+    #[test]
+    fn test_sequential_typing_extends_one_run() {
+        let mut rga = Rga::<char>::new(0, 1);
+        for (i, ch) in "hello world".chars().enumerate() {
+            rga.insert_local(i, ch);
+        }
+        assert_eq!(rga.node_count(), 1);
+
+        // Typing in the middle splits the run once
+        rga.insert_local(5, ',');
+        assert_eq!(rga.node_count(), 3);
+        assert_eq!(rga.read().into_iter().collect::<String>(), "hello, world");
+        assert_eq!(rga.iter().collect::<String>(), "hello, world");
+    }
+
+    #[test]
+    fn test_insert_run_splits_on_concurrent_edits() {
+        let mut site0 = Rga::<char>::new(0, 2);
+        let mut site1 = Rga::<char>::new(1, 2);
+
+        let op_run = site0
+            .insert_run_local(0, "abcdef".chars().collect())
+            .unwrap();
+        assert!(matches!(op_run, RemoteOp::InsertRun { .. }));
+        site1.apply_remote(op_run).unwrap();
+        assert_eq!(site1.node_count(), 1);
+
+        // Concurrent insert inside the run and delete across it
+        let op_ins = site0.insert_local(2, 'X').unwrap();
+        let op_del = site1.delete_range_local(1, 4).unwrap();
+        assert!(matches!(op_del, RemoteOp::DeleteRange { .. }));
+
+        site0.apply_remote(op_del).unwrap();
+        site1.apply_remote(op_ins).unwrap();
+        assert_eq!(site0.read(), vec!['a', 'X', 'f']);
+        assert_eq!(site0.read(), site1.read());
+        assert_eq!(site0.tombstone_count(), 4);
+
+        // Updates isolate a single element of a run
+        site1
+            .apply_remote(site0.update_local(2, 'F').unwrap())
+            .unwrap();
+        assert_eq!(site1.read(), vec!['a', 'X', 'F']);
+    }
+
+    #[test]
+    fn test_run_matches_single_element_inserts() {
+        let mut base = Rga::<char>::new(1, 2);
+        base.insert_local(0, 'x');
+
+        // Site 0 inserts "abc" as one run after 'x' ...
+        let mut site0 = Rga::<char>::new(0, 2);
+        site0
+            .apply_remote(base.insert_local(1, 'y').unwrap())
+            .unwrap();
+        let left_id = site0.id_at(0);
+        let RemoteOp::InsertRun {
+            values,
+            s4v,
+            vector_clock,
+            ..
+        } = site0.insert_run_local(1, vec!['a', 'b', 'c']).unwrap()
+        else {
+            panic!("expected an InsertRun");
+        };
+
+        // ... which must land exactly where the same three inserts would
+        let mut singles = Rga::<char>::new(1, 2);
+        let mut runs = Rga::<char>::new(1, 2);
+        for rga in [&mut singles, &mut runs] {
+            rga.insert_local(0, 'x');
+            rga.insert_local(1, 'y');
+            rga.insert_local(2, 'z');
+        }
+
+        runs.apply_remote(RemoteOp::InsertRun {
+            left_id,
+            values: values.clone(),
+            s4v,
+            vector_clock: vector_clock.clone(),
+        })
+        .unwrap();
+        let mut left = left_id;
+        for (k, value) in values.into_iter().enumerate() {
+            let id = s4v.offset(k as u32);
+            let mut clock = vector_clock.clone();
+            clock[0] = id.seq;
+            singles
+                .apply_remote(RemoteOp::Insert {
+                    left_id: left,
+                    value,
+                    s4v: id,
+                    vector_clock: clock,
+                })
+                .unwrap();
+            left = Some(id);
+        }
+        assert_eq!(runs.read(), singles.read());
+    }
 }
@@ -0,0 +1,245 @@
+// Replicated Hash Table (RHT)
+// Based on "Replicated Abstract Data Types: Building Blocks for Collaborative Applications"
+// by Roh et al., 2011
+//
+// Each key maps to a slot holding the value of the Put or Remove with the
+// greatest S4Vector seen for that key. A Remove leaves a tombstone carrying
+// its S4Vector, so a concurrent or late-arriving Put that precedes it cannot
+// bring the key back. Since every slot is resolved by precedence alone,
+// operations commute and need no causal buffering.
+
+use crate::s4vector::S4Vector;
+use crate::site_clock::SiteClock;
+use crate::validation::{self, ApplyError};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::hash::Hash;
+
+// Remote RHT operations - serializable for network transmission
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum RhtOp<K: Clone, V: Clone> {
+    // Put(key, value) - binds key to value
+    Put {
+        key: K,
+        value: V,
+        s4v: S4Vector,
+        #[serde(with = "crate::compact_clock::sparse_clock")]
+        vector_clock: Vec<u32>,
+    },
+
+    // Remove(key) - unbinds key, leaving a tombstone
+    Remove {
+        key: K,
+        s4v: S4Vector,
+        #[serde(with = "crate::compact_clock::sparse_clock")]
+        vector_clock: Vec<u32>,
+    },
+}
+
+impl<K: Clone, V: Clone> RhtOp<K, V> {
+    // S4Vector identifying this operation
+    pub fn s4v(&self) -> S4Vector {
+        match self {
+            RhtOp::Put { s4v, .. } | RhtOp::Remove { s4v, .. } => *s4v,
+        }
+    }
+
+    pub fn vector_clock(&self) -> &[u32] {
+        match self {
+            RhtOp::Put { vector_clock, .. } | RhtOp::Remove { vector_clock, .. } => vector_clock,
+        }
+    }
+
+    pub fn key(&self) -> &K {
+        match self {
+            RhtOp::Put { key, .. } | RhtOp::Remove { key, .. } => key,
+        }
+    }
+}
+
+// Slot of a key with the S4Vector of the op that wrote it
+// value = None means tombstone
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Slot<V> {
+    value: Option<V>,
+    s_p: S4Vector,
+}
+
+/// Replicated key-value map
+///
+/// Concurrent Puts and Removes of one key are won by the operation with the
+/// greatest S4Vector, so a Put can revive a removed key only if it was made
+/// after seeing the Remove (or wins against it).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Rht<K: Clone + Eq + Hash, V: Clone> {
+    slots: HashMap<K, Slot<V>>,
+    clock: SiteClock,
+}
+
+impl<K: Clone + Eq + Hash, V: Clone> Rht<K, V> {
+    // Create a new, empty RHT for the given site
+    pub fn new(site_id: u32, num_sites: usize) -> Self {
+        Rht {
+            slots: HashMap::new(),
+            clock: SiteClock::new(site_id, num_sites),
+        }
+    }
+
+    /// Value bound to `key`
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.slots.get(key).and_then(|slot| slot.value.as_ref())
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Bound keys and their values, in arbitrary order
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.slots
+            .iter()
+            .filter_map(|(key, slot)| slot.value.as_ref().map(|value| (key, value)))
+    }
+
+    /// Number of bound keys (excluding tombstones)
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Current vector clock
+    pub fn vector_clock(&self) -> &[u32] {
+        &self.clock.vector_clock
+    }
+
+    /// Local Put operation
+    /// Returns None once this site has used up its sequence numbers
+    pub fn put_local(&mut self, key: K, value: V) -> Option<RhtOp<K, V>> {
+        let s4v = self.clock.tick()?;
+        self.slots.insert(
+            key.clone(),
+            Slot {
+                value: Some(value.clone()),
+                s_p: s4v,
+            },
+        );
+
+        Some(RhtOp::Put {
+            key,
+            value,
+            s4v,
+            vector_clock: self.clock.vector_clock.clone(),
+        })
+    }
+
+    /// Local Remove operation
+    /// Returns None if `key` is not bound
+    pub fn remove_local(&mut self, key: &K) -> Option<RhtOp<K, V>> {
+        if !self.contains_key(key) {
+            return None;
+        }
+
+        let s4v = self.clock.tick()?;
+        self.slots.insert(
+            key.clone(),
+            Slot {
+                value: None,
+                s_p: s4v,
+            },
+        );
+
+        Some(RhtOp::Remove {
+            key: key.clone(),
+            s4v,
+            vector_clock: self.clock.vector_clock.clone(),
+        })
+    }
+
+    /// Apply an operation received from another site
+    ///
+    /// Returns whether the op took effect on its key; one that lost to a
+    /// newer Put or Remove (or was already applied) changes nothing.
+    pub fn apply_remote(&mut self, op: RhtOp<K, V>) -> Result<bool, ApplyError> {
+        validation::check_stamp(op.s4v(), op.vector_clock())?;
+        self.clock.merge(op.vector_clock());
+
+        let (key, value, s4v) = match op {
+            RhtOp::Put {
+                key, value, s4v, ..
+            } => (key, Some(value), s4v),
+            RhtOp::Remove { key, s4v, .. } => (key, None, s4v),
+        };
+
+        // Removes of keys not seen yet still leave a tombstone, so the Put
+        // they removed loses when it arrives
+        if let Some(slot) = self.slots.get(&key) {
+            if !slot.s_p.precedes(&s4v) {
+                return Ok(false);
+            }
+        }
+        self.slots.insert(key, Slot { value, s_p: s4v });
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Rht;
+
+    // Bound entries of a replica, sorted
+    fn entries(rht: &Rht<String, String>) -> Vec<(String, String)> {
+        let mut entries: Vec<_> = rht.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+        entries.sort();
+        entries
+    }
+
+    #[test]
+    fn test_put_and_remove_converge() {
+        let mut site0 = Rht::<String, String>::new(0, 2);
+        let mut site1 = Rht::<String, String>::new(1, 2);
+
+        let title = site0.put_local("title".into(), "Notes".into()).unwrap();
+        let tab = site0.put_local("tab_width".into(), "4".into()).unwrap();
+        site1.apply_remote(title).unwrap();
+        site1.apply_remote(tab).unwrap();
+
+        // Site 0 changes the title while site 1 removes it: the remove has
+        // the same sum and a higher site ID, so it wins everywhere
+        let retitle = site0.put_local("title".into(), "Plans".into()).unwrap();
+        let untitle = site1.remove_local(&"title".to_string()).unwrap();
+        assert!(site0.apply_remote(untitle.clone()).unwrap());
+        assert!(!site1.apply_remote(retitle.clone()).unwrap());
+        assert_eq!(entries(&site0), entries(&site1));
+        assert_eq!(site0.get(&"title".to_string()), None);
+
+        // Duplicates change nothing
+        assert!(!site0.apply_remote(untitle).unwrap());
+        assert!(!site1.apply_remote(retitle).unwrap());
+
+        // A put made after seeing the remove revives the key
+        let title = site0.put_local("title".into(), "Final".into()).unwrap();
+        assert!(site1.apply_remote(title).unwrap());
+        assert_eq!(entries(&site0), entries(&site1));
+        assert_eq!(site1.len(), 2);
+    }
+
+    #[test]
+    fn test_remove_before_its_put() {
+        let mut site0 = Rht::<String, String>::new(0, 3);
+        let mut site1 = Rht::<String, String>::new(1, 3);
+        let mut site2 = Rht::<String, String>::new(2, 3);
+
+        let put = site0.put_local("lang".into(), "en".into()).unwrap();
+        site1.apply_remote(put.clone()).unwrap();
+        let remove = site1.remove_local(&"lang".to_string()).unwrap();
+
+        // Site 2 gets the remove first; the put it removed must not win
+        assert!(site2.apply_remote(remove).unwrap());
+        assert!(!site2.apply_remote(put).unwrap());
+        assert!(site2.is_empty());
+        assert_eq!(site2.vector_clock(), site1.vector_clock());
+    }
+}
@@ -1,18 +1,50 @@
+// S4Vectors and their counters
+//
+// `seq` counts the ops of one site and `sum` adds up a whole vector clock,
+// so with many sites and long histories the sum outgrows any single
+// counter: it is kept as a u64, which holds the sum of `MAX_CLOCK_SITES`
+// full u32 counters, and the ordering by (ssn, sum, sid) stays total.
+// Sequence numbers stay u32; a site that runs out of them cannot generate
+// ops until the document rolls over to a new session (see `session.rs`),
+// and remote ops that would go past the limit are rejected.
+
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct S4Vector {
     pub ssn: u32,
     pub sid: u32,
-    pub sum: u32,
+    pub sum: u64,
     pub seq: u32,
 }
 
 impl S4Vector {
-    pub fn new(ssn: u32, sid: u32, sum: u32, seq: u32) -> Self {
+    pub fn new(ssn: u32, sid: u32, sum: u64, seq: u32) -> Self {
         S4Vector { ssn, sid, sum, seq }
     }
 
+    // S4Vector of the element `k` positions into a run starting at `self`
+    // A run of n values takes n consecutive sequence numbers, exactly as if
+    // its site had inserted them one by one
+    // Saturates at the counter limits: validation rejects runs that would
+    // go past them, so within an applied op this is exact
+    pub fn offset(&self, k: u32) -> S4Vector {
+        S4Vector {
+            sum: self.sum.saturating_add(u64::from(k)),
+            seq: self.seq.saturating_add(k),
+            ..*self
+        }
+    }
+
+    // Like `offset`, but None if the sequence number would overflow
+    pub fn checked_offset(&self, k: u32) -> Option<S4Vector> {
+        Some(S4Vector {
+            sum: self.sum.checked_add(u64::from(k))?,
+            seq: self.seq.checked_add(k)?,
+            ..*self
+        })
+    }
+
     pub fn precedes(&self, other: &S4Vector) -> bool {
         // order by session number
         if self.ssn != other.ssn {
@@ -29,6 +61,11 @@ impl S4Vector {
     }
 }
 
+// Sum of a vector clock, as used for S4Vector sums
+pub(crate) fn clock_sum(clock: &[u32]) -> u64 {
+    clock.iter().map(|&count| u64::from(count)).sum()
+}
+
 impl PartialOrd for S4Vector {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
         Some(self.cmp(other))
@@ -49,6 +86,7 @@ impl Ord for S4Vector {
 
 #[cfg(test)]
 mod tests {
+    use super::clock_sum;
     use crate::S4Vector;
 
     #[test]
@@ -65,4 +103,31 @@ mod tests {
         assert!(v2 < v3);
         assert!(v1 < v3);
     }
+
+    #[test]
+    fn test_ordering_near_counter_limits() {
+        // Two sites with full counters sum past u32::MAX without wrapping
+        let full = vec![u32::MAX, u32::MAX];
+        let sum = clock_sum(&full);
+        assert_eq!(sum, 2 * u64::from(u32::MAX));
+
+        let low = S4Vector::new(1, 1, u64::from(u32::MAX), u32::MAX);
+        let high = S4Vector::new(1, 0, sum, u32::MAX);
+        let tie = S4Vector::new(1, 1, sum, u32::MAX);
+        let next_session = S4Vector::new(2, 0, 1, 1);
+
+        let mut ids = vec![next_session, tie, high, low];
+        ids.sort();
+        assert_eq!(ids, vec![low, high, tie, next_session]);
+        assert!(low.precedes(&high) && !high.precedes(&low));
+        assert!(!tie.precedes(&tie));
+    }
+
+    #[test]
+    fn test_offset_at_counter_limits() {
+        let last = S4Vector::new(1, 0, u64::from(u32::MAX), u32::MAX - 1);
+        assert_eq!(last.checked_offset(1).map(|id| id.seq), Some(u32::MAX));
+        assert_eq!(last.checked_offset(2), None);
+        assert_eq!(last.offset(2).seq, u32::MAX);
+    }
 }
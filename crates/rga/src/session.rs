@@ -0,0 +1,243 @@
+// Session rollover
+//
+// S4Vectors order by session number first, so a group of replicas that all
+// reached the same checkpoint can start over with small counters. Each of
+// them rebuilds its document from the visible elements alone, as if their
+// authors had typed them again in document order: maximal runs of one
+// author become InsertRuns of the next session, each placed after the
+// previous one and stamped with a vector clock counting only the elements
+// inserted so far. Every replica at the checkpoint derives the same runs,
+// hence the same S4Vectors, without exchanging anything but the checkpoint.
+//
+// Tombstones, undo history, edit history, moves and the operation log of the
+// old session are dropped; the runs replace the log, so a replica joining
+// later replays them like any other ops. Ops of another session are
+// rejected, since the elements they refer to no longer exist under those
+// S4Vectors.
+
+use crate::remote_op::RemoteOp;
+use crate::rga::Rga;
+use crate::s4vector::{self, S4Vector};
+use crate::validation::ApplyError;
+
+impl<T: Clone> Rga<T> {
+    /// Create a replica for a document in session `session`, to be filled
+    /// by replaying the operations logged in that session
+    pub fn with_session(site_id: u32, num_sites: usize, session: u32) -> Self {
+        Rga {
+            session,
+            ..Rga::new(site_id, num_sites)
+        }
+    }
+
+    /// Session this replica's S4Vectors belong to
+    pub fn session(&self) -> u32 {
+        self.session
+    }
+
+    /// Start the next session from the state at `checkpoint`
+    ///
+    /// Every replica must call this with the same checkpoint after having
+    /// integrated exactly the ops it covers and nothing else; they then end
+    /// up with identical S4Vectors for the same text. Anchors and marks of
+    /// the old session no longer resolve afterwards (see
+    /// `Marks::start_session`). Returns the new session number.
+    ///
+    /// This is also how a site that has used up its sequence numbers, and
+    /// so can no longer generate local ops, gets to edit again.
+    pub fn start_session(&mut self, checkpoint: &[u32]) -> Result<u32, ApplyError> {
+        let sites = self.vector_clock.len().max(checkpoint.len());
+        let at_checkpoint = (0..sites).all(|site| {
+            self.vector_clock.get(site).copied().unwrap_or(0)
+                == checkpoint.get(site).copied().unwrap_or(0)
+        });
+        if !at_checkpoint || !self.pending.is_empty() {
+            return Err(ApplyError::NotAtCheckpoint);
+        }
+
+        let session = self
+            .session
+            .checked_add(1)
+            .ok_or(ApplyError::CounterOverflow)?;
+        let mut rga = Rga::with_session(self.site_id, sites, session);
+        for op in self.session_base(session, sites) {
+            rga.apply_remote(op)?;
+        }
+        rga.observer = self.observer.take();
+        rga.set_undo_limit(self.history.limit);
+        *self = rga;
+        Ok(session)
+    }
+
+    // Visible elements as InsertRuns of `session`, one per maximal run of a
+    // single author, in document order
+    fn session_base(&self, session: u32, sites: usize) -> Vec<RemoteOp<T>> {
+        let mut runs: Vec<(u32, Vec<T>)> = Vec::new();
+        let mut current = self.head;
+        while let Some(slot) = current {
+            let node = &self.nodes[slot];
+            if let Some(values) = &node.obj {
+                match runs.last_mut() {
+                    Some((sid, run)) if *sid == node.s_k.sid => run.extend_from_slice(values),
+                    _ => runs.push((node.s_k.sid, values.clone())),
+                }
+            }
+            current = node.link;
+        }
+
+        let mut clock = vec![0u32; sites];
+        let mut left_id: Option<S4Vector> = None;
+        let mut ops = Vec::with_capacity(runs.len());
+        for (sid, values) in runs {
+            let count = values.len() as u32;
+            // Each author has at most as many visible elements as it had
+            // sequence numbers, so the new counters cannot overflow
+            let sum = s4vector::clock_sum(&clock);
+            let s4v = S4Vector::new(session, sid, sum + 1, clock[sid as usize] + 1);
+            clock[sid as usize] += count;
+
+            ops.push(RemoteOp::InsertRun {
+                left_id,
+                values,
+                s4v,
+                vector_clock: clock.clone(),
+            });
+            left_id = Some(s4v.offset(count - 1));
+        }
+        ops
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ApplyError, Expand, Marks, Rga};
+    use serde_json::json;
+
+    fn text(rga: &Rga<char>) -> String {
+        rga.read().into_iter().collect()
+    }
+
+    #[test]
+    fn test_rollover_renumbers_identically() {
+        let mut site0 = Rga::<char>::new(0, 2);
+        let mut site1 = Rga::<char>::new(1, 2);
+        for op in [
+            site0.insert_run_local(0, "hello world".chars().collect()),
+            site0.delete_range_local(0, 6),
+            site0.update_local(0, 'W'),
+        ] {
+            site1.apply_remote(op.unwrap()).unwrap();
+        }
+        site0
+            .apply_remote(site1.insert_run_local(5, "!!".chars().collect()).unwrap())
+            .unwrap();
+        let stale = site1.insert_local(0, '>').unwrap();
+        site1.undo_local().unwrap();
+
+        // Site 1 is past the checkpoint until site 0 has its edits too
+        let checkpoint = site0.vector_clock().to_vec();
+        assert_eq!(
+            site1.start_session(&checkpoint),
+            Err(ApplyError::NotAtCheckpoint)
+        );
+        let missing = site1.ops_since(site0.vector_clock()).unwrap();
+        for op in missing {
+            site0.apply_remote(op).unwrap();
+        }
+        let checkpoint = site0.vector_clock().to_vec();
+
+        assert_eq!(site0.start_session(&checkpoint), Ok(2));
+        assert_eq!(site1.start_session(&checkpoint), Ok(2));
+        assert_eq!(text(&site0), "World!!");
+        assert_eq!(site0.vector_clock(), &[5, 2]);
+        assert_eq!(site0.tombstone_count(), 0);
+        assert!(!site1.can_undo());
+        for i in 0..7 {
+            assert_eq!(site0.id_at(i), site1.id_at(i));
+        }
+
+        // Old ops are refused; new ones take the small counters
+        assert_eq!(
+            site0.apply_remote(stale),
+            Err(ApplyError::WrongSession {
+                expected: 2,
+                found: 1
+            })
+        );
+        let op = site1.insert_local(0, '>').unwrap();
+        assert_eq!(op.s4v().seq, 3);
+        site0.apply_remote(op).unwrap();
+        assert_eq!(text(&site0), ">World!!");
+    }
+
+    #[test]
+    fn test_late_replica_replays_session_base() {
+        let mut site0 = Rga::<char>::new(0, 1);
+        site0.insert_run_local(0, "abc".chars().collect());
+        site0.move_range_local(0, 1, 3);
+        site0.delete_local(1);
+        let checkpoint = site0.vector_clock().to_vec();
+        site0.start_session(&checkpoint).unwrap();
+        site0.insert_local(2, 'x');
+
+        let mut late = Rga::<char>::with_session(1, 2, site0.session());
+        for op in site0.ops_since(late.vector_clock()).unwrap() {
+            late.apply_remote(op).unwrap();
+        }
+        assert_eq!(text(&late), "bax");
+        assert_eq!(late.session(), 2);
+        assert_eq!(late.vector_clock(), &[3, 0]);
+    }
+
+    #[test]
+    fn test_marks_follow_rollover() {
+        let mut rga = Rga::<char>::new(0, 1);
+        rga.insert_run_local(0, "one two".chars().collect());
+        let mut marks = Marks::new(0, 1);
+        marks.mark_local(&rga, 4..7, "bold", json!(true), Expand::After);
+        rga.delete_range_local(0, 4);
+
+        let checkpoint = rga.vector_clock().to_vec();
+        marks.start_session(&mut rga, &checkpoint).unwrap();
+        rga.insert_local(3, 's');
+
+        let spans = marks.format_spans(&rga);
+        assert_eq!(text(&rga), "twos");
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0].len, 4);
+        assert_eq!(spans[0].attributes.get("bold"), Some(&json!(true)));
+    }
+
+    #[test]
+    fn test_exhausted_site_rolls_over() {
+        let mut site0 = Rga::<char>::new(0, 2);
+        let mut site1 = Rga::<char>::new(1, 2);
+        site1
+            .apply_remote(site0.insert_run_local(0, "ab".chars().collect()).unwrap())
+            .unwrap();
+
+        // Site 1 has one sequence number left
+        site1.vector_clock[1] = u32::MAX - 1;
+        assert!(site1.insert_run_local(2, "cd".chars().collect()).is_none());
+        let op = site1.insert_local(2, 'c').unwrap();
+        assert_eq!(op.s4v().seq, u32::MAX);
+        assert_eq!(op.s4v().sum, u64::from(u32::MAX) + 2);
+        assert!(site1.insert_local(3, 'd').is_none());
+        assert!(site1.delete_local(0).is_none());
+        assert_eq!(site1.vector_clock(), &[2, u32::MAX]);
+
+        // A new session gives it small counters again
+        let checkpoint = site1.vector_clock().to_vec();
+        assert_eq!(site1.start_session(&checkpoint), Ok(2));
+        let op = site1.insert_local(3, 'd').unwrap();
+        assert_eq!(op.s4v().seq, 2);
+        assert_eq!(text(&site1), "abcd");
+    }
+
+    #[test]
+    fn test_rejects_last_session() {
+        let mut rga = Rga::<char>::with_session(0, 1, u32::MAX);
+        assert_eq!(rga.start_session(&[]), Err(ApplyError::CounterOverflow));
+        assert_eq!(rga.session(), u32::MAX);
+    }
+}
@@ -0,0 +1,56 @@
+// Local S4Vector generation for the RFA and RHT types
+//
+// Their operations carry S4Vectors and vector clocks exactly like RGA ops,
+// but need none of the RGA's buffering or run bookkeeping, so the clock is
+// kept on its own.
+
+use crate::s4vector::{self, S4Vector};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct SiteClock {
+    pub(crate) site_id: u32,
+    pub(crate) session: u32,
+    pub(crate) vector_clock: Vec<u32>,
+}
+
+impl SiteClock {
+    // `num_sites` only sizes the initial vector clock, as for `Rga::new`
+    pub(crate) fn new(site_id: u32, num_sites: usize) -> Self {
+        SiteClock {
+            site_id,
+            session: 1,
+            vector_clock: vec![0; num_sites],
+        }
+    }
+
+    // Advance the local entry and return the S4Vector of the new operation
+    // None, leaving the clock untouched, once the local entry is exhausted
+    pub(crate) fn tick(&mut self) -> Option<S4Vector> {
+        let site = self.site_id as usize;
+        self.grow(site + 1);
+        self.vector_clock[site] = self.vector_clock[site].checked_add(1)?;
+        let sum = s4vector::clock_sum(&self.vector_clock);
+
+        Some(S4Vector::new(
+            self.session,
+            self.site_id,
+            sum,
+            self.vector_clock[site],
+        ))
+    }
+
+    // Merge the vector clock of an integrated remote operation
+    pub(crate) fn merge(&mut self, vector_clock: &[u32]) {
+        self.grow(vector_clock.len());
+        for (count, &other) in self.vector_clock.iter_mut().zip(vector_clock) {
+            *count = (*count).max(other);
+        }
+    }
+
+    fn grow(&mut self, len: usize) {
+        if self.vector_clock.len() < len {
+            self.vector_clock.resize(len, 0);
+        }
+    }
+}
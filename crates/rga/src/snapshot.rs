@@ -0,0 +1,304 @@
+// Full-replica serialization for Rga
+//
+// A snapshot captures everything needed to resume a replica losslessly:
+// node order, s_k/s_p and edit history of every node (tombstones included),
+// vector clock, session, cemetery, acknowledged site clocks, buffered remote
+// ops, the operation log used for anti-entropy, the applied sequence
+// numbers used to recognise retransmitted ops and the locations of moved
+// elements.
+// The same state is used for serde (e.g. JSON) and the compact binary form.
+
+use crate::moves::{Locations, MoveTable};
+use crate::node::Node;
+use crate::op_log::OpLog;
+use crate::remote_op::RemoteOp;
+use crate::rga::{run_key, Rga};
+use crate::s4vector::S4Vector;
+use crate::validation::AppliedSeqs;
+use serde::de::{DeserializeOwned, Error as _};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+
+// Current snapshot format version
+pub const SNAPSHOT_VERSION: u32 = 8;
+
+// Errors produced when encoding or restoring a replica snapshot
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SnapshotError {
+    // Binary encoding/decoding failed
+    Codec(String),
+
+    // Snapshot was written by an unknown format version
+    UnsupportedVersion(u32),
+
+    // Snapshot contents are inconsistent
+    Corrupt(&'static str),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Codec(e) => write!(f, "snapshot codec error: {e}"),
+            SnapshotError::UnsupportedVersion(v) => {
+                write!(f, "unsupported snapshot version {v}")
+            }
+            SnapshotError::Corrupt(reason) => write!(f, "corrupt snapshot: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+// Serialized form of a replica
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ReplicaState<T: Clone> {
+    version: u32,
+    site_id: u32,
+    session: u32,
+    vector_clock: Vec<u32>,
+
+    // Nodes (runs) in document order, tombstones included
+    nodes: Vec<Node<T>>,
+
+    cemetery: Vec<S4Vector>,
+
+    // Sorted (by site ID / S4Vector) so identical replicas encode identically
+    site_clocks: Vec<(u32, Vec<u32>)>,
+
+    // Remote ops still waiting for a causal predecessor
+    pending: Vec<RemoteOp<T>>,
+
+    // Operations not yet compacted out of the log
+    log: OpLog<T>,
+
+    applied: AppliedSeqs,
+
+    // Moved elements, sorted by insert ID
+    moves: Vec<Locations>,
+}
+
+impl<T: Clone> Rga<T> {
+    // Capture the complete replica state
+    fn to_state(&self) -> ReplicaState<T> {
+        let mut nodes = Vec::with_capacity(self.runs.len());
+        let mut current = self.head;
+        while let Some(slot) = current {
+            let node = &self.nodes[slot];
+            nodes.push(Node {
+                link: None,
+                ..node.clone()
+            });
+            current = node.link;
+        }
+
+        let mut site_clocks: Vec<(u32, Vec<u32>)> = self
+            .site_clocks
+            .iter()
+            .map(|(&site, clock)| (site, clock.clone()))
+            .collect();
+        site_clocks.sort_unstable_by_key(|(site, _)| *site);
+
+        let mut pending: Vec<RemoteOp<T>> = self.pending_ops().cloned().collect();
+        pending.sort_by_key(RemoteOp::s4v);
+
+        ReplicaState {
+            version: SNAPSHOT_VERSION,
+            site_id: self.site_id,
+            session: self.session,
+            vector_clock: self.vector_clock.clone(),
+            nodes,
+            cemetery: self.cemetery.clone(),
+            site_clocks,
+            pending,
+            log: self.log.clone(),
+            applied: self.applied.clone(),
+            moves: self.moves.to_entries(),
+        }
+    }
+
+    // Rebuild a replica (arena, SVI hash map and position index) from its state
+    fn from_state(state: ReplicaState<T>) -> Result<Self, SnapshotError> {
+        if state.version != SNAPSHOT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion(state.version));
+        }
+
+        let mut rga = Rga::new(state.site_id, state.vector_clock.len());
+        rga.session = state.session;
+        rga.vector_clock = state.vector_clock;
+        rga.site_clocks = state.site_clocks.into_iter().collect();
+        rga.log = state.log;
+        rga.applied = state.applied;
+        rga.moves = MoveTable::from_entries(state.moves);
+
+        let mut prev: Option<(usize, S4Vector)> = None;
+        for node in state.nodes {
+            let (id, len) = (node.s_k, node.len as usize);
+            let visible = !node.is_tombstone();
+            let history_len = node.history.as_ref().map(|history| history.inserted.len());
+            if len == 0
+                || node.obj.as_ref().is_some_and(|values| values.len() != len)
+                || history_len.is_some_and(|history_len| history_len != len)
+            {
+                return Err(SnapshotError::Corrupt(
+                    "run length does not match its values",
+                ));
+            }
+
+            rga.nodes.push(node);
+            let slot = rga.nodes.len() - 1;
+            if rga.runs.insert(run_key(&id), slot).is_some() {
+                return Err(SnapshotError::Corrupt("duplicate node identifier"));
+            }
+
+            match prev {
+                Some((prev_slot, _)) => rga.nodes[prev_slot].link = Some(slot),
+                None => rga.head = Some(slot),
+            }
+            rga.index
+                .insert_after(prev.as_ref().map(|(_, prev_id)| prev_id), id, len, visible);
+            prev = Some((slot, id));
+        }
+
+        let cemetery_valid = state.cemetery.iter().all(|id| {
+            rga.runs
+                .get(&run_key(id))
+                .is_some_and(|&slot| rga.nodes[slot].is_tombstone())
+        });
+        if !cemetery_valid {
+            return Err(SnapshotError::Corrupt("cemetery entry is not a tombstone"));
+        }
+        rga.cemetery = state.cemetery;
+
+        for op in state.pending {
+            let missing = rga.missing_op(&op).ok_or(SnapshotError::Corrupt(
+                "pending op whose predecessors were all integrated",
+            ))?;
+            rga.pending.entry(missing).or_default().push(op);
+        }
+
+        Ok(rga)
+    }
+}
+
+impl<T: Clone + Serialize> Rga<T> {
+    /// Encode the complete replica in the compact binary format
+    pub fn to_bytes(&self) -> Result<Vec<u8>, SnapshotError> {
+        bincode::serialize(&self.to_state()).map_err(|e| SnapshotError::Codec(e.to_string()))
+    }
+}
+
+impl<T: Clone + DeserializeOwned> Rga<T> {
+    /// Restore a replica from bytes produced by `to_bytes`
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SnapshotError> {
+        // The version leads the encoding; check it first, since older
+        // layouts (e.g. the u32 S4Vector sums before version 8) do not
+        // decode as the current one
+        let version: u32 =
+            bincode::deserialize(bytes).map_err(|e| SnapshotError::Codec(e.to_string()))?;
+        if version != SNAPSHOT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+
+        let state: ReplicaState<T> =
+            bincode::deserialize(bytes).map_err(|e| SnapshotError::Codec(e.to_string()))?;
+        Rga::from_state(state)
+    }
+}
+
+impl<T: Clone + Serialize> Serialize for Rga<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.to_state().serialize(serializer)
+    }
+}
+
+impl<'de, T: Clone + Deserialize<'de>> Deserialize<'de> for Rga<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let state = ReplicaState::deserialize(deserializer)?;
+        Rga::from_state(state).map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{RemoteOp, Rga, S4Vector, SnapshotError};
+
+    // Two replicas with tombstones, updates and a buffered op
+    fn edited_replicas() -> (Rga<char>, Rga<char>) {
+        let mut site0 = Rga::<char>::new(0, 2);
+        let mut site1 = Rga::<char>::new(1, 2);
+
+        for (i, ch) in "hello".chars().enumerate() {
+            site1
+                .apply_remote(site0.insert_local(i, ch).unwrap())
+                .unwrap();
+        }
+        site0.apply_remote(site1.delete_local(1).unwrap()).unwrap();
+        site0
+            .apply_remote(site1.update_local(0, 'H').unwrap())
+            .unwrap();
+
+        // Op whose left cobject never arrived
+        site0
+            .apply_remote(RemoteOp::Insert {
+                left_id: Some(S4Vector::new(1, 1, 14, 9)),
+                value: '!',
+                s4v: S4Vector::new(1, 1, 15, 10),
+                vector_clock: vec![5, 10],
+            })
+            .unwrap();
+
+        (site0, site1)
+    }
+
+    #[test]
+    fn test_binary_round_trip_is_lossless() {
+        let (site0, mut site1) = edited_replicas();
+
+        let bytes = site0.to_bytes().unwrap();
+        let mut restored = Rga::<char>::from_bytes(&bytes).unwrap();
+
+        assert_eq!(restored.read(), site0.read());
+        assert_eq!(restored.vector_clock(), site0.vector_clock());
+        assert_eq!(restored.tombstone_count(), site0.tombstone_count());
+        assert_eq!(restored.pending_count(), 1);
+        assert_eq!(restored.log_len(), site0.log_len());
+        assert_eq!(restored.to_bytes().unwrap(), bytes);
+
+        // Identifiers survive, so ops against the old replica still apply
+        restored
+            .apply_remote(site1.insert_local(2, 'x').unwrap())
+            .unwrap();
+        site1
+            .apply_remote(restored.delete_local(0).unwrap())
+            .unwrap();
+        assert_eq!(restored.read(), site1.read());
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let (site0, _) = edited_replicas();
+
+        let json = serde_json::to_string(&site0).unwrap();
+        let restored: Rga<char> = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(restored.to_bytes().unwrap(), site0.to_bytes().unwrap());
+    }
+
+    #[test]
+    fn test_rejects_older_version() {
+        let (site0, _) = edited_replicas();
+        let mut bytes = site0.to_bytes().unwrap();
+        bytes[..4].copy_from_slice(&7u32.to_le_bytes());
+
+        assert_eq!(
+            Rga::<char>::from_bytes(&bytes).unwrap_err(),
+            SnapshotError::UnsupportedVersion(7)
+        );
+    }
+
+    #[test]
+    fn test_rejects_garbage() {
+        let result = Rga::<char>::from_bytes(&[0xff; 3]);
+        assert!(matches!(result, Err(SnapshotError::Codec(_))));
+    }
+}
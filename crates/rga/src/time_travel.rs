@@ -0,0 +1,216 @@
+// Reads of an Rga as of a past causal point
+//
+// Nodes never lose what a Delete or Update replaced (see `node::History`),
+// so the document at any vector clock can be rebuilt from the current list:
+// an element is present if its insert is covered by the clock and none of
+// the covered Deletes hit it, and it shows the covered Update with the
+// greatest S4Vector, exactly as a replica that had only received those
+// operations would.
+
+use crate::node::Node;
+use crate::rga::Rga;
+use crate::s4vector::S4Vector;
+use std::cmp::Ordering;
+
+// Whether the operation `s4v` is covered by `vector_clock` of `session`
+// Every op of an earlier session happened before the clock
+fn covers(session: u32, vector_clock: &[u32], s4v: &S4Vector) -> bool {
+    match s4v.ssn.cmp(&session) {
+        Ordering::Less => true,
+        Ordering::Greater => false,
+        Ordering::Equal => vector_clock
+            .get(s4v.sid as usize)
+            .is_some_and(|&count| count >= s4v.seq),
+    }
+}
+
+// Number of leading elements of `node` whose insert is covered
+// Elements of a run have consecutive seq, so covered ones form a prefix
+fn covered_len<T: Clone>(node: &Node<T>, session: u32, vector_clock: &[u32]) -> usize {
+    match node.s_k.ssn.cmp(&session) {
+        Ordering::Less => return node.len as usize,
+        Ordering::Greater => return 0,
+        Ordering::Equal => {}
+    }
+    let count = vector_clock
+        .get(node.s_k.sid as usize)
+        .copied()
+        .unwrap_or(0);
+    count
+        .saturating_add(1)
+        .saturating_sub(node.s_k.seq)
+        .min(node.len) as usize
+}
+
+impl<T: Clone> Rga<T> {
+    /// Document as it was at `vector_clock`
+    ///
+    /// Includes only elements whose insert is covered by the clock, and
+    /// applies only the Deletes and Updates it covers, so passing an earlier
+    /// value of `vector_clock()` reproduces what `read` returned back then.
+    /// Tombstones removed by `purge_tombstones` are gone for good: reading
+    /// at a clock from before their deletion leaves them out.
+    ///
+    /// `session` is the session the clock was taken in. Returns None for a
+    /// clock of another session: `start_session` renumbers every element,
+    /// so earlier clocks no longer match them, and later ones are unknown.
+    pub fn read_at(&self, session: u32, vector_clock: &[u32]) -> Option<Vec<T>> {
+        if session != self.session {
+            return None;
+        }
+
+        let mut result = Vec::new();
+        let mut current = self.head;
+
+        while let Some(slot) = current {
+            let node = &self.nodes[slot];
+            current = node.link;
+
+            let len = covered_len(node, session, vector_clock);
+            if len == 0 {
+                continue;
+            }
+
+            let Some(history) = &node.history else {
+                // Untouched run: current values are the inserted ones
+                if let Some(values) = &node.obj {
+                    result.extend_from_slice(&values[..len]);
+                }
+                continue;
+            };
+
+            if history
+                .deletes
+                .iter()
+                .any(|s4v| covers(session, vector_clock, s4v))
+            {
+                continue;
+            }
+
+            let latest_update = history
+                .updates
+                .iter()
+                .filter(|(s4v, _)| covers(session, vector_clock, s4v))
+                .max_by_key(|(s4v, _)| *s4v);
+            match latest_update {
+                Some((_, value)) => result.push(value.clone()),
+                None => result.extend_from_slice(&history.inserted[..len]),
+            }
+        }
+
+        Some(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Rga;
+
+    #[test]
+    fn test_read_at_replays_local_history() {
+        let mut rga = Rga::<char>::new(0, 1);
+        let mut clocks = Vec::new();
+        let mut states = Vec::new();
+        let mut record = |rga: &Rga<char>| {
+            clocks.push(rga.vector_clock().to_vec());
+            states.push(rga.read());
+        };
+
+        record(&rga);
+        rga.insert_run_local(0, "hello world".chars().collect());
+        record(&rga);
+        rga.delete_range_local(5, 6);
+        record(&rga);
+        rga.update_local(0, 'H');
+        record(&rga);
+        rga.insert_local(5, '!');
+        record(&rga);
+        rga.update_local(0, 'J');
+        rga.delete_local(1);
+        record(&rga);
+
+        for (clock, state) in clocks.iter().zip(&states) {
+            assert_eq!(rga.read_at(rga.session(), clock).as_ref(), Some(state));
+        }
+    }
+
+    #[test]
+    fn test_read_at_partially_covered_run() {
+        let mut rga = Rga::<char>::new(0, 1);
+        rga.insert_local(0, 'a');
+        rga.insert_local(1, 'b');
+        rga.insert_local(2, 'c');
+
+        // Typing extended one run; a clock can cover only part of it
+        assert_eq!(rga.node_count(), 1);
+        assert_eq!(rga.read_at(rga.session(), &[2]), Some(vec!['a', 'b']));
+        assert_eq!(rga.read_at(rga.session(), &[]), Some(Vec::<char>::new()));
+    }
+
+    #[test]
+    fn test_read_at_rejects_other_sessions() {
+        let mut rga = Rga::<char>::new(0, 1);
+        rga.insert_run_local(0, "abc".chars().collect());
+        let old = rga.vector_clock().to_vec();
+        let session = rga.start_session(&old).unwrap();
+        rga.insert_local(3, 'd');
+
+        // Counters restart with the session, so the old clock would
+        // wrongly cover the new insert
+        assert_eq!(rga.read_at(session - 1, &old), None);
+        assert_eq!(rga.read_at(session + 1, &[]), None);
+        assert_eq!(rga.read_at(session, &[]), Some(Vec::new()));
+        assert_eq!(rga.read_at(session, rga.vector_clock()), Some(rga.read()));
+    }
+
+    #[test]
+    fn test_read_at_concurrent_edits() {
+        let mut site0 = Rga::<char>::new(0, 2);
+        let mut site1 = Rga::<char>::new(1, 2);
+
+        site1
+            .apply_remote(site0.insert_run_local(0, "abc".chars().collect()).unwrap())
+            .unwrap();
+        let base = site0.vector_clock().to_vec();
+
+        // Site 0 updates 'a' while site 1 updates it too and deletes 'c'
+        let update0 = site0.update_local(0, 'x').unwrap();
+        let update1 = site1.update_local(0, 'y').unwrap();
+        let delete1 = site1.delete_local(2).unwrap();
+        site0.apply_remote(update1).unwrap();
+        site0.apply_remote(delete1).unwrap();
+        site1.apply_remote(update0).unwrap();
+        assert_eq!(site0.read(), site1.read());
+
+        // Only site 1's edits: its update wins even if it lost overall
+        let only_site1 = [base[0], site1.vector_clock()[1]];
+        assert_eq!(
+            site0.read_at(site0.session(), &only_site1),
+            Some(vec!['y', 'b'])
+        );
+        assert_eq!(
+            site1.read_at(site1.session(), &only_site1),
+            Some(vec!['y', 'b'])
+        );
+
+        // Only site 0's edits: the delete is not applied yet
+        let only_site0 = [site0.vector_clock()[0], base[1]];
+        assert_eq!(
+            site0.read_at(site0.session(), &only_site0),
+            Some(vec!['x', 'b', 'c'])
+        );
+        assert_eq!(
+            site1.read_at(site1.session(), &only_site0),
+            Some(vec!['x', 'b', 'c'])
+        );
+
+        assert_eq!(
+            site0.read_at(site0.session(), site0.vector_clock()),
+            Some(site0.read())
+        );
+        assert_eq!(
+            site0.read_at(site0.session(), &base),
+            Some(vec!['a', 'b', 'c'])
+        );
+    }
+}
@@ -0,0 +1,438 @@
+// Selective per-site undo/redo for Rga
+//
+// Only edits generated by this site are recorded. Undoing one produces a
+// fresh RemoteOp that inverts it, so other replicas simply apply it like any
+// other operation and edits made concurrently by other sites are kept.
+
+use crate::remote_op::{self, RemoteOp};
+use crate::rga::Rga;
+use crate::s4vector::S4Vector;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+// Default number of local edits remembered for undo
+const MAX_UNDO_DEPTH: usize = 1024;
+
+// A local edit generated by this site
+// Each variant holds what is needed to invert it
+#[derive(Debug, Clone)]
+pub(crate) enum LocalEdit<T: Clone> {
+    // The elements in `spans` were inserted; inverted by deleting those
+    // that are still visible
+    Insert {
+        spans: Vec<(S4Vector, u32)>,
+    },
+
+    // The elements in `spans` holding `values` were deleted; inverted by
+    // inserting `values` again as one run at the first tombstone's position.
+    // `last_updates` are the elements' s_p before the delete, to carry
+    // earlier updates over to the new elements
+    Delete {
+        spans: Vec<(S4Vector, u32)>,
+        values: Vec<T>,
+        last_updates: Vec<S4Vector>,
+    },
+
+    // Element `id` was updated by `s4v` from `previous`; inverted by
+    // restoring `previous` as long as no later update has overridden ours
+    Update {
+        id: S4Vector,
+        s4v: S4Vector,
+        previous: T,
+    },
+
+    // The visible elements `elements` were moved by `s4v`, element k to
+    // s4v.offset(k), from just after `origin` (None = head); inverted by
+    // moving back those our Move still holds in place
+    Move {
+        elements: Vec<S4Vector>,
+        s4v: S4Vector,
+        origin: Option<S4Vector>,
+    },
+
+    // The edits of one transaction, in the order they were made; inverted
+    // as a whole by inverting each of them, last first, into one Batch
+    Batch {
+        edits: Vec<LocalEdit<T>>,
+    },
+}
+
+// Replacement of a deleted element by its re-inserted copy:
+// old id -> (new id, s_p of the old element before it was deleted)
+type Retargets = HashMap<S4Vector, (S4Vector, S4Vector)>;
+
+impl<T: Clone> LocalEdit<T> {
+    // Point an edit on deleted elements at the elements that replaced them
+    // Our own update that was current at deletion time stays current on the
+    // new element, whose s_p is its insert S4Vector
+    fn retarget(&mut self, retargets: &Retargets) {
+        match self {
+            LocalEdit::Insert { spans } | LocalEdit::Delete { spans, .. } => {
+                let mut mapped = Vec::with_capacity(spans.len());
+                for id in remote_op::span_elements(spans) {
+                    let new_id = retargets.get(&id).map_or(id, |(new_id, _)| *new_id);
+                    remote_op::push_span(&mut mapped, new_id);
+                }
+                *spans = mapped;
+            }
+            LocalEdit::Update { id, s4v, .. } => {
+                if let Some((new_id, last_update)) = retargets.get(id) {
+                    if s4v == last_update {
+                        *s4v = *new_id;
+                    }
+                    *id = *new_id;
+                }
+            }
+            LocalEdit::Move { origin, .. } => {
+                if let Some((new_id, _)) = origin.and_then(|id| retargets.get(&id)) {
+                    *origin = Some(*new_id);
+                }
+            }
+            LocalEdit::Batch { edits } => {
+                for edit in edits {
+                    edit.retarget(retargets);
+                }
+            }
+        }
+    }
+
+    // Tombstones that inverting this edit would insert next to
+    fn deleted_ids(&self) -> Vec<S4Vector> {
+        match self {
+            LocalEdit::Delete { spans, .. } => remote_op::span_elements(spans).collect(),
+            LocalEdit::Batch { edits } => edits.iter().flat_map(LocalEdit::deleted_ids).collect(),
+            LocalEdit::Insert { .. } | LocalEdit::Update { .. } | LocalEdit::Move { .. } => {
+                Vec::new()
+            }
+        }
+    }
+}
+
+// Undo and redo stacks of local edits
+#[derive(Debug, Clone)]
+pub(crate) struct UndoHistory<T: Clone> {
+    undo: VecDeque<LocalEdit<T>>,
+    redo: Vec<LocalEdit<T>>,
+
+    // Edits of the transaction in progress, recorded as one when it ends
+    pub(crate) batch: Option<Vec<LocalEdit<T>>>,
+
+    // While a Batch is being inverted: its edits still to invert followed
+    // by the inverses made so far, kept here so that they are retargeted
+    inverting: Vec<LocalEdit<T>>,
+
+    // Most edits kept on each stack; 0 records none
+    pub(crate) limit: usize,
+}
+
+impl<T: Clone> Default for UndoHistory<T> {
+    fn default() -> Self {
+        UndoHistory {
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+            batch: None,
+            inverting: Vec::new(),
+            limit: MAX_UNDO_DEPTH,
+        }
+    }
+}
+
+impl<T: Clone> UndoHistory<T> {
+    fn push_undo(&mut self, edit: LocalEdit<T>) {
+        if self.limit == 0 {
+            return;
+        }
+        if self.undo.len() >= self.limit {
+            self.undo.pop_front();
+        }
+        self.undo.push_back(edit);
+    }
+
+    // Tombstones that undoing or redoing a delete would insert next to
+    pub(crate) fn deleted_ids(&self) -> HashSet<S4Vector> {
+        self.undo
+            .iter()
+            .chain(&self.redo)
+            .chain(self.batch.iter().flatten())
+            .chain(&self.inverting)
+            .flat_map(LocalEdit::deleted_ids)
+            .collect()
+    }
+
+    fn retarget(&mut self, retargets: &Retargets) {
+        for edit in self
+            .undo
+            .iter_mut()
+            .chain(self.redo.iter_mut())
+            .chain(self.batch.iter_mut().flatten())
+            .chain(self.inverting.iter_mut())
+        {
+            edit.retarget(retargets);
+        }
+    }
+}
+
+impl<T: Clone> Rga<T> {
+    // Record a new local edit; any redo history is discarded
+    // Inside a transaction, the edit joins the transaction's instead
+    pub(crate) fn record_local(&mut self, edit: LocalEdit<T>) {
+        if let Some(batch) = &mut self.history.batch {
+            batch.push(edit);
+            return;
+        }
+        self.history.redo.clear();
+        self.history.push_undo(edit);
+    }
+
+    /// Undo this site's most recent edit that can still be inverted
+    ///
+    /// Returns the RemoteOp to broadcast, or None if there is nothing left to
+    /// undo. Edits already overridden by other sites (e.g. an insert another
+    /// site deleted, or an update followed by a newer remote update) are
+    /// skipped rather than reverting someone else's change.
+    pub fn undo_local(&mut self) -> Option<RemoteOp<T>> {
+        while let Some(edit) = self.history.undo.pop_back() {
+            if let Some((op, inverse)) = self.invert(edit) {
+                self.history.redo.push(inverse);
+                return Some(op);
+            }
+        }
+        None
+    }
+
+    /// Redo the most recently undone edit
+    ///
+    /// Returns the RemoteOp to broadcast, or None if there is nothing to redo.
+    pub fn redo_local(&mut self) -> Option<RemoteOp<T>> {
+        while let Some(edit) = self.history.redo.pop() {
+            if let Some((op, inverse)) = self.invert(edit) {
+                self.history.push_undo(inverse);
+                return Some(op);
+            }
+        }
+        None
+    }
+
+    /// Whether any local edits are recorded for undo
+    pub fn can_undo(&self) -> bool {
+        !self.history.undo.is_empty()
+    }
+
+    /// Whether any undone edits are recorded for redo
+    pub fn can_redo(&self) -> bool {
+        !self.history.redo.is_empty()
+    }
+
+    /// Forget all undo/redo history
+    ///
+    /// Tombstones of locally deleted nodes are kept while undo may still need
+    /// their position, so this lets `purge_tombstones` reclaim them.
+    pub fn clear_undo_history(&mut self) {
+        self.history = UndoHistory {
+            limit: self.history.limit,
+            ..UndoHistory::default()
+        };
+    }
+
+    /// Keep at most `limit` local edits for undo (1024 by default)
+    ///
+    /// The oldest edits are forgotten first. A limit of 0 records none,
+    /// for replicas that never undo: their history would otherwise keep
+    /// tombstones from `purge_tombstones` for nothing.
+    pub fn set_undo_limit(&mut self, limit: usize) {
+        let history = &mut self.history;
+        history.limit = limit;
+        let excess = history.undo.len().saturating_sub(limit);
+        history.undo.drain(..excess);
+        let excess = history.redo.len().saturating_sub(limit);
+        history.redo.drain(..excess);
+    }
+
+    // Apply the inverse of `edit` as a new local operation
+    // Returns the op plus the edit that would invert it in turn
+    fn invert(&mut self, edit: LocalEdit<T>) -> Option<(RemoteOp<T>, LocalEdit<T>)> {
+        match edit {
+            LocalEdit::Insert { spans } => {
+                // Only elements no one has deleted yet, wherever they were
+                // moved to, in document order
+                let mut visible: Vec<(usize, S4Vector)> = remote_op::span_elements(&spans)
+                    .map(|id| self.moves.current_location(&id))
+                    .filter_map(|id| Some((self.index_of(&id)?, id)))
+                    .collect();
+                visible.sort_unstable_by_key(|(index, _)| *index);
+
+                let targets: Vec<S4Vector> = visible.into_iter().map(|(_, id)| id).collect();
+                self.generate_delete_elements(&targets)
+            }
+            LocalEdit::Delete {
+                spans,
+                values,
+                last_updates,
+            } => {
+                // Visible elements before the first tombstone give its position
+                let &(first, _) = spans.first()?;
+                let (index, _) = self.element_position(&first)?;
+                let (op, inverse) = self.generate_insert(index, values)?;
+
+                let new_first = op.s4v();
+                let retargets: Retargets = remote_op::span_elements(&spans)
+                    .zip(last_updates)
+                    .enumerate()
+                    .map(|(k, (old, last_update))| (old, (new_first.offset(k as u32), last_update)))
+                    .collect();
+                self.history.retarget(&retargets);
+                Some((op, inverse))
+            }
+            LocalEdit::Update { id, s4v, previous } => {
+                let id = self.moves.current_location(&id);
+                let index = self.index_of(&id)?;
+                if self.element_s_p(&id)? != s4v {
+                    return None;
+                }
+                self.generate_update(index, previous)
+            }
+            LocalEdit::Move {
+                elements,
+                s4v,
+                origin,
+            } => self.invert_move(&elements, s4v, origin),
+            LocalEdit::Batch { edits } => {
+                let log_start = self.log.ops.len();
+                let remaining = edits.len();
+                self.history.inverting = edits;
+                for k in (0..remaining).rev() {
+                    let edit = self.history.inverting[k].clone();
+                    if let Some((_, inverse)) = self.invert(edit) {
+                        self.history.inverting.push(inverse);
+                    }
+                }
+                let inverses = self.history.inverting.split_off(remaining);
+                self.history.inverting.clear();
+
+                let op = self.seal_batch(log_start)?;
+                Some((op, LocalEdit::Batch { edits: inverses }))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Rga;
+
+    #[test]
+    fn test_undo_redo_single_site() {
+        let mut rga = Rga::<char>::new(0, 1);
+
+        rga.insert_local(0, 'a');
+        rga.insert_local(1, 'b');
+        rga.update_local(0, 'A');
+        rga.delete_local(1);
+        assert_eq!(rga.read(), vec!['A']);
+
+        rga.undo_local().unwrap();
+        assert_eq!(rga.read(), vec!['A', 'b']);
+        rga.undo_local().unwrap();
+        assert_eq!(rga.read(), vec!['a', 'b']);
+        rga.undo_local().unwrap();
+        assert_eq!(rga.read(), vec!['a']);
+
+        rga.redo_local().unwrap();
+        rga.redo_local().unwrap();
+        rga.redo_local().unwrap();
+        assert_eq!(rga.read(), vec!['A']);
+        assert!(rga.redo_local().is_none());
+
+        // Undoing a delete carries earlier edits over to the re-inserted node
+        rga.undo_local().unwrap();
+        rga.undo_local().unwrap();
+        assert_eq!(rga.read(), vec!['a', 'b']);
+        rga.redo_local().unwrap();
+        rga.redo_local().unwrap();
+        assert_eq!(rga.read(), vec!['A']);
+
+        // A new edit clears the redo stack
+        rga.undo_local().unwrap();
+        rga.insert_local(0, 'z');
+        assert!(!rga.can_redo());
+    }
+
+    #[test]
+    fn test_undo_keeps_concurrent_remote_edits() {
+        let mut site0 = Rga::<char>::new(0, 2);
+        let mut site1 = Rga::<char>::new(1, 2);
+
+        for (i, ch) in "abc".chars().enumerate() {
+            site1
+                .apply_remote(site0.insert_local(i, ch).unwrap())
+                .unwrap();
+        }
+        site1
+            .apply_remote(site0.update_local(0, 'A').unwrap())
+            .unwrap();
+
+        // Site 1 edits after site 0: overrides the update, deletes 'c'
+        site0
+            .apply_remote(site1.update_local(0, 'X').unwrap())
+            .unwrap();
+        site0.apply_remote(site1.delete_local(2).unwrap()).unwrap();
+        site0
+            .apply_remote(site1.insert_local(1, 'y').unwrap())
+            .unwrap();
+
+        // Undo skips the overridden update and the already deleted 'c',
+        // and removes site 0's 'b' instead
+        let op = site0.undo_local().unwrap();
+        site1.apply_remote(op).unwrap();
+        assert_eq!(site0.read(), vec!['X', 'y']);
+        assert_eq!(site0.read(), site1.read());
+    }
+
+    #[test]
+    fn test_undo_delete_reinserts_at_tombstone_position() {
+        let mut site0 = Rga::<char>::new(0, 2);
+        let mut site1 = Rga::<char>::new(1, 2);
+
+        for (i, ch) in "abc".chars().enumerate() {
+            site1
+                .apply_remote(site0.insert_local(i, ch).unwrap())
+                .unwrap();
+        }
+        site1.apply_remote(site0.delete_local(1).unwrap()).unwrap();
+
+        // Purging must not drop the tombstone undo will re-insert next to
+        let stable = site0.stable_vector_clock(&[]);
+        assert_eq!(site0.purge_tombstones(&stable), 0);
+
+        site0
+            .apply_remote(site1.insert_local(0, '>').unwrap())
+            .unwrap();
+        site1.apply_remote(site0.undo_local().unwrap()).unwrap();
+        assert_eq!(site0.read(), vec!['>', 'a', 'b', 'c']);
+        assert_eq!(site0.read(), site1.read());
+    }
+
+    #[test]
+    fn test_undo_limit() {
+        let mut rga = Rga::<char>::new(0, 1);
+        for (i, ch) in "abc".chars().enumerate() {
+            rga.insert_local(i, ch);
+        }
+
+        // Only the two latest edits are kept
+        rga.set_undo_limit(2);
+        rga.undo_local().unwrap();
+        rga.undo_local().unwrap();
+        assert!(rga.undo_local().is_none());
+        assert_eq!(rga.read(), vec!['a']);
+
+        // Without history, deleted text can be purged right away
+        rga.set_undo_limit(0);
+        assert!(!rga.can_redo());
+        rga.delete_local(0);
+        assert!(!rga.can_undo());
+        let stable = rga.stable_vector_clock(&[]);
+        rga.purge_tombstones(&stable);
+        assert_eq!(rga.tombstone_count(), 0);
+        assert!(rga.is_empty());
+    }
+}
@@ -0,0 +1,555 @@
+// Validation and duplicate detection for remote operations
+//
+// A remote op is checked before anything is buffered or integrated:
+// - structurally: its S4Vector must agree with its own vector clock, and
+//   everything it refers to must be covered by that clock
+// - against what this replica has applied: retransmissions are recognised
+//   and ignored, while reused sequence numbers or a site clock that went
+//   backwards are rejected
+//
+// Ops of one site may be delivered out of order (the pending buffer only
+// waits for the elements an op refers to), so the merged vector clock can
+// run ahead of what was actually integrated. Duplicates are therefore
+// detected with the exact set of applied sequence numbers per site.
+
+use crate::remote_op::{self, RemoteOp};
+use crate::rga::Rga;
+use crate::s4vector::{self, S4Vector};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+
+// Reasons a remote operation is rejected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ApplyError {
+    // InsertRun without values, DeleteRange or Move without elements, or
+    // Batch without ops
+    Empty,
+
+    // The originating site has no entry in the op's vector clock
+    SiteOutOfRange { sid: u32, clock_len: usize },
+
+    // The op's last seq is not its site's entry in the op's vector clock
+    SeqMismatch { s4v: S4Vector, expected: u32 },
+
+    // The op's last sum is not the sum of its vector clock
+    SumMismatch { s4v: S4Vector, expected: u64 },
+
+    // The op refers to an element its own vector clock does not cover
+    UnseenElement(S4Vector),
+
+    // The op refers to an element that was integrated here under a
+    // different S4Vector, or has been purged
+    UnknownElement(S4Vector),
+
+    // The site reused sequence numbers already applied here, or its clock
+    // went backwards relative to what it acknowledged before
+    SeqRegression(S4Vector),
+
+    // An RFA update targets an index outside the array
+    IndexOutOfRange { index: usize, len: usize },
+
+    // An op in a Batch is itself a Batch, does not take the next sequence
+    // numbers of the Batch's site, or refers to an element of the Batch
+    // that no earlier op in it created
+    BrokenBatch(S4Vector),
+
+    // The op belongs to another session than this replica's
+    WrongSession { expected: u32, found: u32 },
+
+    // A new session was requested at a checkpoint this replica is not at,
+    // or while it still buffers ops
+    NotAtCheckpoint,
+
+    // The op takes or refers to sequence numbers past the largest counter,
+    // or a session was requested past the last session number
+    CounterOverflow,
+}
+
+impl fmt::Display for ApplyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApplyError::Empty => write!(f, "operation covers no elements"),
+            ApplyError::SiteOutOfRange { sid, clock_len } => {
+                write!(
+                    f,
+                    "site {sid} is outside a vector clock of {clock_len} sites"
+                )
+            }
+            ApplyError::SeqMismatch { s4v, expected } => {
+                write!(
+                    f,
+                    "seq {} does not match vector clock entry {expected}",
+                    s4v.seq
+                )
+            }
+            ApplyError::SumMismatch { s4v, expected } => {
+                write!(
+                    f,
+                    "sum {} does not match vector clock sum {expected}",
+                    s4v.sum
+                )
+            }
+            ApplyError::UnseenElement(id) => {
+                write!(f, "element {id:?} is not covered by the operation's clock")
+            }
+            ApplyError::UnknownElement(id) => write!(f, "element {id:?} does not exist"),
+            ApplyError::SeqRegression(s4v) => {
+                write!(f, "site {} regressed to seq {}", s4v.sid, s4v.seq)
+            }
+            ApplyError::IndexOutOfRange { index, len } => {
+                write!(f, "index {index} is outside an array of {len} elements")
+            }
+            ApplyError::BrokenBatch(s4v) => {
+                write!(f, "{s4v:?} does not continue its batch")
+            }
+            ApplyError::WrongSession { expected, found } => {
+                write!(
+                    f,
+                    "operation of session {found} applied in session {expected}"
+                )
+            }
+            ApplyError::NotAtCheckpoint => {
+                write!(f, "replica is not exactly at the session checkpoint")
+            }
+            ApplyError::CounterOverflow => write!(f, "S4Vector counters overflow"),
+        }
+    }
+}
+
+impl std::error::Error for ApplyError {}
+
+// Sequence numbers integrated from each site, as sorted disjoint inclusive
+// ranges. Usually a single range per site
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub(crate) struct AppliedSeqs(BTreeMap<u32, Vec<(u32, u32)>>);
+
+impl AppliedSeqs {
+    // How many of the seqs `first..=last` of `site` have been applied
+    pub(crate) fn count(&self, site: u32, first: u32, last: u32) -> u32 {
+        self.0.get(&site).map_or(0, |ranges| {
+            ranges
+                .iter()
+                .filter(|&&(lo, hi)| lo <= last && first <= hi)
+                .map(|&(lo, hi)| hi.min(last) - lo.max(first) + 1)
+                .sum()
+        })
+    }
+
+    // Mark the seqs `first..=last` of `site` as applied
+    pub(crate) fn insert(&mut self, site: u32, first: u32, last: u32) {
+        let ranges = self.0.entry(site).or_default();
+        ranges.push((first, last));
+        ranges.sort_unstable();
+
+        let mut merged: Vec<(u32, u32)> = Vec::with_capacity(ranges.len());
+        for &(lo, hi) in ranges.iter() {
+            match merged.last_mut() {
+                Some((_, last_hi)) if lo <= last_hi.saturating_add(1) => {
+                    *last_hi = (*last_hi).max(hi);
+                }
+                _ => merged.push((lo, hi)),
+            }
+        }
+        *ranges = merged;
+    }
+
+    // Every applied range as (site, first, last)
+    pub(crate) fn ranges(&self) -> impl Iterator<Item = (u32, u32, u32)> + '_ {
+        self.0
+            .iter()
+            .flat_map(|(&site, ranges)| ranges.iter().map(move |&(lo, hi)| (site, lo, hi)))
+    }
+}
+
+// Check that `s4v`, the last S4Vector an op takes, agrees with the op's
+// vector clock: its seq is its site's entry and its sum the clock's sum
+pub(crate) fn check_stamp(s4v: S4Vector, clock: &[u32]) -> Result<(), ApplyError> {
+    let Some(&expected_seq) = clock.get(s4v.sid as usize) else {
+        return Err(ApplyError::SiteOutOfRange {
+            sid: s4v.sid,
+            clock_len: clock.len(),
+        });
+    };
+    if s4v.seq != expected_seq {
+        return Err(ApplyError::SeqMismatch {
+            s4v,
+            expected: expected_seq,
+        });
+    }
+    let expected_sum = s4vector::clock_sum(clock);
+    if s4v.sum != expected_sum {
+        return Err(ApplyError::SumMismatch {
+            s4v,
+            expected: expected_sum,
+        });
+    }
+    Ok(())
+}
+
+// Check an op on its own: it covers something, its last S4Vector agrees
+// with its vector clock, and that clock covers everything it refers to
+// Returns the op's dependencies
+fn check_shape<T: Clone>(op: &RemoteOp<T>) -> Result<Vec<(S4Vector, u32)>, ApplyError> {
+    let empty = match op {
+        RemoteOp::InsertRun { values, .. } => values.is_empty(),
+        RemoteOp::DeleteRange { spans, .. } | RemoteOp::Move { spans, .. } => {
+            spans.is_empty() || spans.iter().any(|&(_, count)| count == 0)
+        }
+        RemoteOp::Batch { ops, .. } => ops.is_empty(),
+        _ => false,
+    };
+    if empty {
+        return Err(ApplyError::Empty);
+    }
+
+    // Offsets within the op are only exact if its spans stay below the
+    // counter limits
+    let dependencies = op.dependencies();
+    let fits = |spans: &[(S4Vector, u32)]| {
+        spans
+            .iter()
+            .all(|&(first, count)| first.checked_offset(count.saturating_sub(1)).is_some())
+    };
+    if !fits(&dependencies) || !fits(&op.created()) {
+        return Err(ApplyError::CounterOverflow);
+    }
+
+    let clock = op.vector_clock();
+    check_stamp(op.last_s4v(), clock)?;
+
+    for &(dependency, count) in &dependencies {
+        let dependency_last = dependency.offset(count - 1);
+        let covered = clock
+            .get(dependency.sid as usize)
+            .is_some_and(|&seen| seen >= dependency_last.seq);
+        if !covered {
+            return Err(ApplyError::UnseenElement(dependency_last));
+        }
+    }
+    Ok(dependencies)
+}
+
+// Check the ops of a Batch starting at `s4v`: each is well formed, takes
+// the sequence numbers right after the previous one, and refers to elements
+// of the Batch only once an earlier op in it created them, so that none can
+// fail halfway through. The last one must carry the Batch's vector clock
+fn check_batch<T: Clone>(
+    ops: &[RemoteOp<T>],
+    s4v: S4Vector,
+    vector_clock: &[u32],
+) -> Result<(), ApplyError> {
+    let own = |id: &S4Vector| id.ssn == s4v.ssn && id.sid == s4v.sid && id.seq >= s4v.seq;
+    let mut created: Vec<(S4Vector, u32)> = Vec::new();
+    // None once the Batch has taken the last sequence number
+    let mut next = Some(s4v);
+
+    for (i, op) in ops.iter().enumerate() {
+        // The first op takes the Batch's own S4Vector, later ones only its
+        // site and next sequence number (their sums follow their own clocks)
+        let first = op.s4v();
+        let continues = match next {
+            Some(_) if i == 0 => first == s4v,
+            Some(next) => first.ssn == next.ssn && first.sid == next.sid && first.seq == next.seq,
+            None => false,
+        };
+        if !continues || matches!(op, RemoteOp::Batch { .. }) {
+            return Err(ApplyError::BrokenBatch(first));
+        }
+
+        for id in remote_op::span_elements(&check_shape(op)?).filter(own) {
+            let known = created.iter().any(|&(start, count)| {
+                id.seq
+                    .checked_sub(start.seq)
+                    .is_some_and(|k| k < count && start.offset(k) == id)
+            });
+            if !known {
+                return Err(ApplyError::BrokenBatch(id));
+            }
+        }
+
+        created.extend(op.created());
+        next = op.last_s4v().checked_offset(1);
+    }
+
+    if ops.last().map(RemoteOp::vector_clock) != Some(vector_clock) {
+        return Err(ApplyError::BrokenBatch(s4v));
+    }
+    Ok(())
+}
+
+impl<T: Clone> Rga<T> {
+    /// Whether `op` has already been applied (or generated) by this replica
+    pub fn has_applied(&self, op: &RemoteOp<T>) -> bool {
+        let (first, last) = (op.s4v(), op.last_s4v());
+        self.applied.count(first.sid, first.seq, last.seq) == last.seq - first.seq + 1
+    }
+
+    // Record that `op` was integrated here
+    pub(crate) fn mark_applied(&mut self, op: &RemoteOp<T>) {
+        let (first, last) = (op.s4v(), op.last_s4v());
+        self.applied.insert(first.sid, first.seq, last.seq);
+    }
+
+    // Reject ops that are malformed or conflict with what was applied here
+    // Well-formed retransmissions pass; callers skip them via `has_applied`
+    pub(crate) fn validate(&self, op: &RemoteOp<T>) -> Result<(), ApplyError> {
+        if op.s4v().ssn != self.session {
+            return Err(ApplyError::WrongSession {
+                expected: self.session,
+                found: op.s4v().ssn,
+            });
+        }
+        if let RemoteOp::Batch {
+            ops,
+            s4v,
+            vector_clock,
+        } = op
+        {
+            check_batch(ops, *s4v, vector_clock)?;
+        }
+        let dependencies = check_shape(op)?;
+
+        let (first, last) = (op.s4v(), op.last_s4v());
+        let clock = op.vector_clock();
+
+        // The elements a retransmission refers to may since have been purged
+        if self.has_applied(op) {
+            return Ok(());
+        }
+
+        for (dependency, count) in dependencies {
+            // Applied here, so it must exist under exactly this S4Vector
+            for k in 0..count {
+                let id = dependency.offset(k);
+                let applied = self.applied.count(id.sid, id.seq, id.seq) == 1;
+                if applied && self.find_by_s4vector(&id).is_none() {
+                    return Err(ApplyError::UnknownElement(id));
+                }
+            }
+        }
+
+        // Some but not all of the op's seqs applied: they were reused
+        let applied = self.applied.count(first.sid, first.seq, last.seq);
+        if applied > 0 && applied <= last.seq - first.seq {
+            return Err(ApplyError::SeqRegression(first));
+        }
+
+        // A newer op than the site acknowledged must have seen everything
+        // the acknowledgement covered
+        if let Some(known) = self.site_clocks.get(&first.sid) {
+            let newer = known
+                .get(first.sid as usize)
+                .is_none_or(|&seq| last.seq > seq);
+            let forgot = known
+                .iter()
+                .enumerate()
+                .any(|(site, &seen)| clock.get(site).copied().unwrap_or(0) < seen);
+            if newer && forgot {
+                return Err(ApplyError::SeqRegression(first));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AppliedSeqs;
+    use crate::{ApplyError, RemoteOp, Rga, S4Vector};
+
+    #[test]
+    fn test_applied_seqs_merge_ranges() {
+        let mut applied = AppliedSeqs::default();
+        applied.insert(1, 4, 6);
+        applied.insert(1, 1, 2);
+        assert_eq!(applied.count(1, 1, 6), 5);
+        assert_eq!(applied.count(1, 3, 3), 0);
+
+        applied.insert(1, 3, 3);
+        assert_eq!(applied.0[&1], vec![(1, 6)]);
+        assert_eq!(applied.count(2, 1, 6), 0);
+    }
+
+    #[test]
+    fn test_retransmission_is_harmless() {
+        let mut site0 = Rga::<char>::new(0, 2);
+        let mut site1 = Rga::<char>::new(1, 2);
+
+        let run = site0.insert_run_local(0, "abc".chars().collect()).unwrap();
+        let delete = site0.delete_local(1).unwrap();
+        let update = site0.update_local(0, 'A').unwrap();
+
+        for _ in 0..2 {
+            for op in [&run, &delete, &update] {
+                site1.apply_remote(op.clone()).unwrap();
+            }
+        }
+        assert_eq!(site1.read(), vec!['A', 'c']);
+        assert_eq!(site1.node_count(), site0.node_count());
+
+        // Own ops echoed back are recognised too
+        assert_eq!(site0.apply_remote(run.clone()), Ok(Vec::new()));
+        assert_eq!(site0.read(), site1.read());
+    }
+
+    #[test]
+    fn test_duplicate_while_buffered() {
+        let mut site0 = Rga::<char>::new(0, 2);
+        let mut site1 = Rga::<char>::new(1, 2);
+
+        let a = site0.insert_local(0, 'a').unwrap();
+        let b = site0.insert_local(1, 'b').unwrap();
+
+        // 'b' waits for 'a' twice, but is only integrated once
+        site1.apply_remote(b.clone()).unwrap();
+        site1.apply_remote(b).unwrap();
+        assert_eq!(site1.pending_count(), 2);
+        site1.apply_remote(a).unwrap();
+        assert_eq!(site1.read(), vec!['a', 'b']);
+        assert_eq!(site1.pending_count(), 0);
+    }
+
+    #[test]
+    fn test_rejects_malformed_ops() {
+        let mut site = Rga::<char>::new(0, 2);
+        let insert = |s4v, vector_clock| RemoteOp::Insert {
+            left_id: None,
+            value: 'x',
+            s4v,
+            vector_clock,
+        };
+
+        assert_eq!(
+            site.apply_remote(insert(S4Vector::new(1, 3, 1, 1), vec![0, 1])),
+            Err(ApplyError::SiteOutOfRange {
+                sid: 3,
+                clock_len: 2
+            })
+        );
+        assert!(matches!(
+            site.apply_remote(insert(S4Vector::new(1, 1, 2, 2), vec![0, 1])),
+            Err(ApplyError::SeqMismatch { expected: 1, .. })
+        ));
+        assert!(matches!(
+            site.apply_remote(insert(S4Vector::new(1, 1, 5, 1), vec![0, 1])),
+            Err(ApplyError::SumMismatch { expected: 1, .. })
+        ));
+        assert_eq!(
+            site.apply_remote(RemoteOp::InsertRun {
+                left_id: None,
+                values: Vec::new(),
+                s4v: S4Vector::new(1, 1, 1, 1),
+                vector_clock: vec![0, 1],
+            }),
+            Err(ApplyError::Empty)
+        );
+
+        // Deleting an element the deleter had not seen
+        let target = S4Vector::new(1, 0, 1, 1);
+        assert_eq!(
+            site.apply_remote(RemoteOp::Delete {
+                target_id: target,
+                s4v: S4Vector::new(1, 1, 1, 1),
+                vector_clock: vec![0, 1],
+            }),
+            Err(ApplyError::UnseenElement(target))
+        );
+        assert!(site.is_empty());
+        assert_eq!(site.pending_count(), 0);
+    }
+
+    #[test]
+    fn test_rejects_mismatched_target_and_reused_seq() {
+        let mut site0 = Rga::<char>::new(0, 2);
+        let mut site1 = Rga::<char>::new(1, 2);
+        site1
+            .apply_remote(site0.insert_run_local(0, "ab".chars().collect()).unwrap())
+            .unwrap();
+
+        // Right site and seq, wrong sum
+        let wrong = S4Vector::new(1, 0, 7, 1);
+        assert_eq!(
+            site1.apply_remote(RemoteOp::Delete {
+                target_id: wrong,
+                s4v: S4Vector::new(1, 0, 3, 3),
+                vector_clock: vec![3, 0],
+            }),
+            Err(ApplyError::UnknownElement(wrong))
+        );
+
+        // A different run claiming seq 2 of site 0, already taken by 'b'
+        assert!(matches!(
+            site1.apply_remote(RemoteOp::InsertRun {
+                left_id: None,
+                values: vec!['x', 'y'],
+                s4v: S4Vector::new(1, 0, 2, 2),
+                vector_clock: vec![3, 0],
+            }),
+            Err(ApplyError::SeqRegression(_))
+        ));
+        assert_eq!(site1.read(), vec!['a', 'b']);
+    }
+
+    #[test]
+    fn test_rejects_clock_regression() {
+        let mut site0 = Rga::<char>::new(0, 2);
+        let mut site1 = Rga::<char>::new(1, 2);
+
+        site1
+            .apply_remote(site0.insert_local(0, 'a').unwrap())
+            .unwrap();
+        site0
+            .apply_remote(site1.insert_local(1, 'b').unwrap())
+            .unwrap();
+        site1
+            .apply_remote(site0.insert_local(2, 'c').unwrap())
+            .unwrap();
+
+        // Site 0 restarted from a state that never saw 'b'
+        let mut stale = Rga::<char>::new(0, 2);
+        stale
+            .apply_remote(site1.ops_since(&[]).unwrap()[0].clone())
+            .unwrap();
+        stale.vector_clock[0] = 3;
+        let op = stale.insert_local(1, 'x').unwrap();
+
+        assert!(matches!(
+            site1.apply_remote(op),
+            Err(ApplyError::SeqRegression(_))
+        ));
+    }
+
+    #[test]
+    fn test_rejects_counter_overflow() {
+        let mut site = Rga::<char>::new(0, 2);
+        let last = u32::MAX - 1;
+
+        // A run whose later elements would need seqs past u32::MAX
+        assert_eq!(
+            site.apply_remote(RemoteOp::InsertRun {
+                left_id: None,
+                values: vec!['x', 'y', 'z'],
+                s4v: S4Vector::new(1, 1, u64::from(last), last),
+                vector_clock: vec![0, u32::MAX],
+            }),
+            Err(ApplyError::CounterOverflow)
+        );
+
+        // Up to the last seq is fine, and sums go past u32::MAX
+        site.vector_clock = vec![u32::MAX, last - 1];
+        let op = RemoteOp::InsertRun {
+            left_id: None,
+            values: vec!['x', 'y'],
+            s4v: S4Vector::new(1, 1, u64::from(u32::MAX) + u64::from(last), last),
+            vector_clock: vec![u32::MAX, u32::MAX],
+        };
+        site.apply_remote(op).unwrap();
+        assert_eq!(site.read(), vec!['x', 'y']);
+        assert_eq!(
+            site.id_at(1).map(|id| id.sum),
+            Some(2 * u64::from(u32::MAX))
+        );
+    }
+}
@@ -40,7 +40,7 @@ mod integration_tests {
                     // Broadcast to all other sites
                     for i in 0..self.sites.len() {
                         if i != from {
-                            self.sites[i].apply_remote(op.clone());
+                            self.sites[i].apply_remote(op.clone()).unwrap();
                         }
                     }
                 }
@@ -59,6 +59,20 @@ mod integration_tests {
         }
     }
 
+    // Every site pulls the ops it misses from every other site
+    fn pull_missing(sites: &mut [Rga<char>]) {
+        for i in 0..sites.len() {
+            for j in 0..sites.len() {
+                if i != j {
+                    let ops = sites[j].ops_since(sites[i].vector_clock()).unwrap();
+                    for op in ops {
+                        sites[i].apply_remote(op).unwrap();
+                    }
+                }
+            }
+        }
+    }
+
     #[test]
     fn test_sequential_inserts() {
         println!("\n=== Test: Sequential Inserts ===");
@@ -362,7 +376,7 @@ mod integration_tests {
     #[test]
     fn test_causality_violation_detection() {
         println!("\n=== Test: Causality Violation Detection ===");
-        // This tests that operations missing their cobjects fail gracefully
+        // This tests that operations missing their causal predecessors are buffered
 
         let mut site = Rga::<char>::new(0, 2);
 
@@ -372,16 +386,51 @@ mod integration_tests {
             left_id: Some(fake_s4v),
             value: 'x',
             s4v: S4Vector::new(1, 1, 101, 51),
-            vector_clock: vec![100, 0], // Fake vector clock
+            vector_clock: vec![50, 51], // Fake but consistent vector clock
         };
 
-        site.apply_remote(op);
+        site.apply_remote(op).unwrap();
 
-        // Operation should have failed gracefully
+        // Operation should be held back until everything it saw arrives,
+        // starting with site 0's first op
         assert_eq!(site.read().len(), 0);
+        assert_eq!(site.pending_count(), 1);
+        assert_eq!(site.missing_ops().next(), Some((0, 1)));
         println!("Causality violation handled gracefully");
     }
 
+    #[test]
+    fn test_reordered_delivery_converges() {
+        println!("\n=== Test: Reordered Delivery Converges ===");
+        let mut site0 = Rga::<char>::new(0, 3);
+        let mut site1 = Rga::<char>::new(1, 3);
+        let mut site2 = Rga::<char>::new(2, 3);
+
+        // Site 0 types "abc", site 1 extends it and edits
+        let mut ops = vec![
+            site0.insert_local(0, 'a').unwrap(),
+            site0.insert_local(1, 'b').unwrap(),
+            site0.insert_local(2, 'c').unwrap(),
+        ];
+        for op in &ops {
+            site1.apply_remote(op.clone()).unwrap();
+        }
+        ops.push(site1.insert_local(3, 'd').unwrap());
+        ops.push(site1.update_local(1, 'B').unwrap());
+        ops.push(site1.delete_local(0).unwrap());
+
+        // Site 2 receives everything in reverse (e.g. after a reconnect)
+        for op in ops.iter().rev() {
+            site2.apply_remote(op.clone()).unwrap();
+        }
+
+        println!("Site 1: {:?}", site1.read());
+        println!("Site 2: {:?}", site2.read());
+        assert_eq!(site2.pending_count(), 0);
+        assert_eq!(site2.read(), vec!['B', 'c', 'd']);
+        assert_eq!(site1.read(), site2.read());
+    }
+
     #[test]
     fn test_interleaved_operations() {
         println!("\n=== Test: Interleaved Operations ===");
@@ -465,8 +514,8 @@ mod integration_tests {
         }
 
         // Site 1 receives operations and updates its vector clock
-        site1.apply_remote(op_a.clone());
-        site1.apply_remote(op_b.clone());
+        site1.apply_remote(op_a.clone()).unwrap();
+        site1.apply_remote(op_b.clone()).unwrap();
 
         // Site 1 now performs an operation
         let op_c = site1.insert_local(2, 'c').unwrap();
@@ -483,9 +532,9 @@ mod integration_tests {
         }
 
         // Site 2 receives all operations
-        site2.apply_remote(op_a);
-        site2.apply_remote(op_b);
-        site2.apply_remote(op_c);
+        site2.apply_remote(op_a).unwrap();
+        site2.apply_remote(op_b).unwrap();
+        site2.apply_remote(op_c).unwrap();
 
         // All sites should converge
         assert_eq!(site0.read(), vec!['a', 'b']);
@@ -494,4 +543,243 @@ mod integration_tests {
 
         println!("Vector clock propagation verified!");
     }
+
+    #[test]
+    fn test_runs_converge_under_random_edits() {
+        let num_sites = 3;
+        let mut sites: Vec<Rga<char>> = (0..num_sites)
+            .map(|i| Rga::new(i as u32, num_sites))
+            .collect();
+
+        // Deterministic xorshift so failures are reproducible
+        let mut seed = 0x2545_F491u32;
+        let mut next = move |bound: usize| {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed as usize % bound.max(1)
+        };
+
+        for round in 0..40 {
+            let mut ops: Vec<(usize, RemoteOp<char>)> = Vec::new();
+            for (site, rga) in sites.iter_mut().enumerate() {
+                let len = rga.len();
+                let op = match next(4) {
+                    0 | 1 => {
+                        let text: Vec<char> = (0..1 + next(5))
+                            .map(|k| char::from(b'a' + ((round + k) % 26) as u8))
+                            .collect();
+                        rga.insert_run_local(next(len + 1), text)
+                    }
+                    2 => rga.delete_range_local(next(len), 1 + next(4)),
+                    _ => rga.update_local(next(len), 'Z'),
+                };
+                ops.extend(op.map(|op| (site, op)));
+            }
+
+            // Every site receives the round's ops in its own shuffled order
+            for (i, rga) in sites.iter_mut().enumerate() {
+                let mut incoming: Vec<_> = ops.iter().filter(|(from, _)| *from != i).collect();
+                for k in (1..incoming.len()).rev() {
+                    incoming.swap(k, next(k + 1));
+                }
+                for (_, op) in incoming {
+                    rga.apply_remote(op.clone()).unwrap();
+                }
+            }
+        }
+
+        let first = sites[0].read();
+        for rga in &sites {
+            assert_eq!(rga.read(), first);
+            assert_eq!(rga.pending_count(), 0);
+        }
+    }
+
+    #[test]
+    fn test_clock_only_covers_integrated_ops() {
+        let mut site0 = Rga::<char>::new(0, 3);
+        let mut site1 = Rga::<char>::new(1, 3);
+        let mut site2 = Rga::<char>::new(2, 3);
+
+        // Site 2 saw site 0's insert before inserting in front of it
+        let first = site0.insert_local(0, 'a').unwrap();
+        site2.apply_remote(first).unwrap();
+        let second = site2.insert_local(0, 'b').unwrap();
+
+        // Site 1 only got site 2's insert: it waits, and does not claim
+        // site 0's insert, so anti-entropy still sends it
+        site1.apply_remote(second).unwrap();
+        assert_eq!(site1.vector_clock(), &[0, 0, 0]);
+        assert_eq!(site1.missing_ops().next(), Some((0, 1)));
+
+        let missing = site0.ops_since(site1.vector_clock()).unwrap();
+        assert_eq!(missing.len(), 1);
+        for op in missing {
+            site1.apply_remote(op).unwrap();
+        }
+        assert_eq!(site1.read(), vec!['b', 'a']);
+        assert_eq!(site1.vector_clock(), &[1, 0, 1]);
+        assert_eq!(site1.pending_count(), 0);
+    }
+
+    #[test]
+    fn test_dropped_ops_recover_through_ops_since() {
+        let num_sites = 3;
+        let mut sites: Vec<Rga<char>> = (0..num_sites)
+            .map(|i| Rga::new(i as u32, num_sites))
+            .collect();
+
+        // Deterministic xorshift so failures are reproducible
+        let mut seed = 0x9E37_79B9u32;
+        let mut next = move |bound: usize| {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed as usize % bound.max(1)
+        };
+
+        for round in 0..60 {
+            let mut ops: Vec<(usize, RemoteOp<char>)> = Vec::new();
+            for (site, rga) in sites.iter_mut().enumerate() {
+                let len = rga.len();
+                let op = match next(4) {
+                    0 | 1 => {
+                        let text: Vec<char> = (0..1 + next(3))
+                            .map(|k| char::from(b'a' + ((round + k) % 26) as u8))
+                            .collect();
+                        rga.insert_run_local(next(len + 1), text)
+                    }
+                    2 => rga.delete_range_local(next(len), 1 + next(3)),
+                    _ => rga.update_local(next(len), 'Z'),
+                };
+                ops.extend(op.map(|op| (site, op)));
+            }
+
+            // Each site gets a shuffled subset of the round's ops
+            for (i, rga) in sites.iter_mut().enumerate() {
+                let mut incoming: Vec<_> = ops
+                    .iter()
+                    .filter(|(from, _)| *from != i)
+                    .filter(|_| next(3) != 0)
+                    .collect();
+                for k in (1..incoming.len()).rev() {
+                    incoming.swap(k, next(k + 1));
+                }
+                for (_, op) in incoming {
+                    rga.apply_remote(op.clone()).unwrap();
+                }
+            }
+
+            // No clock may claim an op its replica has not integrated
+            for (i, rga) in sites.iter().enumerate() {
+                for (j, other) in sites.iter().enumerate() {
+                    let covered = other.ops_since(&[]).unwrap().into_iter().filter(|op| {
+                        let last = op.last_s4v();
+                        rga.vector_clock()
+                            .get(last.sid as usize)
+                            .is_some_and(|&seen| seen >= last.seq)
+                    });
+                    for op in covered {
+                        assert!(
+                            rga.has_applied(&op),
+                            "site {i} claims {:?} of site {j}",
+                            op.s4v()
+                        );
+                    }
+                }
+            }
+
+            if next(5) == 0 {
+                pull_missing(&mut sites);
+            }
+        }
+
+        pull_missing(&mut sites);
+        let first = sites[0].read();
+        for rga in &sites {
+            assert_eq!(rga.read(), first);
+            assert_eq!(rga.vector_clock(), sites[0].vector_clock());
+            assert_eq!(rga.pending_count(), 0);
+        }
+    }
+
+    #[test]
+    fn test_purging_replicas_converge_under_random_edits() {
+        let num_sites = 3;
+        let all_sites: Vec<u32> = (0..num_sites as u32).collect();
+        let mut sites: Vec<Rga<char>> = (0..num_sites)
+            .map(|i| Rga::new(i as u32, num_sites))
+            .collect();
+
+        // Deterministic xorshift so failures are reproducible
+        let mut seed = 0x1390_5EEDu32;
+        let mut next = move |bound: usize| {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed as usize % bound.max(1)
+        };
+
+        let mut purged = 0;
+        for round in 0..80 {
+            let mut ops: Vec<(usize, RemoteOp<char>)> = Vec::new();
+            for (site, rga) in sites.iter_mut().enumerate() {
+                let len = rga.len();
+                let op = match next(3) {
+                    0 | 1 if len < 40 => {
+                        let text: Vec<char> = (0..1 + next(3))
+                            .map(|k| char::from(b'a' + ((round + k) % 26) as u8))
+                            .collect();
+                        rga.insert_run_local(next(len + 1), text)
+                    }
+                    _ => rga.delete_range_local(next(len), 1 + next(3)),
+                };
+                ops.extend(op.map(|op| (site, op)));
+            }
+
+            // Ops arrive reordered and some are lost until the next pull
+            for (i, rga) in sites.iter_mut().enumerate() {
+                let mut incoming: Vec<_> = ops
+                    .iter()
+                    .filter(|(from, _)| *from != i && next(4) != 0)
+                    .collect();
+                for k in (1..incoming.len()).rev() {
+                    incoming.swap(k, next(k + 1));
+                }
+                for (_, op) in incoming {
+                    rga.apply_remote(op.clone()).unwrap();
+                }
+            }
+
+            // Sites occasionally acknowledge what they integrated, then
+            // purge whatever every site has seen deleted
+            for i in 0..num_sites {
+                if next(3) == 0 {
+                    let (site, clock) = (i as u32, sites[i].vector_clock().to_vec());
+                    for (j, rga) in sites.iter_mut().enumerate() {
+                        if j != i {
+                            rga.acknowledge(site, &clock);
+                        }
+                    }
+                }
+            }
+            for rga in &mut sites {
+                let stable = rga.stable_vector_clock(&all_sites);
+                purged += rga.purge_tombstones(&stable);
+            }
+
+            if next(4) == 0 {
+                pull_missing(&mut sites);
+            }
+        }
+
+        pull_missing(&mut sites);
+        assert!(purged > 0);
+        let first = sites[0].read();
+        for rga in &sites {
+            assert_eq!(rga.read(), first);
+            assert_eq!(rga.pending_count(), 0);
+        }
+    }
 }
@@ -48,7 +48,7 @@ mod paper_based_tests {
                     // Broadcast to all other sites
                     for i in 0..self.sites.len() {
                         if i != from {
-                            self.sites[i].apply_remote(op.clone());
+                            self.sites[i].apply_remote(op.clone()).unwrap();
                         }
                     }
                 }
@@ -120,8 +120,8 @@ mod paper_based_tests {
 
         // Initialize all sites with same state
         let init_op = site_a.insert_local(0, 'x').unwrap();
-        site_b.apply_remote(init_op.clone());
-        site_c.apply_remote(init_op.clone());
+        site_b.apply_remote(init_op.clone()).unwrap();
+        site_c.apply_remote(init_op.clone()).unwrap();
 
         // Generate three concurrent operations
         let op1 = site_a.insert_local(1, '1').unwrap();
@@ -129,16 +129,16 @@ mod paper_based_tests {
         let op3 = site_c.insert_local(1, '3').unwrap();
 
         // Site A: op2, op3 (already has op1 local)
-        site_a.apply_remote(op2.clone());
-        site_a.apply_remote(op3.clone());
+        site_a.apply_remote(op2.clone()).unwrap();
+        site_a.apply_remote(op3.clone()).unwrap();
 
         // Site B: op3, op1 (already has op2 local)
-        site_b.apply_remote(op3.clone());
-        site_b.apply_remote(op1.clone());
+        site_b.apply_remote(op3.clone()).unwrap();
+        site_b.apply_remote(op1.clone()).unwrap();
 
         // Site C: op1, op2 (already has op3 local)
-        site_c.apply_remote(op1.clone());
-        site_c.apply_remote(op2.clone());
+        site_c.apply_remote(op1.clone()).unwrap();
+        site_c.apply_remote(op2.clone()).unwrap();
 
         // Despite different delivery orders, all must converge
         assert_eq!(site_a.read(), site_b.read());
@@ -239,11 +239,11 @@ mod paper_based_tests {
 
         // Initialize
         let op_init = site0.insert_local(0, 'a').unwrap();
-        site1.apply_remote(op_init);
+        site1.apply_remote(op_init).unwrap();
 
         // Site 0 deletes
         let op_del = site0.delete_local(0).unwrap();
-        site1.apply_remote(op_del);
+        site1.apply_remote(op_del).unwrap();
 
         assert_eq!(site0.read(), Vec::<char>::new());
 
@@ -253,7 +253,7 @@ mod paper_based_tests {
 
         // Now site 0 receives the update
         if let Some(upd) = op_upd_opt {
-            site0.apply_remote(upd);
+            site0.apply_remote(upd).unwrap();
         }
 
         // Should still be empty - no resurrection
@@ -439,22 +439,22 @@ mod paper_based_tests {
 
         // Initialize all sites with same starting state
         let init_op = site0.insert_local(0, 'x').unwrap();
-        site1.apply_remote(init_op.clone());
-        site2.apply_remote(init_op.clone());
+        site1.apply_remote(init_op.clone()).unwrap();
+        site2.apply_remote(init_op.clone()).unwrap();
 
         // Generate two concurrent operations from different sites
         let op_a = site1.insert_local(1, 'a').unwrap(); // Site 1 inserts 'a'
         let op_b = site2.insert_local(1, 'b').unwrap(); // Site 2 inserts 'b' (concurrent)
 
         // Site 0: Apply in order A then B
-        site0.apply_remote(op_a.clone());
-        site0.apply_remote(op_b.clone());
+        site0.apply_remote(op_a.clone()).unwrap();
+        site0.apply_remote(op_b.clone()).unwrap();
 
         // Site 1: Apply B (already has A local)
-        site1.apply_remote(op_b.clone());
+        site1.apply_remote(op_b.clone()).unwrap();
 
         // Site 2: Apply A (already has B local)
-        site2.apply_remote(op_a.clone());
+        site2.apply_remote(op_a.clone()).unwrap();
 
         // All should reach same state regardless of application order
         assert_eq!(site0.read(), site1.read());
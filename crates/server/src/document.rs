@@ -1,13 +1,26 @@
 // Document management with CRDT and checkpointing
-
-use rga::{RemoteOp, Rga};
+//
+// A checkpoint copies the current text into `base_content` and makes a new
+// CRDT session due: as soon as every client in the room has acknowledged
+// all operations, the replica is renumbered into the next session (see
+// `Rga::start_session`), which drops tombstones and history and restarts
+// the S4Vector counters, and clients are told to do the same.
+
+use crate::positions::{self, PositionCache};
+use protocol::messages::PositionEncoding;
+use rga::{ApplyError, Change, MarkOp, Marks, RemoteOp, Rga};
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use std::ops::Range;
+use std::sync::{Arc, Mutex, PoisonError};
 use tokio::sync::RwLock;
 use uuid::Uuid;
 
 const CHECKPOINT_THRESHOLD: usize = 100;
 
+// Operations kept for anti-entropy before the log is compacted, so clients
+// that miss a few checkpoints can still catch up without a full reload
+const OP_LOG_RETENTION: usize = 10 * CHECKPOINT_THRESHOLD;
+
 // Document being collaboratively edited
 #[derive(Debug)]
 pub struct Document {
@@ -26,19 +39,29 @@ pub struct Document {
     // Base document content (last checkpoint)
     pub base_content: String,
 
-    // Number of sites (clients) in the room
-    pub num_sites: usize,
+    // Formatting marks anchored to characters of `rga`
+    pub marks: Marks,
+
+    // Whether a checkpoint happened since the current session started
+    pub session_due: bool,
+
+    // Character boundaries in client encodings, found while converting
+    // positions and reused until the text changes
+    positions: Mutex<PositionCache>,
 }
 
 impl Document {
     // Create a new document with initial content
-    pub fn new(id: Uuid, filename: String, initial_content: String, num_sites: usize) -> Self {
-        let mut rga = Rga::new(0, num_sites); // Server is site 0
+    // The RGA's vector clock grows as clients (sites) join, so no site count is needed
+    pub fn new(id: Uuid, filename: String, initial_content: String) -> Self {
+        let mut rga = Rga::new(0, 1); // Server is site 0
 
-        // Initialize RGA with content
-        for (i, ch) in initial_content.chars().enumerate() {
-            rga.insert_local(i, ch);
-        }
+        // The server never undoes the edits it makes on behalf of clients;
+        // undo history would only keep tombstones from being purged
+        rga.set_undo_limit(0);
+
+        // Initialize RGA with content as a single run
+        rga.insert_run_local(0, initial_content.chars().collect());
 
         Document {
             id,
@@ -46,16 +69,146 @@ impl Document {
             rga,
             buffered_ops: Vec::new(),
             base_content: initial_content,
-            num_sites,
+            marks: Marks::new(0, 1),
+            session_due: false,
+            positions: Mutex::default(),
+        }
+    }
+
+    // Restore a document from a persisted replica
+    // Buffered ops are kept for late joiners but are already part of `rga`
+    pub fn from_replica(
+        id: Uuid,
+        filename: String,
+        base_content: String,
+        buffered_ops: Vec<RemoteOp<char>>,
+        mut rga: Rga<char>,
+    ) -> Self {
+        rga.set_undo_limit(0);
+        Document {
+            id,
+            filename,
+            rga,
+            buffered_ops,
+            base_content,
+            marks: Marks::new(0, 1),
+            session_due: false,
+            positions: Mutex::default(),
+        }
+    }
+
+    // Restore a document from storage: from its persisted replica if there
+    // is one this version can load, else by replaying the buffered ops over
+    // the checkpointed content
+    // Replayed ops that no longer apply (e.g. from an earlier session) are
+    // skipped, so a rebuilt document may lose edits since the checkpoint
+    pub fn restore(
+        id: Uuid,
+        filename: String,
+        base_content: String,
+        buffered_ops: Vec<RemoteOp<char>>,
+        replica: Option<&[u8]>,
+    ) -> Self {
+        match replica.map(Rga::from_bytes) {
+            Some(Ok(rga)) => {
+                return Document::from_replica(id, filename, base_content, buffered_ops, rga)
+            }
+            Some(Err(e)) => tracing::warn!(
+                "Cannot load the replica of document {}, rebuilding it: {}",
+                filename,
+                e
+            ),
+            None => {}
+        }
+
+        let mut document = Document::new(id, filename, base_content);
+        for op in buffered_ops {
+            if let Err(e) = document.apply_operation(op) {
+                tracing::warn!(
+                    "Skipping stored operation for document {}: {}",
+                    document.filename,
+                    e
+                );
+            }
         }
+        document
     }
 
     // Apply a remote operation and buffer it
-    pub fn apply_operation(&mut self, op: RemoteOp<char>) {
-        self.rga.apply_remote(op.clone());
+    // Returns false for a retransmission that was already applied
+    pub fn apply_operation(&mut self, op: RemoteOp<char>) -> Result<bool, ApplyError> {
+        if self.rga.has_applied(&op) {
+            return Ok(false);
+        }
+        let before = self.version();
+        let changes = self.rga.apply_remote(op.clone())?;
         self.buffered_ops.push(op);
 
+        let first_changed = changes
+            .iter()
+            .map(|change| match change {
+                Change::Insert { index, .. }
+                | Change::Delete { index, .. }
+                | Change::Update { index, .. } => *index,
+            })
+            .min();
+        self.text_changed(&before, first_changed.unwrap_or(usize::MAX));
+
         // Note: checkpoint is now handled by the server to ensure persistence
+        Ok(true)
+    }
+
+    // Make a local edit on behalf of a client and buffer its op
+    // `index` is the first code point the edit changes
+    pub fn edit_local(
+        &mut self,
+        index: usize,
+        edit: impl FnOnce(&mut Rga<char>) -> Option<RemoteOp<char>>,
+    ) -> Option<RemoteOp<char>> {
+        let before = self.version();
+        let op = edit(&mut self.rga)?;
+        self.buffered_ops.push(op.clone());
+        self.text_changed(&before, index);
+        Some(op)
+    }
+
+    // Session and vector clock identifying the current text
+    fn version(&self) -> (u32, Vec<u32>) {
+        (self.rga.session(), self.rga.vector_clock().to_vec())
+    }
+
+    // Keep the cached boundaries before code point `index` across a change
+    // of the text from version `before`
+    fn text_changed(&mut self, before: &(u32, Vec<u32>), index: usize) {
+        let after = (self.rga.session(), self.rga.vector_clock());
+        self.positions
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+            .edited((before.0, &before.1), after, index);
+    }
+
+    // Apply a formatting mark
+    // Returns false for a retransmission that was already applied
+    // Marks anchored in another session are rejected, as their anchors
+    // would never resolve
+    pub fn apply_mark(&mut self, op: MarkOp) -> Result<bool, ApplyError> {
+        let session = self.rga.session();
+        if let Some(id) = [op.start.id, op.end.id]
+            .into_iter()
+            .flatten()
+            .find(|id| id.ssn != session)
+        {
+            return Err(ApplyError::WrongSession {
+                expected: session,
+                found: id.ssn,
+            });
+        }
+        self.marks.apply_remote(op)
+    }
+
+    // Every formatting mark, for clients joining or resyncing
+    pub fn mark_ops(&self) -> Vec<MarkOp> {
+        self.marks.ops().to_vec()
     }
 
     // Perform checkpoint: apply all buffered ops to base content
@@ -71,6 +224,7 @@ impl Document {
 
         // Clear buffered operations
         self.buffered_ops.clear();
+        self.session_due = true;
 
         tracing::info!(
             "Checkpoint completed for document {}: {} operations applied",
@@ -81,6 +235,80 @@ impl Document {
         ops_count
     }
 
+    // Drop tombstones whose deletion every listed site has already seen
+    // `sites` must include every site that may still send ops referring to
+    // them (see `Room::replica_site_ids`)
+    pub fn purge_tombstones(&mut self, sites: &[u32]) -> usize {
+        let stable_vc = self.rga.stable_vector_clock(sites);
+        let purged = self.rga.purge_tombstones(&stable_vc);
+
+        if self.rga.log_len() > OP_LOG_RETENTION {
+            let compacted = self.rga.compact_log(&stable_vc);
+            tracing::info!(
+                "Compacted {} logged operations of document {}",
+                compacted,
+                self.filename
+            );
+        }
+
+        if purged > 0 {
+            tracing::info!(
+                "Purged {} tombstones from document {} ({} remaining)",
+                purged,
+                self.filename,
+                self.rga.tombstone_count()
+            );
+        }
+
+        purged
+    }
+
+    // Record that a client's replica has integrated every operation
+    // covered by `vector_clock`
+    pub fn acknowledge(&mut self, site_id: u32, vector_clock: &[u32]) {
+        self.rga.acknowledge(site_id, vector_clock);
+    }
+
+    // Start the next CRDT session if one is due and every listed site has
+    // acknowledged all operations
+    // Returns the new session and the checkpoint clients must start it from
+    pub fn start_session_if_acknowledged(
+        &mut self,
+        active_sites: &[u32],
+    ) -> Option<(u32, Vec<u32>)> {
+        let checkpoint = self.rga.vector_clock().to_vec();
+        if !self.session_due
+            || self.rga.pending_count() > 0
+            || self.rga.stable_vector_clock(active_sites) != checkpoint
+        {
+            return None;
+        }
+
+        let session = match self.marks.start_session(&mut self.rga, &checkpoint) {
+            Ok(session) => session,
+            Err(e) => {
+                tracing::warn!(
+                    "Cannot start a new session of document {}: {}",
+                    self.filename,
+                    e
+                );
+                return None;
+            }
+        };
+        self.session_due = false;
+        self.buffered_ops.clear();
+        self.base_content = self.get_content();
+
+        tracing::info!(
+            "Document {} started session {} at {:?}",
+            self.filename,
+            session,
+            checkpoint
+        );
+
+        Some((session, checkpoint))
+    }
+
     // Force a checkpoint regardless of threshold
     pub fn force_checkpoint(&mut self) -> usize {
         self.checkpoint()
@@ -91,6 +319,61 @@ impl Document {
         self.rga.read().into_iter().collect()
     }
 
+    // Code point range of `length` positions from `position`, both counted
+    // in `encoding`
+    // None if the range is out of bounds or splits a character of `encoding`
+    // Only the text up to the end of the range is read, and only once
+    // until it changes
+    pub fn char_range(
+        &self,
+        encoding: PositionEncoding,
+        position: usize,
+        length: usize,
+    ) -> Option<Range<usize>> {
+        // The RGA counts code points itself
+        if encoding == PositionEncoding::CodePoint {
+            let end = position.checked_add(length)?;
+            return (end <= self.rga.len()).then_some(position..end);
+        }
+        let mut cache = self
+            .positions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let version = (self.rga.session(), self.rga.vector_clock());
+        let boundaries = cache
+            .get(version, encoding)?
+            .boundaries(encoding, |from| self.rga.iter_from(from).copied());
+        positions::char_range(boundaries, position, length)
+    }
+
+    // Positions in `encoding` of the code point indices `indices`, which
+    // must not decrease
+    // An index inside a character of `encoding` maps to that character
+    pub fn encoded_positions(&self, encoding: PositionEncoding, indices: &[usize]) -> Vec<usize> {
+        let mut cache = self
+            .positions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let version = (self.rga.session(), self.rga.vector_clock());
+        let Some(known) = cache.get(version, encoding) else {
+            return indices.to_vec();
+        };
+        let boundaries = known.boundaries(encoding, |from| self.rga.iter_from(from).copied());
+        positions::units_before(boundaries, indices)
+    }
+
+    // Content as of `vector_clock`, without needing a saved version
+    // Edits to text removed by `purge_tombstones` cannot be replayed, and
+    // clocks of an earlier session give None
+    pub fn content_at(&self, session: u32, vector_clock: &[u32]) -> Option<String> {
+        Some(
+            self.rga
+                .read_at(session, vector_clock)?
+                .into_iter()
+                .collect(),
+        )
+    }
+
     // Get base content (last checkpoint)
     pub fn get_base_content(&self) -> &str {
         &self.base_content
@@ -101,6 +384,12 @@ impl Document {
         &self.buffered_ops
     }
 
+    // Operations a client at `vector_clock` is missing
+    // None if they were compacted away and the client needs a full sync
+    pub fn ops_since(&self, vector_clock: &[u32]) -> Option<Vec<RemoteOp<char>>> {
+        self.rga.ops_since(vector_clock)
+    }
+
     // Get number of buffered operations
     pub fn buffered_ops_count(&self) -> usize {
         self.buffered_ops.len()
@@ -112,11 +401,6 @@ impl Document {
     }
 }
 
-// SAFETY: Document is always wrapped in Arc<RwLock<Document>>, which ensures
-// that only one thread can access the RGA CRDT at a time. Safe to use sync/send
-unsafe impl Send for Document {}
-unsafe impl Sync for Document {}
-
 // Shared document state wrapped in Arc<RwLock<>> for concurrent access
 pub type SharedDocument = Arc<RwLock<Document>>;
 
@@ -133,15 +417,11 @@ pub struct DocumentMetadata {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rga::Expand;
 
     #[test]
     fn test_document_creation() {
-        let doc = Document::new(
-            Uuid::new_v4(),
-            "test.txt".to_string(),
-            "Hello".to_string(),
-            2,
-        );
+        let doc = Document::new(Uuid::new_v4(), "test.txt".to_string(), "Hello".to_string());
 
         assert_eq!(doc.get_content(), "Hello");
         assert_eq!(doc.buffered_ops_count(), 0);
@@ -149,18 +429,22 @@ mod tests {
 
     #[test]
     fn test_checkpoint_threshold() {
-        let mut doc = Document::new(Uuid::new_v4(), "test.txt".to_string(), "".to_string(), 2);
+        let mut doc = Document::new(Uuid::new_v4(), "test.txt".to_string(), "".to_string());
+        let mut client = Rga::<char>::new(1, 2);
 
         for i in 0..(CHECKPOINT_THRESHOLD - 1) {
-            let op = doc.rga.insert_local(i, 'a').unwrap();
-            doc.apply_operation(op);
+            let op = client.insert_local(i, 'a').unwrap();
+            assert!(doc.apply_operation(op).unwrap());
         }
 
         assert_eq!(doc.buffered_ops_count(), CHECKPOINT_THRESHOLD - 1);
 
         // This operation should reach threshold
-        let op = doc.rga.insert_local(CHECKPOINT_THRESHOLD - 1, 'b').unwrap();
-        doc.apply_operation(op);
+        let op = client.insert_local(CHECKPOINT_THRESHOLD - 1, 'b').unwrap();
+        doc.apply_operation(op.clone()).unwrap();
+
+        // A retransmission is neither applied nor buffered again
+        assert!(!doc.apply_operation(op).unwrap());
 
         // Verify needs_checkpoint() returns true when threshold is reached
         assert!(doc.needs_checkpoint());
@@ -172,14 +456,39 @@ mod tests {
         assert_eq!(doc.buffered_ops_count(), 0);
     }
 
+    #[test]
+    fn test_marks_follow_text() {
+        let mut doc = Document::new(Uuid::new_v4(), "test.txt".to_string(), "Hello".to_string());
+        let mut client = Rga::<char>::new(1, 2);
+        for op in doc.ops_since(client.vector_clock()).unwrap() {
+            client.apply_remote(op).unwrap();
+        }
+
+        // A client bolds "Hello" and then types at its end
+        let mut marks = Marks::new(1, 2);
+        let bold = marks
+            .mark_local(
+                &client,
+                0..5,
+                "bold",
+                serde_json::json!(true),
+                Expand::After,
+            )
+            .unwrap();
+        assert!(doc.apply_mark(bold.clone()).unwrap());
+        assert!(!doc.apply_mark(bold).unwrap());
+        doc.apply_operation(client.insert_local(5, '!').unwrap())
+            .unwrap();
+
+        let spans = doc.marks.format_spans(&doc.rga);
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0].len, 6);
+        assert_eq!(doc.mark_ops().len(), 1);
+    }
+
     #[test]
     fn test_force_checkpoint() {
-        let mut doc = Document::new(
-            Uuid::new_v4(),
-            "test.txt".to_string(),
-            "Hello".to_string(),
-            2,
-        );
+        let mut doc = Document::new(Uuid::new_v4(), "test.txt".to_string(), "Hello".to_string());
 
         // Add a few operations
         for _ in 0..5 {
@@ -193,4 +502,215 @@ mod tests {
         assert_eq!(applied, 5);
         assert_eq!(doc.buffered_ops_count(), 0);
     }
+
+    #[test]
+    fn test_purge_tombstones() {
+        let mut doc = Document::new(Uuid::new_v4(), "test.txt".to_string(), "Hello".to_string());
+
+        let op = doc.rga.delete_local(0).unwrap();
+        doc.buffered_ops.push(op);
+        assert_eq!(doc.rga.tombstone_count(), 1);
+
+        // With no other sites in the room the server's own clock is stable
+        assert_eq!(doc.purge_tombstones(&[]), 1);
+        assert_eq!(doc.rga.tombstone_count(), 0);
+        assert_eq!(doc.get_content(), "ello");
+    }
+
+    #[test]
+    fn test_restore_from_replica_keeps_identifiers() {
+        let doc = Document::new(Uuid::new_v4(), "test.txt".to_string(), "Hello".to_string());
+        let id_e = doc.rga.id_at(1).unwrap();
+
+        let bytes = doc.rga.to_bytes().unwrap();
+        let mut restored = Document::from_replica(
+            doc.id,
+            doc.filename.clone(),
+            doc.base_content.clone(),
+            doc.buffered_ops.clone(),
+            Rga::from_bytes(&bytes).unwrap(),
+        );
+
+        assert_eq!(restored.get_content(), "Hello");
+        assert_eq!(restored.rga.index_of(&id_e), Some(1));
+
+        // New local ops continue the server's sequence instead of reusing it
+        let op = restored.rga.insert_local(0, '>').unwrap();
+        assert_eq!(op.s4v().seq, 6);
+        assert_eq!(restored.rga.index_of(&id_e), Some(2));
+    }
+
+    #[test]
+    fn test_restore_rebuilds_unreadable_replica() {
+        let mut doc = Document::new(Uuid::new_v4(), "test.txt".to_string(), "Hello".to_string());
+        let mut client = Rga::<char>::new(1, 2);
+        for op in doc.ops_since(client.vector_clock()).unwrap() {
+            client.apply_remote(op).unwrap();
+        }
+        doc.apply_operation(client.insert_local(5, '!').unwrap())
+            .unwrap();
+
+        // A replica written by an unknown version is replaced by a replay
+        let mut bytes = doc.rga.to_bytes().unwrap();
+        bytes[..4].copy_from_slice(&u32::MAX.to_le_bytes());
+        let restored = Document::restore(
+            doc.id,
+            doc.filename.clone(),
+            doc.base_content.clone(),
+            doc.buffered_ops.clone(),
+            Some(&bytes),
+        );
+        assert_eq!(restored.get_content(), "Hello!");
+        assert_eq!(restored.buffered_ops_count(), 1);
+
+        let bytes = doc.rga.to_bytes().unwrap();
+        let restored = Document::restore(
+            doc.id,
+            doc.filename.clone(),
+            doc.base_content.clone(),
+            doc.buffered_ops.clone(),
+            Some(&bytes),
+        );
+        assert_eq!(restored.rga.vector_clock(), doc.rga.vector_clock());
+    }
+
+    #[test]
+    fn test_ops_since_survives_checkpoint() {
+        let mut doc = Document::new(Uuid::new_v4(), "test.txt".to_string(), "ab".to_string());
+        let mut client = Rga::<char>::new(1, 2);
+        for op in doc.ops_since(client.vector_clock()).unwrap() {
+            client.apply_remote(op).unwrap();
+        }
+
+        // Another client edits while this one is away
+        let mut other = Rga::<char>::new(2, 3);
+        for op in doc.ops_since(other.vector_clock()).unwrap() {
+            other.apply_remote(op).unwrap();
+        }
+        doc.apply_operation(other.insert_local(2, 'c').unwrap())
+            .unwrap();
+        doc.checkpoint();
+        assert_eq!(doc.buffered_ops_count(), 0);
+
+        let missing = doc.ops_since(client.vector_clock()).unwrap();
+        assert_eq!(missing.len(), 1);
+        for op in missing {
+            client.apply_remote(op).unwrap();
+        }
+        assert_eq!(
+            client.read().into_iter().collect::<String>(),
+            doc.get_content()
+        );
+    }
+
+    #[test]
+    fn test_session_starts_once_acknowledged() {
+        let mut doc = Document::new(Uuid::new_v4(), "test.txt".to_string(), "ab".to_string());
+        let mut client = Rga::<char>::new(1, 2);
+        for op in doc.ops_since(client.vector_clock()).unwrap() {
+            client.apply_remote(op).unwrap();
+        }
+        doc.apply_operation(client.delete_local(0).unwrap())
+            .unwrap();
+        assert_eq!(doc.start_session_if_acknowledged(&[1]), None);
+
+        // Due after a checkpoint, but the client has not seen everything yet
+        let op = doc.rga.insert_local(1, 'c').unwrap();
+        doc.buffered_ops.push(op.clone());
+        doc.checkpoint();
+        doc.acknowledge(1, client.vector_clock());
+        assert_eq!(doc.start_session_if_acknowledged(&[1]), None);
+
+        client.apply_remote(op).unwrap();
+        doc.acknowledge(1, client.vector_clock());
+        let (session, checkpoint) = doc.start_session_if_acknowledged(&[1]).unwrap();
+        assert_eq!(doc.rga.tombstone_count(), 0);
+        assert_eq!(doc.buffered_ops_count(), 0);
+
+        // The client follows and both keep editing in the new session
+        client.start_session(&checkpoint).unwrap();
+        assert_eq!(client.session(), session);
+        doc.apply_operation(client.insert_local(0, '>').unwrap())
+            .unwrap();
+        assert_eq!(doc.get_content(), ">bc");
+        assert_eq!(doc.rga.vector_clock(), &[2, 1]);
+    }
+
+    #[test]
+    fn test_content_at_past_clock() {
+        let mut doc = Document::new(Uuid::new_v4(), "test.txt".to_string(), "draft".to_string());
+        let meeting = (doc.rga.session(), doc.rga.vector_clock().to_vec());
+
+        let mut client = Rga::<char>::new(1, 2);
+        for op in doc.ops_since(client.vector_clock()).unwrap() {
+            client.apply_remote(op).unwrap();
+        }
+        doc.apply_operation(client.delete_range_local(0, 5).unwrap())
+            .unwrap();
+        doc.apply_operation(
+            client
+                .insert_run_local(0, "final".chars().collect())
+                .unwrap(),
+        )
+        .unwrap();
+        doc.checkpoint();
+
+        assert_eq!(doc.get_content(), "final");
+        assert_eq!(
+            doc.content_at(meeting.0, &meeting.1).as_deref(),
+            Some("draft")
+        );
+    }
+
+    #[test]
+    fn test_positions_in_client_encodings() {
+        // "👋" is one code point and two UTF-16 units; "e\u{301}" is two
+        // code points and one grapheme cluster
+        let mut doc = Document::new(
+            Uuid::new_v4(),
+            "test.txt".to_string(),
+            "👋 cafe\u{301}!".to_string(),
+        );
+
+        // A browser deleting the accented "e" and a grapheme-based editor
+        // pointing at "!" both land on code points
+        assert_eq!(doc.char_range(PositionEncoding::Utf16, 6, 2), Some(5..7));
+        assert_eq!(doc.char_range(PositionEncoding::Grapheme, 6, 1), Some(7..8));
+        assert_eq!(
+            doc.char_range(PositionEncoding::CodePoint, 7, 1),
+            Some(7..8)
+        );
+        assert_eq!(doc.char_range(PositionEncoding::Utf16, 1, 0), None);
+        assert_eq!(doc.char_range(PositionEncoding::Grapheme, 5, 1), Some(5..7));
+        assert_eq!(doc.char_range(PositionEncoding::CodePoint, 8, 1), None);
+
+        let range = doc.char_range(PositionEncoding::Utf16, 3, 6).unwrap();
+        doc.rga.delete_range_local(range.start, range.len());
+        assert_eq!(doc.get_content(), "👋 ");
+
+        assert_eq!(
+            doc.encoded_positions(PositionEncoding::Utf16, &[0, 1, 2]),
+            vec![0, 2, 3]
+        );
+
+        // Boundaries cached before an edit are kept or recounted, whether
+        // the edit is made here or comes from a client
+        let mut client = Rga::<char>::new(1, 2);
+        for op in doc.ops_since(client.vector_clock()).unwrap() {
+            client.apply_remote(op).unwrap();
+        }
+        doc.edit_local(1, |rga| rga.insert_run_local(1, "😀x".chars().collect()))
+            .unwrap();
+        assert_eq!(doc.get_content(), "👋😀x ");
+        assert_eq!(doc.char_range(PositionEncoding::Utf16, 4, 1), Some(2..3));
+
+        doc.apply_operation(client.insert_local(0, '😀').unwrap())
+            .unwrap();
+        assert_eq!(doc.get_content(), "😀👋😀x ");
+        assert_eq!(doc.char_range(PositionEncoding::Utf16, 6, 2), Some(3..5));
+        assert_eq!(
+            doc.encoded_positions(PositionEncoding::Utf16, &[1, 2, 5]),
+            vec![2, 4, 8]
+        );
+    }
 }
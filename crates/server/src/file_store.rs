@@ -1,7 +1,7 @@
 // File storage for documents and operations
 
 use anyhow::{Context, Result};
-use rga::RemoteOp;
+use rga::{MarkOp, RemoteOp};
 use serde::{Deserialize, Serialize};
 use std::path::{Path, PathBuf};
 use tokio::fs;
@@ -17,6 +17,9 @@ pub struct StoredDocument {
     pub content: String,
     // Buffered operations since last checkpoint
     pub buffered_ops: Vec<RemoteOp<char>>,
+    // Formatting marks (missing in documents stored before marks existed)
+    #[serde(default)]
+    pub marks: Vec<MarkOp>,
     pub created_at: chrono::DateTime<chrono::Utc>,
     pub updated_at: chrono::DateTime<chrono::Utc>,
 }
@@ -44,6 +47,11 @@ impl FileStore {
         self.root_dir.join(format!("{room_id}.json"))
     }
 
+    // Get path for a document's serialized RGA replica
+    fn replica_path(&self, room_id: &str) -> PathBuf {
+        self.root_dir.join(format!("{room_id}.rga"))
+    }
+
     // Get path for a document's actual content file
     fn content_path(&self, room_id: &str, filename: &str) -> PathBuf {
         self.root_dir.join(format!("{room_id}_{filename}"))
@@ -83,6 +91,36 @@ impl FileStore {
         Ok(())
     }
 
+    // Save the binary-encoded RGA replica of a document
+    pub async fn save_replica(&self, room_id: &str, bytes: &[u8]) -> Result<()> {
+        let path = self.replica_path(room_id);
+
+        // Write to temporary file first, then rename (atomic operation)
+        let temp_path = path.with_extension("rga.tmp");
+        fs::write(&temp_path, bytes)
+            .await
+            .context("Failed to write replica")?;
+
+        fs::rename(&temp_path, &path)
+            .await
+            .context("Failed to rename temp replica file")?;
+
+        tracing::debug!("Saved replica for room {}", room_id);
+        Ok(())
+    }
+
+    // Load the binary-encoded RGA replica of a document, if one was saved
+    pub async fn load_replica(&self, room_id: &str) -> Result<Option<Vec<u8>>> {
+        let path = self.replica_path(room_id);
+
+        if fs::metadata(&path).await.is_err() {
+            return Ok(None);
+        }
+
+        let bytes = fs::read(&path).await.context("Failed to read replica")?;
+        Ok(Some(bytes))
+    }
+
     // Load document from disk
     pub async fn load_document(&self, room_id: &str) -> Result<StoredDocument> {
         let path = self.document_path(room_id);
@@ -113,10 +151,12 @@ impl FileStore {
     pub async fn delete_document(&self, room_id: &str, filename: &str) -> Result<()> {
         let doc_path = self.document_path(room_id);
         let content_path = self.content_path(room_id, filename);
+        let replica_path = self.replica_path(room_id);
 
-        // Delete both files, ignore errors if they don't exist
+        // Delete all files, ignore errors if they don't exist
         let _ = fs::remove_file(doc_path).await;
         let _ = fs::remove_file(content_path).await;
+        let _ = fs::remove_file(replica_path).await;
 
         tracing::debug!("Deleted document for room {}", room_id);
         Ok(())
@@ -203,6 +243,7 @@ mod tests {
             room_id: "room1".to_string(),
             content: "Hello World".to_string(),
             buffered_ops: vec![],
+            marks: vec![],
             created_at: chrono::Utc::now(),
             updated_at: chrono::Utc::now(),
         };
@@ -230,6 +271,7 @@ mod tests {
             room_id: "room1".to_string(),
             content: "Hello World".to_string(),
             buffered_ops: vec![],
+            marks: vec![],
             created_at: chrono::Utc::now(),
             updated_at: chrono::Utc::now(),
         };
@@ -241,4 +283,19 @@ mod tests {
         let docs = store.list_documents().await.unwrap();
         assert!(docs.iter().any(|d| d == "room1"));
     }
+
+    #[tokio::test]
+    async fn test_save_and_load_replica() {
+        let temp_dir = TempDir::new().unwrap();
+        let store = FileStore::new(temp_dir.path()).await.unwrap();
+
+        assert!(store.load_replica("room1").await.unwrap().is_none());
+
+        store.save_replica("room1", &[1, 2, 3]).await.unwrap();
+        let loaded = store.load_replica("room1").await.unwrap();
+        assert_eq!(loaded, Some(vec![1, 2, 3]));
+
+        // Replica files are not listed as documents
+        assert!(store.list_documents().await.unwrap().is_empty());
+    }
 }
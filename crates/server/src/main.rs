@@ -4,6 +4,7 @@ mod database;
 mod document;
 mod features;
 mod file_store;
+mod positions;
 mod room;
 mod secure_channel;
 mod server;
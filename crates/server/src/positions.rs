@@ -0,0 +1,376 @@
+// Position encodings of client connections
+//
+// The document is an `Rga<char>`, so the server counts positions in code
+// points. Clients count in whatever their platform does: JavaScript strings
+// in UTF-16 code units, editors that move the cursor by user-perceived
+// character in grapheme clusters. Positions are converted at the edge by
+// walking the boundaries of the client's units, as (units, code points)
+// pairs, only as far as needed. An offset that falls inside a surrogate
+// pair or a grapheme cluster is rejected rather than rounded: the client
+// counted in another encoding or has a different text.
+//
+// Boundaries found for one message are cached for the next (see
+// `PositionCache`), and after an edit only the text from the edit on is
+// walked again, resuming from the last boundary kept before it.
+
+use protocol::messages::PositionEncoding;
+use std::collections::VecDeque;
+use std::iter;
+use std::ops::Range;
+use unicode_segmentation::UnicodeSegmentation;
+
+// (units, code points) before every boundary of `encoding` in `text`, from
+// the start of the text to its end
+// Every encoding is counted lazily, so callers only pay for the text up to
+// the last position they need
+pub fn boundaries<'a>(
+    text: impl Iterator<Item = char> + 'a,
+    encoding: PositionEncoding,
+) -> Box<dyn Iterator<Item = (usize, usize)> + 'a> {
+    let width: fn(char) -> usize = match encoding {
+        PositionEncoding::CodePoint => |_: char| 1,
+        PositionEncoding::Utf16 => char::len_utf16,
+        PositionEncoding::Grapheme => {
+            return Box::new(iter::once((0, 0)).chain(Graphemes {
+                text,
+                pending: String::new(),
+                ready: VecDeque::new(),
+                units: 0,
+                chars: 0,
+            }));
+        }
+    };
+
+    Box::new(
+        iter::once((0, 0)).chain(text.scan((0, 0), move |(units, chars), ch| {
+            *units += width(ch);
+            *chars += 1;
+            Some((*units, *chars))
+        })),
+    )
+}
+
+// Ends of the grapheme clusters of `text`, as (clusters, code points)
+// A boundary never depends on the text after it, so every cluster but the
+// last one read is final and only that one is held back in `pending`
+struct Graphemes<I> {
+    text: I,
+    pending: String,
+    ready: VecDeque<(usize, usize)>,
+    units: usize,
+    chars: usize,
+}
+
+impl<I> Graphemes<I> {
+    // Queue the end of every cluster of `text`
+    fn push_clusters(&mut self, text: &str) {
+        for cluster in text.graphemes(true) {
+            self.units += 1;
+            self.chars += cluster.chars().count();
+            self.ready.push_back((self.units, self.chars));
+        }
+    }
+}
+
+impl<I: Iterator<Item = char>> Iterator for Graphemes<I> {
+    type Item = (usize, usize);
+
+    fn next(&mut self) -> Option<(usize, usize)> {
+        while self.ready.is_empty() {
+            let Some(ch) = self.text.next() else {
+                let rest = std::mem::take(&mut self.pending);
+                self.push_clusters(&rest);
+                break;
+            };
+            self.pending.push(ch);
+
+            let last = self
+                .pending
+                .grapheme_indices(true)
+                .next_back()
+                .map_or(0, |(start, _)| start);
+            if last > 0 {
+                let done: String = self.pending.drain(..last).collect();
+                self.push_clusters(&done);
+            }
+        }
+        self.ready.pop_front()
+    }
+}
+
+// Boundaries of one encoding found so far, from the start of the text
+// A boundary only depends on the text before it and the code point at it,
+// so an edit at some index leaves every boundary before that index valid
+#[derive(Debug)]
+pub struct BoundaryCache {
+    known: Vec<(usize, usize)>,
+}
+
+impl Default for BoundaryCache {
+    fn default() -> Self {
+        BoundaryCache {
+            known: vec![(0, 0)],
+        }
+    }
+}
+
+impl BoundaryCache {
+    // Forget the boundaries at or after code point `index`
+    pub fn invalidate_from(&mut self, index: usize) {
+        let keep = self.known.partition_point(|&(_, chars)| chars < index);
+        self.known.truncate(keep.max(1));
+    }
+
+    // Every boundary of `encoding`, as `boundaries` gives them: the known
+    // ones, then those found by segmenting `text_from(chars)`, the text
+    // from the code point after the last known one on, which are kept
+    pub fn boundaries<'a, I: Iterator<Item = char> + 'a>(
+        &'a mut self,
+        encoding: PositionEncoding,
+        text_from: impl FnOnce(usize) -> I,
+    ) -> impl Iterator<Item = (usize, usize)> + 'a {
+        let (units, chars) = self.known[self.known.len() - 1];
+        let found = boundaries(text_from(chars), encoding)
+            .skip(1)
+            .map(move |(u, c)| (units + u, chars + c));
+        Cached {
+            known: &mut self.known,
+            next: 0,
+            found,
+        }
+    }
+}
+
+// Known boundaries followed by newly found ones, which are recorded
+struct Cached<'a, I> {
+    known: &'a mut Vec<(usize, usize)>,
+    next: usize,
+    found: I,
+}
+
+impl<I: Iterator<Item = (usize, usize)>> Iterator for Cached<'_, I> {
+    type Item = (usize, usize);
+
+    fn next(&mut self) -> Option<(usize, usize)> {
+        if self.next == self.known.len() {
+            let boundary = self.found.next()?;
+            self.known.push(boundary);
+        }
+        self.next += 1;
+        Some(self.known[self.next - 1])
+    }
+}
+
+// Boundaries of a document's text in the encodings that are not code
+// points, kept across messages until the text changes
+#[derive(Debug, Default)]
+pub struct PositionCache {
+    // Session and vector clock of the text the boundaries belong to
+    version: (u32, Vec<u32>),
+    utf16: BoundaryCache,
+    grapheme: BoundaryCache,
+}
+
+impl PositionCache {
+    // Boundaries of `encoding` (None for code points, which need none) in
+    // the text at `version`
+    // Boundaries of another text are dropped: it changed without `edited`
+    pub fn get(
+        &mut self,
+        version: (u32, &[u32]),
+        encoding: PositionEncoding,
+    ) -> Option<&mut BoundaryCache> {
+        if self.version.0 != version.0 || self.version.1 != version.1 {
+            *self = PositionCache {
+                version: (version.0, version.1.to_vec()),
+                ..PositionCache::default()
+            };
+        }
+        match encoding {
+            PositionEncoding::CodePoint => None,
+            PositionEncoding::Utf16 => Some(&mut self.utf16),
+            PositionEncoding::Grapheme => Some(&mut self.grapheme),
+        }
+    }
+
+    // Record that the text at `before` changed from code point `index` on
+    // and is now at `after`, keeping the boundaries before `index`
+    pub fn edited(&mut self, before: (u32, &[u32]), after: (u32, &[u32]), index: usize) {
+        if self.version.0 != before.0 || self.version.1 != before.1 {
+            // Nothing cached for that text anyway
+            *self = PositionCache::default();
+        }
+        self.utf16.invalidate_from(index);
+        self.grapheme.invalidate_from(index);
+        self.version = (after.0, after.1.to_vec());
+    }
+}
+
+// Code point range of the `length` units from `position`
+// None if either end is past the text or not on a boundary
+pub fn char_range(
+    mut boundaries: impl Iterator<Item = (usize, usize)>,
+    position: usize,
+    length: usize,
+) -> Option<Range<usize>> {
+    let end = position.checked_add(length)?;
+    let (units, start) = boundaries.find(|&(units, _)| units >= position)?;
+    if units != position {
+        return None;
+    }
+    if length == 0 {
+        return Some(start..start);
+    }
+
+    let (units, chars) = boundaries.find(|&(units, _)| units >= end)?;
+    (units == end).then_some(start..chars)
+}
+
+// Units before each code point of `indices`, which must not decrease, in
+// one pass; a unit spanning an index is not counted
+pub fn units_before(
+    boundaries: impl Iterator<Item = (usize, usize)>,
+    indices: &[usize],
+) -> Vec<usize> {
+    let mut boundaries = boundaries.peekable();
+    let mut units = 0;
+    indices
+        .iter()
+        .map(|&index| {
+            while let Some((next, _)) = boundaries.next_if(|&(_, chars)| chars <= index) {
+                units = next;
+            }
+            units
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(
+        text: &str,
+        encoding: PositionEncoding,
+        position: usize,
+        length: usize,
+    ) -> Option<Range<usize>> {
+        char_range(boundaries(text.chars(), encoding), position, length)
+    }
+
+    #[test]
+    fn test_utf16_surrogate_pairs() {
+        // "a😀b": the emoji is one code point but two UTF-16 units
+        let text = "a😀b";
+        assert_eq!(range(text, PositionEncoding::Utf16, 1, 2), Some(1..2));
+        assert_eq!(range(text, PositionEncoding::Utf16, 3, 1), Some(2..3));
+        assert_eq!(range(text, PositionEncoding::Utf16, 4, 0), Some(3..3));
+
+        // Offsets between the two halves of the pair, or past the end
+        assert_eq!(range(text, PositionEncoding::Utf16, 2, 0), None);
+        assert_eq!(range(text, PositionEncoding::Utf16, 1, 1), None);
+        assert_eq!(range(text, PositionEncoding::Utf16, 3, 2), None);
+
+        let bounds = || boundaries(text.chars(), PositionEncoding::Utf16);
+        assert_eq!(units_before(bounds(), &[1, 2, 3]), vec![1, 3, 4]);
+    }
+
+    #[test]
+    fn test_grapheme_combining_marks() {
+        // "e\u{301}" is one cluster of two code points, and a flag is one
+        // cluster of two regional indicators
+        let text = "ne\u{301}e 🇫🇷!";
+        assert_eq!(range(text, PositionEncoding::Grapheme, 1, 1), Some(1..3));
+        assert_eq!(range(text, PositionEncoding::Grapheme, 2, 0), Some(3..3));
+        assert_eq!(range(text, PositionEncoding::Grapheme, 4, 1), Some(5..7));
+        assert_eq!(range(text, PositionEncoding::Grapheme, 6, 0), Some(8..8));
+        assert_eq!(range(text, PositionEncoding::Grapheme, 6, 1), None);
+
+        // The combining mark alone is not a grapheme position
+        let bounds = || boundaries(text.chars(), PositionEncoding::Grapheme);
+        assert_eq!(units_before(bounds(), &[2, 3, 3, 7]), vec![1, 2, 2, 5]);
+
+        // Code points count the mark separately
+        assert_eq!(range(text, PositionEncoding::CodePoint, 2, 1), Some(2..3));
+    }
+
+    #[test]
+    fn test_encodings_agree_on_ascii() {
+        for encoding in [
+            PositionEncoding::CodePoint,
+            PositionEncoding::Utf16,
+            PositionEncoding::Grapheme,
+        ] {
+            assert_eq!(range("hello", encoding, 1, 3), Some(1..4));
+            assert_eq!(range("hello", encoding, 5, 0), Some(5..5));
+            assert_eq!(range("hello", encoding, 5, 1), None);
+            assert_eq!(range("", encoding, 0, 0), Some(0..0));
+        }
+    }
+
+    #[test]
+    fn test_boundaries_stop_where_needed() {
+        // Endless text: every encoding must stop at the end of the range
+        for encoding in [
+            PositionEncoding::CodePoint,
+            PositionEncoding::Utf16,
+            PositionEncoding::Grapheme,
+        ] {
+            let text = iter::repeat("ab😀e\u{301}".chars()).flatten();
+            assert!(char_range(boundaries(text, encoding), 2, 3).is_some());
+        }
+
+        // Segmenting as the text comes gives the same clusters as
+        // segmenting it whole
+        let text = "🇫🇷🇩🇪🇮👨\u{200d}👩\u{200d}👧x\u{301}\u{302}\r\n";
+        let mut chars = 0;
+        let expected: Vec<(usize, usize)> = iter::once((0, 0))
+            .chain(text.graphemes(true).enumerate().map(|(i, cluster)| {
+                chars += cluster.chars().count();
+                (i + 1, chars)
+            }))
+            .collect();
+        let lazy: Vec<(usize, usize)> =
+            boundaries(text.chars(), PositionEncoding::Grapheme).collect();
+        assert_eq!(lazy, expected);
+    }
+
+    #[test]
+    fn test_cache_resumes_after_edits() {
+        let mut text: Vec<char> = "a😀e\u{301} 🇫🇷!".chars().collect();
+        let mut cache = BoundaryCache::default();
+        let encoding = PositionEncoding::Grapheme;
+
+        // Reading part of the text only caches that far
+        let read = |cache: &mut BoundaryCache, text: &[char]| {
+            let cached: Vec<_> = cache
+                .boundaries(encoding, |from| text[from..].iter().copied())
+                .collect();
+            assert_eq!(
+                cached,
+                boundaries(text.iter().copied(), encoding).collect::<Vec<_>>()
+            );
+        };
+        assert_eq!(
+            char_range(
+                cache.boundaries(encoding, |from| text[from..].iter().copied()),
+                1,
+                1
+            ),
+            Some(1..2)
+        );
+        read(&mut cache, &text);
+
+        // A combining mark after "a" joins its cluster; a flag half after
+        // the flag starts a new one
+        text.insert(1, '\u{301}');
+        cache.invalidate_from(1);
+        read(&mut cache, &text);
+        text.insert(8, '🇩');
+        cache.invalidate_from(8);
+        read(&mut cache, &text);
+        text.drain(0..3);
+        cache.invalidate_from(0);
+        read(&mut cache, &text);
+    }
+}
@@ -4,8 +4,8 @@ use crate::document::{Document, SharedDocument};
 use anyhow::{anyhow, Result};
 use argon2::password_hash::{rand_core::OsRng, SaltString};
 use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
-use protocol::messages::ServerMessage;
-use rga::RemoteOp;
+use protocol::messages::{BlameSpan, PositionEncoding, ServerMessage};
+use rga::{MarkOp, RemoteOp};
 use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::{mpsc, RwLock};
@@ -17,6 +17,10 @@ pub struct Client {
     pub id: Uuid,
     pub site_id: u32,
     pub sender: mpsc::UnboundedSender<ServerMessage>,
+
+    // Whether the client keeps an RGA replica (it has asked for the op log,
+    // acknowledged or sent ops); clients that edit by position do not
+    pub holds_replica: bool,
 }
 
 // A collaborative editing room
@@ -37,6 +41,9 @@ pub struct Room {
     // Connected clients
     pub(crate) clients: HashMap<Uuid, Client>,
 
+    // Client that joined as each site ID, kept after it leaves for blame views
+    pub(crate) site_clients: HashMap<u32, Uuid>,
+
     // Next site ID to assign
     pub next_site_id: u32,
 
@@ -63,7 +70,7 @@ impl Room {
 
         // Create document (site 0 is reserved for server)
         let doc_id = Uuid::new_v4();
-        let document = Document::new(doc_id, filename, initial_content, 10); // Start with 10 sites
+        let document = Document::new(doc_id, filename, initial_content);
 
         Ok(Room {
             id,
@@ -71,6 +78,7 @@ impl Room {
             password_hash,
             document: Arc::new(RwLock::new(document)),
             clients: HashMap::new(),
+            site_clients: HashMap::new(),
             next_site_id: 1, // Start from 1 (0 is server)
             created_at: chrono::Utc::now(),
         })
@@ -101,9 +109,11 @@ impl Room {
             id: client_id,
             site_id,
             sender,
+            holds_replica: false,
         };
 
         self.clients.insert(client_id, client);
+        self.site_clients.insert(site_id, client_id);
 
         // Notify other clients
         self.broadcast_except(
@@ -149,17 +159,59 @@ impl Room {
         self.clients.len()
     }
 
+    // Record that a client keeps a replica of the document
+    pub fn mark_replica(&mut self, client_id: Uuid) {
+        if let Some(client) = self.clients.get_mut(&client_id) {
+            client.holds_replica = true;
+        }
+    }
+
+    // Sites whose replicas purging and new sessions wait for: connected
+    // clients holding one, and sites whose ops are still buffered in `doc`
+    // A site that left is dropped once its last ops are integrated
+    pub fn replica_site_ids(&self, doc: &Document) -> Vec<u32> {
+        let mut sites: Vec<u32> = self
+            .clients
+            .values()
+            .filter(|c| c.holds_replica)
+            .map(|c| c.site_id)
+            .chain(doc.rga.pending_ops().map(|op| op.s4v().sid))
+            .collect();
+        sites.sort_unstable();
+        sites.dedup();
+        sites
+    }
+
+    // Checkpoint the document if enough ops are buffered, then purge the
+    // tombstones every replica has seen deleted
+    // Returns the checkpointed content and the number of ops applied
+    pub fn checkpoint_if_needed(&self, doc: &mut Document) -> Option<(String, usize)> {
+        if !doc.needs_checkpoint() {
+            return None;
+        }
+        let ops_applied = doc.checkpoint();
+        doc.purge_tombstones(&self.replica_site_ids(doc));
+        Some((doc.get_content(), ops_applied))
+    }
+
     // Check if room is empty
     pub fn is_empty(&self) -> bool {
         self.clients.is_empty()
     }
 
     // Broadcast operation to all clients except sender
+    // A Batch goes out as one message, so clients apply it all at once
     pub async fn broadcast_operation(&self, from_client: Uuid, from_site: u32, op: RemoteOp<char>) {
         let message = ServerMessage::Operation { from_site, op };
         self.broadcast_except(from_client, message).await;
     }
 
+    // Broadcast formatting mark to all clients except sender
+    pub async fn broadcast_mark(&self, from_client: Uuid, from_site: u32, op: MarkOp) {
+        let message = ServerMessage::Mark { from_site, op };
+        self.broadcast_except(from_client, message).await;
+    }
+
     // Broadcast checkpoint to all clients
     pub async fn broadcast_checkpoint(&self, content: String, ops_applied: usize) {
         let message = ServerMessage::Checkpoint {
@@ -169,16 +221,45 @@ impl Room {
         self.broadcast(message).await;
     }
 
+    // Start the next CRDT session if one is due and every replica is at the
+    // checkpoint (see `replica_site_ids`), telling all clients to follow
+    // Returns whether a session started
+    pub async fn start_session_if_ready(&self) -> bool {
+        let started = {
+            let mut doc = self.document.write().await;
+            let sites = self.replica_site_ids(&doc);
+            doc.start_session_if_acknowledged(&sites)
+        };
+        match started {
+            Some((session, vector_clock)) => {
+                self.broadcast_new_session(session, vector_clock).await;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Tell all clients to start a new CRDT session from `vector_clock`
+    pub async fn broadcast_new_session(&self, session: u32, vector_clock: Vec<u32>) {
+        let message = ServerMessage::NewSession {
+            session,
+            vector_clock,
+        };
+        self.broadcast(message).await;
+    }
+
     // Broadcast sync response to all clients (for auto-sync after operations)
     pub async fn broadcast_sync(&self) {
         let doc = self.document.read().await;
         let content = doc.get_content();
         let buffered_ops = doc.get_buffered_ops().to_vec();
+        let marks = doc.mark_ops();
         drop(doc);
 
         let message = ServerMessage::SyncResponse {
             document_content: content,
             buffered_ops,
+            marks,
         };
         self.broadcast(message).await;
     }
@@ -210,13 +291,42 @@ impl Room {
         Ok(())
     }
 
+    // Authorship of the current document, with each site mapped back to the
+    // client that joined as it and positions counted in `encoding`
+    pub async fn blame(&self, encoding: PositionEncoding) -> Vec<BlameSpan> {
+        let doc = self.document.read().await;
+        let spans = doc.rga.authorship();
+        let user = |site: u32| self.site_clients.get(&site).map(Uuid::to_string);
+
+        let bounds: Vec<usize> = spans
+            .iter()
+            .flat_map(|span| [span.index, span.index + span.len])
+            .collect();
+        let bounds = doc.encoded_positions(encoding, &bounds);
+
+        spans
+            .into_iter()
+            .zip(bounds.chunks_exact(2))
+            .map(|(span, bounds)| BlameSpan {
+                position: bounds[0],
+                length: bounds[1] - bounds[0],
+                site_id: span.site,
+                user_id: user(span.site),
+                inserted: span.inserted,
+                last_update: span.last_update,
+                updated_by: span.last_update.and_then(|s4v| user(s4v.sid)),
+            })
+            .collect()
+    }
+
     // Get room info for new joiners
-    pub async fn get_room_info(&self) -> (String, String, Vec<RemoteOp<char>>) {
+    pub async fn get_room_info(&self) -> (String, String, Vec<RemoteOp<char>>, Vec<MarkOp>) {
         let doc = self.document.read().await;
         (
             doc.filename.clone(),
             doc.get_base_content().to_string(),
             doc.get_buffered_ops().to_vec(),
+            doc.mark_ops(),
         )
     }
 }
@@ -267,4 +377,124 @@ mod tests {
         assert_eq!(room.client_count(), 0);
         assert!(room.is_empty());
     }
+
+    #[tokio::test]
+    async fn test_replica_sites() {
+        let mut room = Room::new(
+            "room1".to_string(),
+            "Test Room".to_string(),
+            "password123",
+            "test.txt".to_string(),
+            "Hello".to_string(),
+        )
+        .unwrap();
+
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let (web, cli, gone) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        room.add_client(web, tx.clone()).await.unwrap();
+        let cli_site = room.add_client(cli, tx.clone()).await.unwrap();
+        let gone_site = room.add_client(gone, tx).await.unwrap();
+        room.mark_replica(cli);
+        room.mark_replica(gone);
+
+        // The departed site's op waits on an insert the server never got
+        let mut replica = rga::Rga::<char>::new(gone_site, 4);
+        let mut other = rga::Rga::<char>::new(cli_site, 4);
+        let first = other.insert_local(0, 'x').unwrap();
+        replica.apply_remote(first.clone()).unwrap();
+        let late = replica.insert_local(1, 'y').unwrap();
+        room.remove_client(gone).await.unwrap();
+
+        let mut doc = room.document.write().await;
+        assert_eq!(room.replica_site_ids(&doc), vec![cli_site]);
+        doc.apply_operation(late).unwrap();
+        assert_eq!(room.replica_site_ids(&doc), vec![cli_site, gone_site]);
+
+        // Integrating its last op forgets the departed site
+        doc.apply_operation(first).unwrap();
+        assert_eq!(room.replica_site_ids(&doc), vec![cli_site]);
+    }
+
+    #[tokio::test]
+    async fn test_sessions_wait_for_replicas_only() {
+        let mut room = Room::new(
+            "room1".to_string(),
+            "Test Room".to_string(),
+            "password123",
+            "test.txt".to_string(),
+            "Hello".to_string(),
+        )
+        .unwrap();
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let (web, cli) = (Uuid::new_v4(), Uuid::new_v4());
+        room.add_client(web, tx.clone()).await.unwrap();
+        let cli_site = room.add_client(cli, tx).await.unwrap();
+        room.mark_replica(cli);
+        while rx.try_recv().is_ok() {}
+
+        let checkpoint = {
+            let mut doc = room.document.write().await;
+            let op = doc.rga.insert_local(5, '!').unwrap();
+            doc.buffered_ops.push(op);
+            doc.checkpoint();
+            doc.rga.vector_clock().to_vec()
+        };
+        assert!(!room.start_session_if_ready().await);
+
+        // The web client never acknowledges, and need not
+        room.document
+            .write()
+            .await
+            .acknowledge(cli_site, &checkpoint);
+        assert!(room.start_session_if_ready().await);
+        assert!(matches!(
+            rx.try_recv(),
+            Ok(ServerMessage::NewSession { session: 2, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_blame_maps_sites_to_clients() {
+        let mut room = Room::new(
+            "room1".to_string(),
+            "Test Room".to_string(),
+            "password123",
+            "test.txt".to_string(),
+            "Hello".to_string(),
+        )
+        .unwrap();
+
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let client_id = Uuid::new_v4();
+        let site_id = room.add_client(client_id, tx).await.unwrap();
+
+        let mut replica = rga::Rga::<char>::new(site_id, 2);
+        {
+            let mut doc = room.document.write().await;
+            for op in doc.ops_since(replica.vector_clock()).unwrap() {
+                replica.apply_remote(op).unwrap();
+            }
+            doc.apply_operation(
+                replica
+                    .insert_run_local(5, "😀!".chars().collect())
+                    .unwrap(),
+            )
+            .unwrap();
+        }
+
+        // Authorship survives the client leaving
+        room.remove_client(client_id).await.unwrap();
+        let spans = room.blame(PositionEncoding::CodePoint).await;
+
+        assert_eq!(spans.len(), 2);
+        assert_eq!((spans[0].position, spans[0].length), (0, 5));
+        assert_eq!((spans[0].site_id, spans[0].user_id.as_deref()), (0, None));
+        assert_eq!((spans[1].position, spans[1].length), (5, 2));
+        assert_eq!(spans[1].user_id, Some(client_id.to_string()));
+
+        // The emoji takes two UTF-16 units
+        let spans = room.blame(PositionEncoding::Utf16).await;
+        assert_eq!((spans[1].position, spans[1].length), (5, 3));
+    }
 }
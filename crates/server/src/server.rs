@@ -17,7 +17,7 @@ use axum::{
     Router,
 };
 use futures_util::{SinkExt, StreamExt}; // For split() and next()
-use protocol::messages::{ClientMessage, ServerMessage};
+use protocol::messages::{ClientMessage, PositionEncoding, ServerMessage};
 use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::sync::Arc;
@@ -96,16 +96,29 @@ impl ServerState {
             .await?
             .ok_or_else(|| anyhow!("Room not found"))?;
 
-        // Reconstruct document
+        // Reconstruct document, preferring the persisted replica so that
+        // identifiers clients already hold stay valid across restarts
+        // A replica that cannot be loaded is rebuilt rather than failing
+        // the room
         let doc_id = Uuid::parse_str(&stored_doc.id)?;
-        let mut document =
-            Document::new(doc_id, stored_doc.filename.clone(), stored_doc.content, 10);
-
-        // Reapply buffered operations
-        for op in stored_doc.buffered_ops {
-            document.apply_operation(op);
+        let replica = self.file_store.load_replica(room_id).await?;
+        let mut document = Document::restore(
+            doc_id,
+            stored_doc.filename.clone(),
+            stored_doc.content,
+            stored_doc.buffered_ops,
+            replica.as_deref(),
+        );
+
+        for op in stored_doc.marks {
+            if let Err(e) = document.apply_mark(op) {
+                tracing::warn!("Skipping stored mark for room {}: {}", room_id, e);
+            }
         }
 
+        // Never hand out a site ID the restored replica has already seen
+        let next_site_id = document.rga.vector_clock().len().max(1) as u32;
+
         // Create room (note: we can't get the original password, so verification will use stored hash)
         let created_at = room_record.created_at_parsed()?;
 
@@ -115,7 +128,8 @@ impl ServerState {
             password_hash: room_record.password_hash.clone(),
             document: Arc::new(RwLock::new(document)),
             clients: HashMap::new(),
-            next_site_id: 1,
+            site_clients: HashMap::new(),
+            next_site_id,
             created_at,
         };
 
@@ -147,19 +161,22 @@ impl ServerState {
             .await?;
 
         // Save to file store
-        let stored_doc = {
+        let (stored_doc, replica) = {
             let doc = room.document.read().await;
-            StoredDocument {
+            let stored_doc = StoredDocument {
                 id: doc.id.to_string(),
                 filename: filename.clone(),
                 room_id: room_id.clone(),
                 content: initial_content,
                 buffered_ops: vec![],
+                marks: vec![],
                 created_at: chrono::Utc::now(),
                 updated_at: chrono::Utc::now(),
-            }
+            };
+            (stored_doc, doc.rga.to_bytes()?)
         }; // doc is dropped here, releasing the borrow
         self.file_store.save_document(&stored_doc).await?;
+        self.file_store.save_replica(&room_id, &replica).await?;
 
         // Add to memory
         let room_arc = Arc::new(RwLock::new(room));
@@ -185,16 +202,47 @@ impl ServerState {
             room_id: room_id.to_string(),
             content: doc.get_base_content().to_string(),
             buffered_ops: doc.get_buffered_ops().to_vec(),
+            marks: doc.mark_ops(),
             created_at: room_guard.created_at,
             updated_at: chrono::Utc::now(),
         };
+        let replica = doc.rga.to_bytes()?;
 
         self.file_store.save_document(&stored_doc).await?;
+        self.file_store.save_replica(room_id, &replica).await?;
         self.db.touch_room(room_id).await?;
 
         Ok(())
     }
 
+    // Tell a room's clients about a checkpoint, start the next session right
+    // away if no replica lags behind it, and persist the room
+    async fn finish_checkpoint(
+        &self,
+        room_id: &str,
+        content: String,
+        ops_applied: usize,
+    ) -> Result<()> {
+        if let Some(room) = self.get_room(room_id).await? {
+            let room_guard = room.read().await;
+            room_guard.broadcast_checkpoint(content, ops_applied).await;
+            room_guard.start_session_if_ready().await;
+        }
+        self.persist_room(room_id).await
+    }
+
+    // Start the next session of a room if a client leaving unblocked it
+    async fn start_session_after_leave(&self, room_id: &str) -> Result<()> {
+        let started = match self.get_room(room_id).await? {
+            Some(room) => room.read().await.start_session_if_ready().await,
+            None => false,
+        };
+        if started {
+            self.persist_room(room_id).await?;
+        }
+        Ok(())
+    }
+
     // Remove room if empty
     async fn cleanup_room(&self, room_id: &str) -> Result<()> {
         let room = match self.get_room(room_id).await? {
@@ -283,6 +331,7 @@ async fn handle_socket(socket: WebSocket, state: ServerState) {
     });
 
     let mut current_room: Option<String> = None;
+    let mut encoding = PositionEncoding::default();
 
     // Receiving loop
     while let Some(Ok(msg)) = receiver.next().await {
@@ -309,6 +358,7 @@ async fn handle_socket(socket: WebSocket, state: ServerState) {
                             &tx,
                             client_msg,
                             &mut current_room,
+                            &mut encoding,
                         )
                         .await
                         {
@@ -336,6 +386,7 @@ async fn handle_socket(socket: WebSocket, state: ServerState) {
         if let Ok(Some(room)) = state.get_room(&room_id).await {
             let _ = room.write().await.remove_client(client_id).await;
             let _ = state.db.remove_user(&client_id.to_string(), &room_id).await;
+            let _ = state.start_session_after_leave(&room_id).await;
             let _ = state.cleanup_room(&room_id).await;
         }
     }
@@ -351,6 +402,7 @@ async fn handle_client_message(
     tx: &mpsc::UnboundedSender<ServerMessage>,
     message: ClientMessage,
     current_room: &mut Option<String>,
+    encoding: &mut PositionEncoding,
 ) -> Result<()> {
     match message {
         ClientMessage::CreateRoom {
@@ -384,7 +436,6 @@ async fn handle_client_message(
             tx.send(ServerMessage::RoomCreated {
                 room_id,
                 site_id,
-                num_sites: 10,
                 filename: filename_for_response,
                 document_content: content_for_response,
             })?;
@@ -409,17 +460,18 @@ async fn handle_client_message(
                 .await?;
 
             // Send room info
-            let (filename, base_content, buffered_ops) = room.read().await.get_room_info().await;
+            let (filename, base_content, buffered_ops, marks) =
+                room.read().await.get_room_info().await;
 
             *current_room = Some(room_id.clone());
 
             tx.send(ServerMessage::JoinedRoom {
                 room_id,
                 site_id,
-                num_sites: 10,
                 filename,
                 document_content: base_content,
                 buffered_ops,
+                marks,
             })?;
         }
 
@@ -431,11 +483,16 @@ async fn handle_client_message(
                         .db
                         .remove_user(&client_id.to_string(), &room_id)
                         .await?;
+                    state.start_session_after_leave(&room_id).await?;
                     state.cleanup_room(&room_id).await?;
                 }
             }
         }
 
+        ClientMessage::SetPositionEncoding { encoding: declared } => {
+            *encoding = declared;
+        }
+
         ClientMessage::Operation { op } => {
             tracing::info!("Received operation: {:?}", op);
 
@@ -445,9 +502,11 @@ async fn handle_client_message(
                     .await?
                     .ok_or_else(|| anyhow!("Room not found"))?;
 
-                // Get site_id for this client
+                // Get site_id for this client, which keeps a replica to
+                // generate ops from
                 let site_id = {
-                    let room_guard = room.read().await;
+                    let mut room_guard = room.write().await;
+                    room_guard.mark_replica(client_id);
                     room_guard
                         .clients
                         .get(&client_id)
@@ -459,25 +518,27 @@ async fn handle_client_message(
                 {
                     let room_guard = room.read().await;
                     let mut doc = room_guard.document.write().await;
-                    doc.apply_operation(op.clone());
+                    match doc.apply_operation(op.clone()) {
+                        Ok(true) => {}
+                        // Retransmission: others already have it
+                        Ok(false) => return Ok(()),
+                        Err(e) => {
+                            tx.send(ServerMessage::Error {
+                                message: format!("Operation rejected: {e}"),
+                            })?;
+                            return Ok(());
+                        }
+                    }
 
                     // Check if checkpoint needed
-                    if doc.needs_checkpoint() {
-                        let ops_applied = doc.checkpoint();
-                        let content = doc.get_content();
-
+                    if let Some((content, ops_applied)) = room_guard.checkpoint_if_needed(&mut doc)
+                    {
                         // Drop locks before broadcasting
                         drop(doc);
                         drop(room_guard);
-
-                        // Broadcast checkpoint
-                        room.read()
-                            .await
-                            .broadcast_checkpoint(content, ops_applied)
-                            .await;
-
-                        // Persist to disk
-                        state.persist_room(room_id).await?;
+                        state
+                            .finish_checkpoint(room_id, content, ops_applied)
+                            .await?;
                     }
                 }
 
@@ -489,6 +550,48 @@ async fn handle_client_message(
             }
         }
 
+        ClientMessage::Mark { op } => {
+            if let Some(room_id) = current_room.as_ref() {
+                let room = state
+                    .get_room(room_id)
+                    .await?
+                    .ok_or_else(|| anyhow!("Room not found"))?;
+
+                let site_id = {
+                    let room_guard = room.read().await;
+                    room_guard
+                        .clients
+                        .get(&client_id)
+                        .map(|c| c.site_id)
+                        .ok_or_else(|| anyhow!("Client not found in room"))?
+                };
+
+                let applied = {
+                    let room_guard = room.read().await;
+                    let mut doc = room_guard.document.write().await;
+                    doc.apply_mark(op.clone())
+                };
+                match applied {
+                    Ok(true) => {}
+                    // Retransmission: others already have it
+                    Ok(false) => return Ok(()),
+                    Err(e) => {
+                        tx.send(ServerMessage::Error {
+                            message: format!("Mark rejected: {e}"),
+                        })?;
+                        return Ok(());
+                    }
+                }
+
+                // Marks are few and small, so persist each one right away
+                state.persist_room(room_id).await?;
+                room.read()
+                    .await
+                    .broadcast_mark(client_id, site_id, op)
+                    .await;
+            }
+        }
+
         ClientMessage::Insert { position, text } => {
             if let Some(room_id) = current_room.as_ref() {
                 let room = state
@@ -506,32 +609,34 @@ async fn handle_client_message(
                         .ok_or_else(|| anyhow!("Client not found in room"))?
                 };
 
-                // Insert each character using insert_local to get proper CRDT operations
+                // Insert the whole text as one run, producing a single CRDT operation
                 let mut ops = Vec::new();
                 {
                     let room_guard = room.read().await;
                     let mut doc = room_guard.document.write().await;
 
-                    for (i, ch) in text.chars().enumerate() {
-                        if let Some(op) = doc.rga.insert_local(position + i, ch) {
-                            doc.buffered_ops.push(op.clone());
-                            ops.push(op);
-                        }
+                    let Some(index) = doc.char_range(*encoding, position, 0) else {
+                        tx.send(ServerMessage::Error {
+                            message: "Insert position out of bounds".to_string(),
+                        })?;
+                        return Ok(());
+                    };
+
+                    if let Some(op) = doc.edit_local(index.start, |rga| {
+                        rga.insert_run_local(index.start, text.chars().collect())
+                    }) {
+                        ops.push(op);
                     }
 
                     // Check if checkpoint needed
-                    if doc.needs_checkpoint() {
-                        let ops_applied = doc.checkpoint();
-                        let content = doc.get_content();
+                    if let Some((content, ops_applied)) = room_guard.checkpoint_if_needed(&mut doc)
+                    {
+                        // Drop locks before broadcasting
                         drop(doc);
                         drop(room_guard);
-
-                        room.read()
-                            .await
-                            .broadcast_checkpoint(content, ops_applied)
-                            .await;
-
-                        state.persist_room(room_id).await?;
+                        state
+                            .finish_checkpoint(room_id, content, ops_applied)
+                            .await?;
                     }
                 }
 
@@ -565,32 +670,169 @@ async fn handle_client_message(
                         .ok_or_else(|| anyhow!("Client not found in room"))?
                 };
 
-                // Delete each character using delete_local
+                // Delete the whole range with a single CRDT operation
                 let mut ops = Vec::new();
                 {
                     let room_guard = room.read().await;
                     let mut doc = room_guard.document.write().await;
 
-                    // Delete from the same position repeatedly (as chars shift left)
-                    for _ in 0..length {
-                        if let Some(op) = doc.rga.delete_local(position) {
-                            doc.buffered_ops.push(op.clone());
-                            ops.push(op);
+                    let Some(range) = doc.char_range(*encoding, position, length) else {
+                        tx.send(ServerMessage::Error {
+                            message: "Delete range out of bounds".to_string(),
+                        })?;
+                        return Ok(());
+                    };
+
+                    if let Some(op) = doc.edit_local(range.start, |rga| {
+                        rga.delete_range_local(range.start, range.len())
+                    }) {
+                        ops.push(op);
+                    }
+
+                    if let Some((content, ops_applied)) = room_guard.checkpoint_if_needed(&mut doc)
+                    {
+                        // Drop locks before broadcasting
+                        drop(doc);
+                        drop(room_guard);
+                        state
+                            .finish_checkpoint(room_id, content, ops_applied)
+                            .await?;
+                    }
+                }
+
+                // Broadcast operations
+                for op in ops {
+                    room.read()
+                        .await
+                        .broadcast_operation(client_id, site_id, op)
+                        .await;
+                }
+
+                // Auto-sync: broadcast updated document to all clients
+                room.read().await.broadcast_sync().await;
+            }
+        }
+
+        ClientMessage::Move {
+            position,
+            length,
+            destination,
+        } => {
+            if let Some(room_id) = current_room.as_ref() {
+                let room = state
+                    .get_room(room_id)
+                    .await?
+                    .ok_or_else(|| anyhow!("Room not found"))?;
+
+                let site_id = {
+                    let room_guard = room.read().await;
+                    room_guard
+                        .clients
+                        .get(&client_id)
+                        .map(|c| c.site_id)
+                        .ok_or_else(|| anyhow!("Client not found in room"))?
+                };
+
+                // Relocate the whole range with a single CRDT operation
+                let mut ops = Vec::new();
+                {
+                    let room_guard = room.read().await;
+                    let mut doc = room_guard.document.write().await;
+
+                    let moved = doc
+                        .char_range(*encoding, position, length)
+                        .zip(doc.char_range(*encoding, destination, 0))
+                        .and_then(|(range, destination)| {
+                            doc.edit_local(range.start.min(destination.start), |rga| {
+                                rga.move_range_local(range.start, range.len(), destination.start)
+                            })
+                        });
+                    match moved {
+                        Some(op) => ops.push(op),
+                        None => {
+                            tx.send(ServerMessage::Error {
+                                message: "Move range out of bounds".to_string(),
+                            })?;
+                            return Ok(());
                         }
                     }
 
-                    if doc.needs_checkpoint() {
-                        let ops_applied = doc.checkpoint();
-                        let content = doc.get_content();
+                    if let Some((content, ops_applied)) = room_guard.checkpoint_if_needed(&mut doc)
+                    {
+                        // Drop locks before broadcasting
                         drop(doc);
                         drop(room_guard);
+                        state
+                            .finish_checkpoint(room_id, content, ops_applied)
+                            .await?;
+                    }
+                }
+
+                // Broadcast operations
+                for op in ops {
+                    room.read()
+                        .await
+                        .broadcast_operation(client_id, site_id, op)
+                        .await;
+                }
+
+                // Auto-sync: broadcast updated document to all clients
+                room.read().await.broadcast_sync().await;
+            }
+        }
 
-                        room.read()
-                            .await
-                            .broadcast_checkpoint(content, ops_applied)
-                            .await;
+        ClientMessage::Replace {
+            position,
+            length,
+            text,
+        } => {
+            if let Some(room_id) = current_room.as_ref() {
+                let room = state
+                    .get_room(room_id)
+                    .await?
+                    .ok_or_else(|| anyhow!("Room not found"))?;
 
-                        state.persist_room(room_id).await?;
+                let site_id = {
+                    let room_guard = room.read().await;
+                    room_guard
+                        .clients
+                        .get(&client_id)
+                        .map(|c| c.site_id)
+                        .ok_or_else(|| anyhow!("Client not found in room"))?
+                };
+
+                // Delete and insert as one transaction, broadcast as one op
+                let mut ops = Vec::new();
+                {
+                    let room_guard = room.read().await;
+                    let mut doc = room_guard.document.write().await;
+
+                    let Some(range) = doc.char_range(*encoding, position, length) else {
+                        tx.send(ServerMessage::Error {
+                            message: "Replace range out of bounds".to_string(),
+                        })?;
+                        return Ok(());
+                    };
+
+                    let chars: Vec<char> = text.chars().collect();
+                    let op = doc.edit_local(range.start, |rga| {
+                        rga.transact(|rga| {
+                            rga.delete_range_local(range.start, range.len());
+                            rga.insert_run_local(range.start, chars);
+                        })
+                    });
+                    if let Some(op) = op {
+                        ops.push(op);
+                    }
+
+                    if let Some((content, ops_applied)) = room_guard.checkpoint_if_needed(&mut doc)
+                    {
+                        // Drop locks before broadcasting
+                        drop(doc);
+                        drop(room_guard);
+                        state
+                            .finish_checkpoint(room_id, content, ops_applied)
+                            .await?;
                     }
                 }
 
@@ -619,16 +861,60 @@ async fn handle_client_message(
                 let doc = room_guard.document.read().await;
                 let current_content = doc.get_content();
                 let buffered_ops = doc.get_buffered_ops().to_vec();
+                let marks = doc.mark_ops();
                 drop(doc);
                 drop(room_guard);
 
                 tx.send(ServerMessage::SyncResponse {
                     document_content: current_content,
                     buffered_ops,
+                    marks,
                 })?;
             }
         }
 
+        ClientMessage::SyncSince {
+            vector_clock,
+            session,
+        } => {
+            if let Some(room_id) = current_room.as_ref() {
+                let room = state
+                    .get_room(room_id)
+                    .await?
+                    .ok_or_else(|| anyhow!("Room not found"))?;
+
+                // Ops are only asked for to build or update a replica
+                room.write().await.mark_replica(client_id);
+                let room_guard = room.read().await;
+                let doc = room_guard.document.read().await;
+
+                // A replica of an older session starts over from the current
+                // session's log; clients that do not say are assumed current
+                let current = doc.rga.session();
+                let vector_clock = match session {
+                    Some(session) if session != current => Vec::new(),
+                    _ => vector_clock,
+                };
+
+                // Fall back to a full sync if the needed ops were compacted away
+                let message = match doc.ops_since(&vector_clock) {
+                    Some(operations) => ServerMessage::MissingOps {
+                        operations,
+                        session: Some(current),
+                    },
+                    None => ServerMessage::SyncResponse {
+                        document_content: doc.get_content(),
+                        buffered_ops: doc.get_buffered_ops().to_vec(),
+                        marks: doc.mark_ops(),
+                    },
+                };
+                drop(doc);
+                drop(room_guard);
+
+                tx.send(message)?;
+            }
+        }
+
         ClientMessage::SaveVersion { author } => {
             if let Some(room_id) = current_room.as_ref() {
                 let room = state
@@ -708,6 +994,83 @@ async fn handle_client_message(
             tx.send(ServerMessage::ActivityLog { events })?;
         }
 
+        ClientMessage::RequestBlame => {
+            if let Some(room_id) = current_room.as_ref() {
+                let room = state
+                    .get_room(room_id)
+                    .await?
+                    .ok_or_else(|| anyhow!("Room not found"))?;
+
+                let spans = room.read().await.blame(*encoding).await;
+                tx.send(ServerMessage::Blame { spans })?;
+            }
+        }
+
+        ClientMessage::RequestContentAt {
+            session,
+            vector_clock,
+        } => {
+            if let Some(room_id) = current_room.as_ref() {
+                let room = state
+                    .get_room(room_id)
+                    .await?
+                    .ok_or_else(|| anyhow!("Room not found"))?;
+
+                let content = room
+                    .read()
+                    .await
+                    .document
+                    .read()
+                    .await
+                    .content_at(session, &vector_clock);
+                let message = match content {
+                    Some(document_content) => ServerMessage::ContentAt {
+                        session,
+                        vector_clock,
+                        document_content,
+                    },
+                    None => ServerMessage::Error {
+                        message: "Content at that clock is no longer available".to_string(),
+                    },
+                };
+                tx.send(message)?;
+            }
+        }
+
+        ClientMessage::Acknowledge {
+            vector_clock,
+            session,
+        } => {
+            if let Some(room_id) = current_room.as_ref() {
+                let room = state
+                    .get_room(room_id)
+                    .await?
+                    .ok_or_else(|| anyhow!("Room not found"))?;
+
+                {
+                    room.write().await.mark_replica(client_id);
+                    let room_guard = room.read().await;
+                    let site_id = room_guard
+                        .clients
+                        .get(&client_id)
+                        .map(|c| c.site_id)
+                        .ok_or_else(|| anyhow!("Client not found in room"))?;
+                    let mut doc = room_guard.document.write().await;
+
+                    // Clocks of another session count different operations
+                    if session != doc.rga.session() {
+                        return Ok(());
+                    }
+                    doc.acknowledge(site_id, &vector_clock);
+                }
+
+                // Everyone is at the checkpoint: have them renumber too
+                if room.read().await.start_session_if_ready().await {
+                    state.persist_room(room_id).await?;
+                }
+            }
+        }
+
         ClientMessage::Ping => {
             tx.send(ServerMessage::Pong)?;
         }
@@ -113,6 +113,8 @@ export function useWebSocket(): UseWebSocketResult {
     setConnectionStatus('connecting');
     try {
       await wsService.connect(url);
+      // Editor positions are JavaScript string offsets
+      wsService.send({ type: 'SetPositionEncoding', encoding: 'Utf16' });
       setConnectionStatus('connected');
     } catch (error) {
       setConnectionStatus('disconnected');
@@ -32,10 +32,14 @@ export type ServerMessage =
   | { type: 'Error'; message: string }
   | { type: 'Pong' };
 
+// Unit of the positions and lengths a connection sends
+export type PositionEncoding = 'CodePoint' | 'Utf16' | 'Grapheme';
+
 export type ClientMessage =
   | { type: 'CreateRoom'; room_name: string; password: string; filename: string; initial_content: string }
   | { type: 'JoinRoom'; room_id: string; password: string }
   | { type: 'LeaveRoom' }
+  | { type: 'SetPositionEncoding'; encoding: PositionEncoding }
   | { type: 'RequestSync' }
   | { type: 'Insert'; position: number; text: string }
   | { type: 'Delete'; position: number; length: number }